@@ -0,0 +1,28 @@
+use std::process::Command;
+
+fn main() {
+    // Rebuild when migrations change, they're embedded with `sqlx::migrate!`
+    println!("cargo:rerun-if-changed=migrations");
+
+    println!("cargo:rerun-if-env-changed=AUSPEX_GIT_HASH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    // Builds outside a git checkout (e.g. in a container) can pass the hash themselves
+    let hash = std::env::var("AUSPEX_GIT_HASH").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+
+        String::from_utf8(output.stdout)
+            .ok()
+            .map(|hash| hash.trim().to_string())
+    });
+
+    println!(
+        "cargo:rustc-env=AUSPEX_GIT_HASH={}",
+        hash.unwrap_or_else(|| "unknown".into())
+    );
+}
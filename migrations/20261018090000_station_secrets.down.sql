@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE stations DROP COLUMN secret_hash;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE stations ADD COLUMN secret_hash TEXT;
\ No newline at end of file
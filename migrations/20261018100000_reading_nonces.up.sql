@@ -0,0 +1,7 @@
+-- Add up migration script here
+CREATE TABLE reading_nonces (
+    station_id INT NOT NULL,
+    nonce TEXT NOT NULL,
+    date TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (station_id, nonce)
+);
\ No newline at end of file
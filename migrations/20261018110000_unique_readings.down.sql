@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE readings DROP CONSTRAINT readings_station_id_date_key;
\ No newline at end of file
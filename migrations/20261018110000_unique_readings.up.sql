@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- Keep the oldest of the readings stored more than once for the same station and date, like `dedupe_readings`
+DELETE FROM readings a
+USING readings b
+WHERE b.station_id = a.station_id
+AND b.date = a.date
+AND b.id < a.id;
+
+ALTER TABLE readings ADD CONSTRAINT readings_station_id_date_key UNIQUE (station_id, date);
\ No newline at end of file
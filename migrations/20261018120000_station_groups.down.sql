@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE station_group_members;
+DROP TABLE station_groups;
\ No newline at end of file
@@ -0,0 +1,11 @@
+-- Add up migration script here
+CREATE TABLE station_groups (
+    id serial PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE station_group_members (
+    group_id INT NOT NULL,
+    station_id INT NOT NULL,
+    PRIMARY KEY (group_id, station_id)
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Add down migration script here
+DROP INDEX readings_date_id_idx;
\ No newline at end of file
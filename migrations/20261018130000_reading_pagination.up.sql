@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Keyset pagination of readings across stations, per station the unique (station_id, date) index is used
+CREATE INDEX readings_date_id_idx ON readings(date, id);
\ No newline at end of file
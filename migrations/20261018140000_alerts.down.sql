@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE alerts;
+DROP TABLE alert_states;
+DROP TABLE alert_rules;
\ No newline at end of file
@@ -0,0 +1,36 @@
+-- Add up migration script here
+CREATE TABLE alert_rules (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    metric TEXT NOT NULL,
+    comparison TEXT NOT NULL,
+    threshold FLOAT(8) NOT NULL,
+    clear_threshold FLOAT(8) NOT NULL,
+    duration_seconds INT NOT NULL,
+    station_id INT,
+    group_id INT,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE alert_states (
+    rule_id INT NOT NULL,
+    station_id INT NOT NULL,
+    breaching_since TIMESTAMPTZ,
+    firing BOOLEAN NOT NULL DEFAULT FALSE,
+    last_date TIMESTAMPTZ,
+    PRIMARY KEY (rule_id, station_id)
+);
+
+CREATE TABLE alerts (
+    id SERIAL PRIMARY KEY,
+    rule_id INT NOT NULL,
+    station_id INT NOT NULL,
+    since TIMESTAMPTZ NOT NULL,
+    fired_at TIMESTAMPTZ NOT NULL,
+    value FLOAT(8) NOT NULL,
+    resolved_at TIMESTAMPTZ,
+    resolved_value FLOAT(8)
+);
+
+CREATE INDEX alerts_fired_at_idx ON alerts (fired_at DESC, id DESC);
+CREATE UNIQUE INDEX alerts_firing_idx ON alerts (rule_id, station_id) WHERE resolved_at IS NULL;
\ No newline at end of file
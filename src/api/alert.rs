@@ -0,0 +1,109 @@
+use actix_web::{
+    delete, get, post, put,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::auth::Admin,
+    error::Result,
+    models::{alert::Comparison, metric::Metric},
+    repository::db::DBRepository,
+    services::alert_service::AlertService,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct AlertRuleRequest {
+    pub name: String,
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: f32,
+    /// Defaults to `threshold`, i.e. no hysteresis
+    pub clear_threshold: Option<f32>,
+    #[serde(default)]
+    pub duration_seconds: i32,
+    /// Token of the station to limit the rule to
+    pub station: Option<String>,
+    /// Name of the group to limit the rule to
+    pub group: Option<String>,
+    /// Defaults to true
+    pub enabled: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AlertsRequest {
+    pub station: Option<String>,
+    pub rule: Option<i32>,
+    /// Only the firing alerts when true, only the resolved ones when false
+    pub firing: Option<bool>,
+    /// The `next` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[get("/alert/rules")]
+pub async fn get_alert_rules(db: Data<DBRepository>) -> Result<HttpResponse> {
+    let service = AlertService::new(&db);
+    let rules = service.get_rules().await?;
+
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+#[get("/alert/rule/{id}")]
+pub async fn get_alert_rule(db: Data<DBRepository>, id: Path<i32>) -> Result<HttpResponse> {
+    let service = AlertService::new(&db);
+    let rule = service.get_rule(id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+#[post("/alert/rule")]
+pub async fn add_alert_rule(
+    db: Data<DBRepository>,
+    _admin: Admin,
+    body: Json<AlertRuleRequest>,
+) -> Result<HttpResponse> {
+    let service = AlertService::new(&db);
+    let rule = service.put_rule(body.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+#[put("/alert/rule/{id}")]
+pub async fn update_alert_rule(
+    db: Data<DBRepository>,
+    _admin: Admin,
+    id: Path<i32>,
+    body: Json<AlertRuleRequest>,
+) -> Result<HttpResponse> {
+    let service = AlertService::new(&db);
+    let rule = service
+        .update_rule(id.into_inner(), body.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+#[delete("/alert/rule/{id}")]
+pub async fn delete_alert_rule(
+    db: Data<DBRepository>,
+    _admin: Admin,
+    id: Path<i32>,
+) -> Result<HttpResponse> {
+    let service = AlertService::new(&db);
+    service.delete_rule(id.into_inner()).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/alert/history")]
+pub async fn get_alerts(
+    db: Data<DBRepository>,
+    query: Query<AlertsRequest>,
+) -> Result<HttpResponse> {
+    let service = AlertService::new(&db);
+    let alerts = service.get_alerts(query.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(alerts))
+}
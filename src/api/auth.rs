@@ -0,0 +1,219 @@
+use std::{
+    future::{ready, Future, Ready},
+    pin::Pin,
+};
+
+use actix_web::{
+    dev::Payload,
+    http::header::AUTHORIZATION,
+    web::{Bytes, Data},
+    FromRequest, HttpRequest,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    error::Error, models::station::Station, repository::db::DBRepository,
+    services::auth_service::AuthService,
+};
+
+/// A station that proved ownership of the `{station_token}` in the request path by
+/// sending its secret as `Authorization: Bearer <secret>`
+pub struct AuthenticatedStation(pub Station);
+
+impl FromRequest for AuthenticatedStation {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let db = req.app_data::<Data<DBRepository>>().cloned();
+        let token = req.match_info().get("station_token").map(String::from);
+        let secret = bearer_token(req);
+
+        Box::pin(async move {
+            let (db, token, secret) = match (db, token, secret) {
+                (Some(db), Some(token), Some(secret)) => (db, token, secret),
+                _ => return Err(Error::Unauthorized("missing station credentials".into())),
+            };
+
+            let service = AuthService::new(&db);
+            let station = service.authenticate(token, &secret).await?;
+
+            Ok(AuthenticatedStation(station))
+        })
+    }
+}
+
+/// A request from an operator, who sent the configured `auth.admin_token` as
+/// `Authorization: Bearer <token>`
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let result = match (req.app_data::<Data<DBRepository>>(), bearer_token(req)) {
+            (Some(db), Some(token)) => AuthService::new(db).authorize_admin(&token),
+            _ => Err(Error::Unauthorized("missing admin token".into())),
+        };
+
+        ready(result.map(|_| Admin))
+    }
+}
+
+/// A request body signed by a station, see `AuthService::verify_signature`.
+///
+/// The signature is sent as `X-Auspex-Signature` and is the hex encoded HMAC-SHA256 of
+/// `{timestamp}.{nonce}.{body}` keyed with the station's signing key, where `timestamp` (milliseconds since epoch) and `nonce`
+/// are sent as `X-Auspex-Timestamp` and `X-Auspex-Nonce`
+pub struct SignedPayload {
+    pub timestamp: i64,
+    pub nonce: String,
+    pub signature: String,
+    pub body: Bytes,
+}
+
+impl SignedPayload {
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+impl FromRequest for SignedPayload {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let timestamp = header(req, "X-Auspex-Timestamp").and_then(|t| t.parse().ok());
+        let nonce = header(req, "X-Auspex-Nonce").filter(|n| (8..=64).contains(&n.len()));
+        let signature = header(req, "X-Auspex-Signature");
+        let body = Bytes::from_request(req, payload);
+
+        Box::pin(async move {
+            let body = body.await.map_err(|e| Error::Validation(e.to_string()))?;
+
+            match (timestamp, nonce, signature) {
+                (Some(timestamp), Some(nonce), Some(signature)) => Ok(SignedPayload {
+                    timestamp,
+                    nonce,
+                    signature,
+                    body,
+                }),
+                _ => Err(Error::Unauthorized(
+                    "missing or malformed signature headers".into(),
+                )),
+            }
+        })
+    }
+}
+
+fn header(req: &HttpRequest, name: &str) -> Option<String> {
+    let value = req.headers().get(name)?.to_str().ok()?;
+
+    Some(value.trim().to_string())
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        api::station::{add_station, update_station, AddStationResponse},
+        services::station_service::StationService,
+        testing::TestDb,
+    };
+
+    async fn update(db: &TestDb, token: &str, secret: Option<&str>) -> StatusCode {
+        let app = test::init_service(App::new().app_data(db.data()).service(update_station)).await;
+        let mut req = test::TestRequest::post()
+            .uri(&format!("/station/{token}/update"))
+            .set_json(json!({ "hw_version": 7 }));
+        if let Some(secret) = secret {
+            req = req.insert_header((AUTHORIZATION, format!("Bearer {secret}")));
+        }
+
+        test::call_service(&app, req.to_request()).await.status()
+    }
+
+    #[actix_web::test]
+    async fn registration_issues_credentials_that_authenticate_the_station() {
+        let db = TestDb::new().await;
+        let app = test::init_service(App::new().app_data(db.data()).service(add_station)).await;
+        let req = test::TestRequest::put()
+            .uri("/station/st-1/register")
+            .set_json(json!({ "token": "st-1", "hw_version": 1, "sw_version": 1 }))
+            .to_request();
+        let response: AddStationResponse = test::call_and_read_body_json(&app, req).await;
+        assert!(!response.secret.is_empty());
+        assert_ne!(response.secret, response.signing_key);
+
+        assert_eq!(
+            update(&db, "st-1", Some(&response.secret)).await,
+            StatusCode::OK
+        );
+        let station = db.get_station("st-1".into(), false).await.unwrap();
+        assert_eq!(station.hw_version, 7);
+    }
+
+    #[actix_web::test]
+    async fn writes_without_the_right_secret_are_unauthorized() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let (_, other) = db.station("st-2").await;
+
+        assert_eq!(update(&db, "st-1", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            update(&db, "st-1", Some("wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            update(&db, "st-1", Some(&other.secret)).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // The signing key isn't a secret the station authenticates with
+        assert_eq!(
+            update(&db, "st-1", Some(&credentials.signing_key)).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // Unknown stations look the same as a wrong secret
+        assert_eq!(
+            update(&db, "unknown", Some(&credentials.secret)).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let station = db.get_station("st-1".into(), false).await.unwrap();
+        assert_eq!(station.hw_version, 1);
+    }
+
+    #[actix_web::test]
+    async fn rotated_secrets_stop_working() {
+        let db = TestDb::new().await;
+        let (_, old) = db.station("st-1").await;
+
+        let new = StationService::new(&db)
+            .rotate_secret("st-1")
+            .await
+            .unwrap();
+        assert_ne!(old.secret, new.secret);
+        assert_ne!(old.signing_key, new.signing_key);
+
+        assert_eq!(
+            update(&db, "st-1", Some(&old.secret)).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(update(&db, "st-1", Some(&new.secret)).await, StatusCode::OK);
+    }
+}
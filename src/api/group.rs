@@ -0,0 +1,41 @@
+use actix_web::{
+    get, put,
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::auth::Admin, error::Result, repository::db::DBRepository,
+    services::group_service::GroupService,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct PutGroupRequest {
+    /// Tokens of the member stations
+    pub stations: Vec<String>,
+}
+
+#[get("/group/{name}")]
+pub async fn get_group(db: Data<DBRepository>, name: Path<String>) -> Result<HttpResponse> {
+    let service = GroupService::new(&db);
+    let group = service.get_group(name.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(group))
+}
+
+#[put("/group/{name}")]
+pub async fn put_group(
+    db: Data<DBRepository>,
+    _admin: Admin,
+    name: Path<String>,
+    body: Json<PutGroupRequest>,
+) -> Result<HttpResponse> {
+    let service = GroupService::new(&db);
+    let request = body.into_inner();
+    let group = service
+        .put_group(name.into_inner(), request.stations)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(group))
+}
@@ -0,0 +1,123 @@
+use actix_web::{get, web::Data, HttpResponse};
+
+use crate::{repository::db::DBRepository, services::health_service::HealthService};
+
+#[get("/healthz")]
+pub async fn get_health() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+#[get("/readyz")]
+pub async fn get_readiness(db: Data<DBRepository>) -> HttpResponse {
+    let service = HealthService::new(&db);
+    let readiness = service.get_readiness().await;
+
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+#[get("/version")]
+pub async fn get_version(db: Data<DBRepository>) -> HttpResponse {
+    let service = HealthService::new(&db);
+    let version = service.get_version().await;
+
+    HttpResponse::Ok().json(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+
+    use super::*;
+    use crate::{
+        models::health::{Readiness, Version},
+        testing::{settings, TestDb},
+    };
+
+    #[actix_web::test]
+    async fn a_migrated_database_is_ready() {
+        let db = TestDb::new().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(get_health)
+                .service(get_readiness)
+                .service(get_version),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "ok");
+
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let readiness: Readiness = test::read_body_json(res).await;
+        assert!(readiness.ready && readiness.database && readiness.migrations);
+
+        let req = test::TestRequest::get().uri("/version").to_request();
+        let version: Version = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+        assert!(!version.git_hash.is_empty());
+        let latest = sqlx::query_scalar::<_, i64>("SELECT max(version) FROM _sqlx_migrations")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(version.schema_version, Some(latest));
+    }
+
+    #[actix_web::test]
+    async fn pending_migrations_are_not_ready() {
+        let db = TestDb::unmigrated(settings()).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(get_health)
+                .service(get_readiness),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let readiness: Readiness = test::read_body_json(res).await;
+        assert!(readiness.database);
+        assert!(!readiness.ready && !readiness.migrations);
+
+        // Alive all the same
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn an_unreachable_database_is_not_ready() {
+        let db = TestDb::new().await;
+        db.pool.close().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(get_health)
+                .service(get_readiness)
+                .service(get_version),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let readiness: Readiness = test::read_body_json(res).await;
+        assert!(!readiness.ready && !readiness.database && !readiness.migrations);
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/version").to_request();
+        let version: Version = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(version.schema_version, None);
+    }
+}
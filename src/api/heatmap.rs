@@ -0,0 +1,75 @@
+use actix_web::{
+    get,
+    web::{Data, Query},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    models::{aggregate::Window, metric::Metric},
+    repository::db::DBRepository,
+    services::heatmap_service::HeatmapService,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct HeatmapRequest {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+    pub metric: Metric,
+    /// Average the readings over the window (e.g. `24h`) instead of using the latest one
+    pub window: Option<Window>,
+    pub rows: Option<usize>,
+    pub cols: Option<usize>,
+    /// Cells further than this from every station are left empty
+    pub max_distance_km: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ContoursRequest {
+    /// Comma separated values to draw contour lines at
+    pub levels: Option<String>,
+}
+
+#[get("/reading/heatmap")]
+pub async fn get_heatmap(
+    db: Data<DBRepository>,
+    query: Query<HeatmapRequest>,
+) -> Result<HttpResponse> {
+    let service = HeatmapService::new(&db);
+    let grid = service.get_heatmap(&query).await?;
+
+    Ok(HttpResponse::Ok().json(grid))
+}
+
+#[get("/reading/heatmap/contours")]
+pub async fn get_heatmap_contours(
+    db: Data<DBRepository>,
+    query: Query<HeatmapRequest>,
+    contours: Query<ContoursRequest>,
+) -> Result<HttpResponse> {
+    let service = HeatmapService::new(&db);
+    let levels = match &contours.levels {
+        Some(levels) => Some(parse_levels(levels)?),
+        None => None,
+    };
+    let collection = service.get_contours(&query, levels).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/geo+json")
+        .json(collection))
+}
+
+fn parse_levels(levels: &str) -> Result<Vec<f32>> {
+    levels
+        .split(',')
+        .map(|level| {
+            level
+                .trim()
+                .parse()
+                .map_err(|_| Error::Validation(format!("invalid level {level:?}")))
+        })
+        .collect()
+}
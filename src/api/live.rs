@@ -0,0 +1,345 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use actix_web::{
+    get,
+    rt::time::{interval_at, Instant},
+    web::{Data, Payload},
+    HttpRequest, HttpResponse,
+};
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, CloseCode, Session};
+use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    events::{StationStatusEvent, Subscription},
+    models::{alert::Alert, metric::Metric},
+    repository::db::DBRepository,
+    services::live_service::{LiveService, LiveSubscription},
+};
+
+/// Missed pings after which a silent client is dropped
+const MISSED_PINGS: u32 = 2;
+
+/// A message from the client, e.g. `{"type": "subscribe", "stations": ["..."]}`
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe(Topics),
+    Unsubscribe(Topics),
+}
+
+/// Stations, groups and metrics of a live subscription. Without stations and groups every
+/// station is included, without metrics every metric
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Topics {
+    pub stations: Vec<String>,
+    pub groups: Vec<String>,
+    pub metrics: Vec<Metric>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The whole subscription after a change
+    Subscribed(Topics),
+    Reading(LiveReading),
+    StationStatus(Arc<StationStatusEvent>),
+    Alert(Arc<Alert>),
+    Error {
+        message: String,
+    },
+}
+
+/// A new reading, with the values of the subscribed metrics only
+#[derive(Serialize)]
+pub struct LiveReading {
+    pub station: String,
+    pub id: i32,
+    #[serde(with = "ts_milliseconds")]
+    pub date: DateTime<Utc>,
+    pub values: BTreeMap<&'static str, f32>,
+}
+
+impl From<Error> for ServerMessage {
+    fn from(e: Error) -> Self {
+        let message = match e {
+            Error::Storage(_) => "the request could not be completed due to a storage error".into(),
+            e => e.to_string(),
+        };
+        ServerMessage::Error { message }
+    }
+}
+
+/// WebSocket carrying the readings, station status changes and alerts a client subscribes
+/// to
+#[get("/live")]
+pub async fn live(db: Data<DBRepository>, req: HttpRequest, body: Payload) -> Result<HttpResponse> {
+    let (response, session, messages) =
+        actix_ws::handle(&req, body).map_err(|e| Error::Validation(e.to_string()))?;
+    let events = db.subscribe_events();
+
+    actix_web::rt::spawn(serve(
+        db,
+        session,
+        messages.aggregate_continuations(),
+        events,
+    ));
+
+    Ok(response)
+}
+
+/// Answer the client and push its events until either side closes the socket
+async fn serve(
+    db: Data<DBRepository>,
+    mut session: Session,
+    mut messages: AggregatedMessageStream,
+    mut events: Subscription,
+) {
+    let service = LiveService::new(&db);
+    let mut subscription = LiveSubscription::default();
+    let period = Duration::from_secs(db.settings().events.keep_alive_seconds);
+    let mut ping = interval_at(Instant::now() + period, period);
+    let mut last_heard = Instant::now();
+
+    let reason = loop {
+        let reply = tokio::select! {
+            message = messages.recv() => {
+                last_heard = Instant::now();
+                match message {
+                    Some(Ok(AggregatedMessage::Text(text))) => {
+                        Some(service.handle(&mut subscription, &text).await)
+                    }
+                    Some(Ok(AggregatedMessage::Binary(_))) => Some(ServerMessage::Error {
+                        message: "messages must be text".into(),
+                    }),
+                    Some(Ok(AggregatedMessage::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                        None
+                    }
+                    Some(Ok(AggregatedMessage::Pong(_))) => None,
+                    Some(Ok(AggregatedMessage::Close(reason))) => break reason,
+                    Some(Err(_)) => break Some(CloseCode::Protocol.into()),
+                    None => break None,
+                }
+            }
+            event = events.next() => match event {
+                Some(event) => subscription.message(&event),
+                None => break Some(CloseCode::Away.into()),
+            },
+            _ = ping.tick() => {
+                if last_heard.elapsed() > period * MISSED_PINGS {
+                    break Some(CloseCode::Away.into());
+                }
+                if session.ping(b"").await.is_err() {
+                    return;
+                }
+                None
+            }
+        };
+
+        let Some(reply) = reply else {
+            continue;
+        };
+        let sent = match serde_json::to_string(&reply) {
+            Ok(text) => session.text(text).await.is_ok(),
+            Err(_) => true,
+        };
+        if !sent {
+            return;
+        }
+    };
+
+    let _ = session.close(reason).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{rt::time::timeout, web::Bytes, App, HttpServer};
+    use awc::{
+        error::WsProtocolError,
+        ws::{Frame, Message},
+    };
+    use chrono::Duration as ChronoDuration;
+    use futures_util::{Sink, SinkExt, Stream, StreamExt};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        services::{group_service::GroupService, reading_service::ReadingService},
+        testing::{reading_body, sign, TestDb},
+    };
+
+    /// The client end of the WebSocket
+    trait Socket:
+        Sink<Message, Error = WsProtocolError>
+        + Stream<Item = std::result::Result<Frame, WsProtocolError>>
+        + Unpin
+    {
+    }
+
+    impl<T> Socket for T where
+        T: Sink<Message, Error = WsProtocolError>
+            + Stream<Item = std::result::Result<Frame, WsProtocolError>>
+            + Unpin
+    {
+    }
+
+    /// Serve the live endpoint on a loopback port and connect to it
+    async fn connect(db: &TestDb) -> impl Socket {
+        let data = db.data();
+        let server = HttpServer::new(move || App::new().app_data(data.clone()).service(live))
+            .workers(1)
+            .disable_signals()
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let port = server.addrs()[0].port();
+        actix_web::rt::spawn(server.run());
+
+        let (_, socket) = awc::Client::new()
+            .ws(format!("http://127.0.0.1:{port}/live"))
+            .connect()
+            .await
+            .unwrap();
+        socket
+    }
+
+    async fn send(socket: &mut impl Socket, message: Value) {
+        socket
+            .send(Message::Text(message.to_string().into()))
+            .await
+            .unwrap();
+    }
+
+    /// The next message of the server, skipping pings
+    async fn recv(socket: &mut impl Socket) -> Value {
+        loop {
+            let frame = timeout(std::time::Duration::from_secs(5), socket.next())
+                .await
+                .expect("no message within 5 seconds")
+                .expect("the socket closed")
+                .unwrap();
+            match frame {
+                Frame::Text(text) => return serde_json::from_slice(&text).unwrap(),
+                Frame::Ping(_) => continue,
+                frame => panic!("unexpected frame {frame:?}"),
+            }
+        }
+    }
+
+    async fn put_reading(db: &TestDb, token: &str, key: &str, minutes_ago: i64, pm25: f32) {
+        let body = reading_body(
+            token,
+            Utc::now() - ChronoDuration::minutes(minutes_ago),
+            pm25,
+        );
+        ReadingService::new(db)
+            .put_reading(token.into(), sign(key, &body))
+            .await
+            .unwrap();
+    }
+
+    #[actix_web::test]
+    async fn clients_get_the_events_they_subscribe_to() {
+        let db = TestDb::new().await;
+        let (_, first) = db.station("st-1").await;
+        let (_, second) = db.station("st-2").await;
+        GroupService::new(&db)
+            .put_group("g".into(), vec!["st-2".into()])
+            .await
+            .unwrap();
+        let mut socket = connect(&db).await;
+
+        send(
+            &mut socket,
+            json!({"type": "subscribe", "stations": ["st-1"], "metrics": ["pm25"]}),
+        )
+        .await;
+        assert_eq!(
+            recv(&mut socket).await,
+            json!({"type": "subscribed", "stations": ["st-1"], "groups": [], "metrics": ["pm25"]})
+        );
+
+        // Nothing of the other station, the one subscribed to coming online and its reading
+        // with the subscribed metric only
+        put_reading(&db, "st-2", &second.signing_key, 5, 8.0).await;
+        put_reading(&db, "st-1", &first.signing_key, 5, 7.0).await;
+        let status = recv(&mut socket).await;
+        assert_eq!(status["type"], "station_status");
+        assert_eq!(status["station"], "st-1");
+        let reading = recv(&mut socket).await;
+        assert_eq!(reading["type"], "reading");
+        assert_eq!(reading["station"], "st-1");
+        assert_eq!(reading["values"], json!({"pm25": 7.0}));
+
+        send(&mut socket, json!({"type": "subscribe", "groups": ["g"]})).await;
+        assert_eq!(recv(&mut socket).await["groups"], json!(["g"]));
+        send(
+            &mut socket,
+            json!({"type": "unsubscribe", "stations": ["st-1"]}),
+        )
+        .await;
+        assert_eq!(
+            recv(&mut socket).await,
+            json!({"type": "subscribed", "stations": [], "groups": ["g"], "metrics": ["pm25"]})
+        );
+
+        // Only the members of the group now
+        put_reading(&db, "st-1", &first.signing_key, 4, 7.0).await;
+        put_reading(&db, "st-2", &second.signing_key, 4, 9.0).await;
+        let reading = recv(&mut socket).await;
+        assert_eq!(reading["station"], "st-2");
+        assert_eq!(reading["values"], json!({"pm25": 9.0}));
+
+        socket.send(Message::Close(None)).await.unwrap();
+        let frame = timeout(std::time::Duration::from_secs(5), socket.next())
+            .await
+            .unwrap();
+        assert!(matches!(frame, Some(Ok(Frame::Close(_)))));
+    }
+
+    #[actix_web::test]
+    async fn refused_messages_leave_the_subscription_as_it_was() {
+        let db = TestDb::new().await;
+        db.station("st-1").await;
+        let mut socket = connect(&db).await;
+
+        send(
+            &mut socket,
+            json!({"type": "subscribe", "stations": ["st-1"]}),
+        )
+        .await;
+        recv(&mut socket).await;
+
+        for message in [
+            json!({"type": "subscribe", "stations": ["st-1", "unknown"]}),
+            json!({"type": "subscribe", "groups": ["unknown"]}),
+            json!({"type": "subscribe", "metrics": ["ozone"]}),
+            json!({"type": "subscribe", "station": "st-1"}),
+            json!({"type": "publish"}),
+        ] {
+            send(&mut socket, message.clone()).await;
+            let reply = recv(&mut socket).await;
+            assert_eq!(reply["type"], "error", "{message}");
+            assert!(reply["message"].is_string());
+        }
+
+        socket
+            .send(Message::Binary(Bytes::from_static(b"{}")))
+            .await
+            .unwrap();
+        assert_eq!(
+            recv(&mut socket).await,
+            json!({"type": "error", "message": "messages must be text"})
+        );
+
+        send(&mut socket, json!({"type": "subscribe"})).await;
+        assert_eq!(
+            recv(&mut socket).await,
+            json!({"type": "subscribed", "stations": ["st-1"], "groups": [], "metrics": []})
+        );
+    }
+}
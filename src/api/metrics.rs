@@ -0,0 +1,72 @@
+use actix_web::{get, web::Data, HttpResponse};
+
+use crate::{
+    error::Result, repository::db::DBRepository, services::metrics_service::MetricsService,
+};
+
+#[get("/metrics")]
+pub async fn get_metrics(db: Data<DBRepository>) -> Result<HttpResponse> {
+    let service = MetricsService::new(&db);
+    let metrics = service.gather().await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header::CONTENT_TYPE, test, App};
+    use chrono::{Duration, Utc};
+
+    use super::*;
+    use crate::{
+        api::reading::add_reading,
+        testing::{reading_body, sign, signed_request, TestDb},
+    };
+
+    // The metrics are global, so the station has a token no other test uses
+    #[actix_web::test]
+    async fn metrics_show_the_readings_and_the_fleet() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("metrics-1").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(add_reading)
+                .service(get_metrics),
+        )
+        .await;
+
+        let body = reading_body("metrics-1", Utc::now() - Duration::minutes(1), 7.5);
+        for _ in 0..2 {
+            let payload = sign(&credentials.signing_key, &body);
+            let req = signed_request(
+                test::TestRequest::put().uri("/reading/metrics-1/new"),
+                &payload,
+            );
+            test::call_service(&app, req.to_request()).await;
+        }
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get(CONTENT_TYPE).unwrap(),
+            prometheus::TEXT_FORMAT
+        );
+        let body = test::read_body(res).await;
+        let metrics = std::str::from_utf8(&body).unwrap();
+        let lines: Vec<&str> = metrics.lines().collect();
+
+        // The re-sent reading isn't counted again
+        for expected in [
+            r#"auspex_readings_ingested_total{station="metrics-1"} 1"#,
+            r#"auspex_station_latest_pm25{station="metrics-1"} 7.5"#,
+            r#"auspex_station_latest_co2{station="metrics-1"} 450"#,
+            "auspex_active_stations 1",
+            "auspex_db_pool_max_connections 5",
+        ] {
+            assert!(lines.contains(&expected), "{expected} in {metrics}");
+        }
+    }
+}
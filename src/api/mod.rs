@@ -1,2 +1,12 @@
+pub mod auth;
+pub mod health;
 pub mod station;
-pub mod reading;
\ No newline at end of file
+pub mod reading;
+pub mod status;
+pub mod metrics;
+pub mod group;
+pub mod heatmap;
+pub mod stream;
+pub mod live;
+pub mod alert;
+pub mod webhook;
\ No newline at end of file
@@ -1,18 +1,38 @@
 use actix_web::{
-    get, put,
-    web::{Data, Json, Path},
-    HttpResponse,
+    get,
+    http::header::{self, ContentDisposition, DispositionParam, DispositionType},
+    put,
+    web::{Bytes, Data, Path, Query},
+    HttpRequest, HttpResponse,
 };
-use chrono::{serde::ts_milliseconds, serde::ts_milliseconds_option, DateTime, Utc};
+use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use futures_util::{future, stream, StreamExt, TryStreamExt};
+use log::error;
 use serde::{Deserialize, Serialize};
 
-use crate::{repository::db::DBRepository, services::reading_service::ReadingService};
+use crate::{
+    api::auth::SignedPayload,
+    error::Result,
+    models::{
+        aggregate::{Bucket, Window},
+        aqi::Aqi,
+        csv::{CsvRow, TimestampFormat},
+        metric::Metric,
+        reading::Reading,
+    },
+    repository::{db::DBRepository, query::RowStream},
+    services::reading_service::ReadingService,
+};
+
+/// Media types of the `Accept` header that select newline-delimited JSON, streamed row by
+/// row instead of a single array
+const NDJSON_TYPES: [&str; 2] = ["application/x-ndjson", "application/ndjson"];
 
 #[derive(Serialize, Deserialize)]
 pub struct AddReadingRequest {
     pub station_token: String,
-    #[serde(with = "ts_milliseconds_option")]
-    pub date: Option<DateTime<Utc>>,
+    #[serde(with = "ts_milliseconds")]
+    pub date: DateTime<Utc>,
     pub temperature: f32,
     pub humidity: f32,
     pub pm10: f32,
@@ -21,6 +41,25 @@ pub struct AddReadingRequest {
     pub voc: f32,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct AddReadingBatchItem {
+    #[serde(with = "ts_milliseconds")]
+    pub date: DateTime<Utc>,
+    pub temperature: f32,
+    pub humidity: f32,
+    pub pm10: f32,
+    pub pm25: f32,
+    pub co2: f32,
+    pub voc: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchReadingResult {
+    pub index: usize,
+    pub id: Option<i32>,
+    pub error: Option<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ReadingsBetweenRequest {
     station_token: String,
@@ -30,105 +69,1165 @@ pub struct ReadingsBetweenRequest {
     end: DateTime<Utc>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct AggregateReadingsRequest {
+    #[serde(with = "ts_milliseconds")]
+    start: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    end: DateTime<Utc>,
+    bucket: Bucket,
+}
+
+/// Keyset pagination of a listing, `cursor` is the `next` of the previous page
+#[derive(Serialize, Deserialize)]
+pub struct PageRequest {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportReadingsRequest {
+    #[serde(with = "ts_milliseconds")]
+    start: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    end: DateTime<Utc>,
+    #[serde(default)]
+    timestamps: TimestampFormat,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportAggregateRequest {
+    #[serde(with = "ts_milliseconds")]
+    start: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    end: DateTime<Utc>,
+    bucket: Bucket,
+    #[serde(default)]
+    timestamps: TimestampFormat,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CompareReadingsRequest {
+    /// Comma separated station tokens
+    stations: Option<String>,
+    group: Option<String>,
+    metric: Metric,
+    #[serde(with = "ts_milliseconds")]
+    start: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    end: DateTime<Utc>,
+    bucket: Bucket,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AverageReadingRequest {
+    /// Extra windows as a comma separated list, e.g. `15m,8h,7d`
+    windows: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LatestReadingResponse {
+    #[serde(flatten)]
+    pub reading: Reading,
+    pub aqi: Aqi,
+}
+
 #[get("/reading/{station_token}/latest")]
 pub async fn get_latest_reading(
     db: Data<DBRepository>,
     station_token: Path<String>,
-) -> HttpResponse {
+) -> Result<HttpResponse> {
     let service = ReadingService::new(&db);
-    let token = station_token.into_inner();
-    let result = service.get_latest_reading(token).await;
+    let (reading, aqi) = service
+        .get_latest_reading_aqi(station_token.into_inner())
+        .await?;
 
-    if let Ok(reading) = result {
-        HttpResponse::Ok().json(reading)
-    } else {
-        HttpResponse::NoContent().finish()
-    }
+    Ok(HttpResponse::Ok().json(LatestReadingResponse { reading, aqi }))
 }
 
 #[get("/reading/{station_token}/latest/{count}")]
 pub async fn get_latest_readings(
     db: Data<DBRepository>,
     params: Path<(String, i64)>,
-) -> HttpResponse {
+    query: Query<PageRequest>,
+) -> Result<HttpResponse> {
     let service = ReadingService::new(&db);
     let (token, count) = params.into_inner();
-    let result = service.get_latest_readings(token, count).await;
+    let page = PageRequest {
+        cursor: query.into_inner().cursor,
+        limit: Some(count),
+    };
+    let readings = service.get_latest_readings(token, &page).await?;
 
-    if let Ok(readings) = result {
-        HttpResponse::Ok().json(readings)
-    } else {
-        HttpResponse::NoContent().finish()
-    }
+    Ok(HttpResponse::Ok().json(readings))
 }
 
 #[get("/reading/{station_token}/average")]
 pub async fn get_average_reading(
     db: Data<DBRepository>,
     station_token: Path<String>,
-) -> HttpResponse {
+    query: Query<AverageReadingRequest>,
+) -> Result<HttpResponse> {
     let service = ReadingService::new(&db);
     let token = station_token.into_inner();
-    let result = service.get_average_reading(token).await;
+    let windows = match &query.windows {
+        Some(windows) => Window::parse_list(windows)?,
+        None => vec![],
+    };
+    let reading = service.get_average_reading(token, &windows).await?;
 
-    if let Ok(reading) = result {
-        HttpResponse::Ok().json(reading)
-    } else {
-        HttpResponse::InternalServerError().finish()
-    }
+    Ok(HttpResponse::Ok().json(reading))
+}
+
+#[get("/reading/{station_token}/aqi")]
+pub async fn get_aqi(db: Data<DBRepository>, station_token: Path<String>) -> Result<HttpResponse> {
+    let service = ReadingService::new(&db);
+    let token = station_token.into_inner();
+    let aqi = service.get_aqi(token).await?;
+
+    Ok(HttpResponse::Ok().json(aqi))
 }
 
 #[get("/reading/{station_token}/between/{start}/{end}")]
 pub async fn get_readings_between(
+    req: HttpRequest,
     db: Data<DBRepository>,
     path: Path<ReadingsBetweenRequest>,
-) -> HttpResponse {
+    page: Query<PageRequest>,
+) -> Result<HttpResponse> {
     let service = ReadingService::new(&db);
     let request = path.into_inner();
-    let result = service
-        .get_readings_between(request.station_token, request.start, request.end)
-        .await;
-
-    if let Ok(readings) = result {
-        HttpResponse::Ok().json(readings)
-    } else {
-        HttpResponse::NoContent().finish()
+    if wants_ndjson(&req) {
+        let rows = service
+            .stream_readings_between(request.station_token, request.start, request.end)
+            .await?;
+        return Ok(ndjson_response(rows));
     }
+
+    let readings = service
+        .get_readings_between(request.station_token, request.start, request.end, &page)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(readings))
 }
 
-#[get("/reading/all/past_hour")]
-pub async fn get_past_hour_readings(db: Data<DBRepository>) -> HttpResponse {
+#[get("/reading/{station_token}/aggregate")]
+pub async fn get_aggregated_readings(
+    db: Data<DBRepository>,
+    station_token: Path<String>,
+    query: Query<AggregateReadingsRequest>,
+) -> Result<HttpResponse> {
+    let service = ReadingService::new(&db);
+    let token = station_token.into_inner();
+    let request = query.into_inner();
+    let buckets = service
+        .get_reading_buckets(token, request.start, request.end, request.bucket)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(buckets))
+}
+
+#[get("/reading/{station_token}/between.csv")]
+pub async fn export_readings_between(
+    db: Data<DBRepository>,
+    station_token: Path<String>,
+    query: Query<ExportReadingsRequest>,
+) -> Result<HttpResponse> {
+    let service = ReadingService::new(&db);
+    let token = station_token.into_inner();
+    let request = query.into_inner();
+    let rows = service
+        .stream_readings_between(token.clone(), request.start, request.end)
+        .await?;
+
+    Ok(csv_response(
+        format!("{token}-readings.csv"),
+        rows,
+        request.timestamps,
+    ))
+}
+
+#[get("/reading/{station_token}/aggregate.csv")]
+pub async fn export_aggregated_readings(
+    db: Data<DBRepository>,
+    station_token: Path<String>,
+    query: Query<ExportAggregateRequest>,
+) -> Result<HttpResponse> {
     let service = ReadingService::new(&db);
-    let result = service.get_past_hour_readings().await;
+    let token = station_token.into_inner();
+    let request = query.into_inner();
+    let rows = service
+        .stream_reading_buckets(token.clone(), request.start, request.end, request.bucket)
+        .await?;
+
+    Ok(csv_response(
+        format!("{token}-aggregate.csv"),
+        rows,
+        request.timestamps,
+    ))
+}
+
+/// Stream the rows as a CSV download, the header first. A storage error halfway aborts the
+/// response, as the status has already been sent
+fn csv_response<T: CsvRow + Send + 'static>(
+    filename: String,
+    rows: RowStream<T>,
+    timestamps: TimestampFormat,
+) -> HttpResponse {
+    let body = stream::once(future::ready(Ok(T::csv_header())))
+        .chain(rows.map_ok(move |row| row.csv_row(timestamps)))
+        .map_ok(Bytes::from)
+        .inspect_err(|e| error!("CSV export aborted: {e}"));
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(body)
+}
+
+fn wants_ndjson(req: &HttpRequest) -> bool {
+    let Some(accept) = req.headers().get(header::ACCEPT) else {
+        return false;
+    };
 
-    if let Ok(readings) = result {
-        HttpResponse::Ok().json(readings)
-    } else {
-        HttpResponse::NoContent().finish()
+    accept
+        .to_str()
+        .unwrap_or_default()
+        .split(',')
+        .any(|media_type| {
+            let essence = media_type.split(';').next().unwrap_or_default().trim();
+            NDJSON_TYPES.contains(&essence)
+        })
+}
+
+/// Stream the rows as newline-delimited JSON, one object per line. A storage error halfway
+/// aborts the response, as the status has already been sent
+fn ndjson_response<T: Serialize + Send + 'static>(rows: RowStream<T>) -> HttpResponse {
+    let body = rows
+        .and_then(|row| {
+            future::ready(
+                serde_json::to_vec(&row)
+                    .map_err(Into::into)
+                    .map(|mut line| {
+                        line.push(b'\n');
+                        Bytes::from(line)
+                    }),
+            )
+        })
+        .inspect_err(|e| error!("NDJSON response aborted: {e}"));
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(body)
+}
+
+#[get("/reading/compare")]
+pub async fn compare_readings(
+    db: Data<DBRepository>,
+    query: Query<CompareReadingsRequest>,
+) -> Result<HttpResponse> {
+    let service = ReadingService::new(&db);
+    let request = query.into_inner();
+    let tokens = request
+        .stations
+        .iter()
+        .flat_map(|stations| stations.split(','))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(String::from)
+        .collect();
+    let series = service
+        .compare_readings(
+            tokens,
+            request.group,
+            request.metric,
+            request.start,
+            request.end,
+            request.bucket,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(series))
+}
+
+#[get("/reading/all/past_hour")]
+pub async fn get_past_hour_readings(
+    req: HttpRequest,
+    db: Data<DBRepository>,
+    page: Query<PageRequest>,
+) -> Result<HttpResponse> {
+    let service = ReadingService::new(&db);
+    if wants_ndjson(&req) {
+        return Ok(ndjson_response(service.stream_past_hour_readings()));
     }
+
+    let readings = service.get_past_hour_readings(&page).await?;
+
+    Ok(HttpResponse::Ok().json(readings))
 }
 
 #[get("/reading/all/past_minutes")]
-pub async fn get_past_minutes_readings(db: Data<DBRepository>) -> HttpResponse {
+pub async fn get_past_minutes_readings(
+    db: Data<DBRepository>,
+    page: Query<PageRequest>,
+) -> Result<HttpResponse> {
     let service = ReadingService::new(&db);
-    let result = service.get_past_minute_readings().await;
+    let readings = service.get_past_minute_readings(&page).await?;
 
-    if result.is_ok() {
-        HttpResponse::Ok().json(result.unwrap())
-    } else {
-        HttpResponse::InternalServerError().finish()
-    }
+    Ok(HttpResponse::Ok().json(readings))
 }
 
 #[put("/reading/{station_token}/new")]
-pub async fn add_reading(db: Data<DBRepository>, body: Json<AddReadingRequest>) -> HttpResponse {
+pub async fn add_reading(
+    db: Data<DBRepository>,
+    station_token: Path<String>,
+    payload: SignedPayload,
+) -> Result<HttpResponse> {
     let service = ReadingService::new(&db);
-    let request = body.into_inner();
-    let id = service.put_reading(request).await;
+    let token = station_token.into_inner();
+    let id = service.put_reading(token, payload).await?;
+
+    Ok(HttpResponse::Ok().json(id))
+}
+
+#[put("/reading/{station_token}/batch")]
+pub async fn add_readings(
+    db: Data<DBRepository>,
+    station_token: Path<String>,
+    payload: SignedPayload,
+) -> Result<HttpResponse> {
+    let service = ReadingService::new(&db);
+    let token = station_token.into_inner();
+    let results = service.put_readings(token, payload).await?;
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+    use chrono::{Duration, SecondsFormat, TimeZone};
+
+    use super::*;
+    use crate::{
+        api::auth::SignedPayload,
+        models::{
+            aggregate::{ComparisonSeries, ReadingBucket},
+            page::Page,
+        },
+        services::{
+            group_service::GroupService,
+            reading_service::{MAX_BATCH_SIZE, MAX_COMPARED_STATIONS},
+        },
+        testing::{reading_body, sign, sign_at, signed_request, TestDb},
+    };
+
+    fn put_signed(uri: &str, payload: &SignedPayload) -> test::TestRequest {
+        signed_request(test::TestRequest::put().uri(uri), payload)
+    }
+
+    /// The readings of the station, the latest first
+    async fn stored(db: &TestDb, token: &str) -> Vec<Reading> {
+        let station = db.get_station(token.into(), false).await.unwrap();
+
+        db.get_latest_readings(station, None, 100)
+            .await
+            .unwrap()
+            .items
+    }
+
+    /// Body of a batch of ordinary readings at `dates`
+    fn batch_body(dates: &[DateTime<Utc>]) -> String {
+        let readings: Vec<_> = dates.iter().map(|date| (*date, 5.0)).collect();
+
+        pm25_batch_body(&readings)
+    }
+
+    /// Body of a batch of readings with the given dates and pm25
+    fn pm25_batch_body(readings: &[(DateTime<Utc>, f32)]) -> String {
+        let items: Vec<serde_json::Value> = readings
+            .iter()
+            .map(|(date, pm25)| {
+                let mut item: serde_json::Value =
+                    serde_json::from_str(&reading_body("", *date, *pm25)).unwrap();
+                item.as_object_mut().unwrap().remove("station_token");
+                item
+            })
+            .collect();
+
+        serde_json::Value::from(items).to_string()
+    }
+
+    #[actix_web::test]
+    async fn signed_readings_are_stored_once_per_nonce() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let app = test::init_service(App::new().app_data(db.data()).service(add_reading)).await;
+        let date = Utc::now() - Duration::minutes(1);
+
+        let payload = sign(&credentials.signing_key, &reading_body("st-1", date, 5.0));
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let id: i32 = test::read_body_json(res).await;
+
+        // Another reading under a nonce that was used already is a replay
+        let body = reading_body("st-1", date + Duration::seconds(10), 5.0);
+        let replayed = sign_at(
+            &credentials.signing_key,
+            &body,
+            payload.timestamp,
+            &payload.nonce,
+        );
+        let res = test::call_service(
+            &app,
+            put_signed("/reading/st-1/new", &replayed).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let readings = stored(&db, "st-1").await;
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].id, id);
+    }
+
+    #[actix_web::test]
+    async fn unsigned_or_badly_signed_readings_are_unauthorized() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let (_, other) = db.station("st-2").await;
+        let app = test::init_service(App::new().app_data(db.data()).service(add_reading)).await;
+        let body = reading_body("st-1", Utc::now(), 5.0);
+
+        let req = test::TestRequest::put()
+            .uri("/reading/st-1/new")
+            .set_payload(body.clone())
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // Nonces must be 8 to 64 characters
+        let payload = sign_at(
+            &credentials.signing_key,
+            &body,
+            Utc::now().timestamp_millis(),
+            "short",
+        );
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let payload = sign(&other.signing_key, &body);
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let old = (Utc::now() - Duration::hours(1)).timestamp_millis();
+        let payload = sign_at(&credentials.signing_key, &body, old, "0123456789");
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        assert!(stored(&db, "st-1").await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn readings_naming_another_station_are_refused() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        db.station("st-2").await;
+        let app = test::init_service(App::new().app_data(db.data()).service(add_reading)).await;
+
+        let body = reading_body("st-2", Utc::now(), 5.0);
+        let payload = sign(&credentials.signing_key, &body);
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        for token in ["st-1", "st-2"] {
+            assert!(stored(&db, token).await.is_empty());
+        }
+    }
+
+    #[actix_web::test]
+    async fn nonces_are_forgotten_once_their_payloads_would_be_refused() {
+        let db = TestDb::new().await;
+        let (station, credentials) = db.station("st-1").await;
+        let app = test::init_service(App::new().app_data(db.data()).service(add_reading)).await;
+        let skew = db.settings().windows.max_clock_skew_seconds;
+        let put = |date: DateTime<Utc>, nonce: &str| {
+            let body = reading_body("st-1", date, 5.0);
+            let payload = sign_at(
+                &credentials.signing_key,
+                &body,
+                Utc::now().timestamp_millis(),
+                nonce,
+            );
+            put_signed("/reading/st-1/new", &payload).to_request()
+        };
+        let age_nonce = |nonce: &'static str, seconds: i64| {
+            sqlx::query("UPDATE reading_nonces SET date = NOW() - make_interval(secs => $1) WHERE station_id = $2 AND nonce = $3")
+                .bind(seconds as f64)
+                .bind(station.id)
+                .bind(nonce)
+                .execute(&db.pool)
+        };
+        let start = Utc::now() - Duration::minutes(10);
+
+        for (i, nonce) in ["nonce-old", "nonce-recent"].into_iter().enumerate() {
+            let res =
+                test::call_service(&app, put(start + Duration::seconds(i as i64), nonce)).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        // A payload signed with the old nonce would be outside the clock skew by now, the
+        // recent one could still be replayed
+        age_nonce("nonce-old", 2 * skew + 60).await.unwrap();
+        age_nonce("nonce-recent", 2 * skew - 60).await.unwrap();
+
+        // Storing another reading forgets the nonces that can't be replayed any more
+        let res = test::call_service(&app, put(start + Duration::seconds(2), "nonce-new")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = test::call_service(&app, put(start + Duration::seconds(3), "nonce-old")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = test::call_service(&app, put(start + Duration::seconds(4), "nonce-recent")).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(stored(&db, "st-1").await.len(), 4);
+    }
+
+    #[actix_web::test]
+    async fn re_sent_readings_get_their_original_id() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let app = test::init_service(App::new().app_data(db.data()).service(add_reading)).await;
+        let date = Utc::now() - Duration::minutes(1);
+        let payload = sign(&credentials.signing_key, &reading_body("st-1", date, 5.0));
+
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let id: i32 = test::read_body_json(res).await;
+
+        // The same payload again, as a station that got no answer would retry it
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body_json::<i32, _>(res).await, id);
+
+        // Or signed again, the first values are kept
+        let payload = sign(&credentials.signing_key, &reading_body("st-1", date, 50.0));
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body_json::<i32, _>(res).await, id);
+
+        let readings = stored(&db, "st-1").await;
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].pm25, 5.0);
+    }
+
+    #[actix_web::test]
+    async fn readings_without_a_date_are_refused() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let app = test::init_service(App::new().app_data(db.data()).service(add_reading)).await;
+
+        let mut body: serde_json::Value =
+            serde_json::from_str(&reading_body("st-1", Utc::now(), 5.0)).unwrap();
+        body.as_object_mut().unwrap().remove("date");
+        let payload = sign(&credentials.signing_key, &body.to_string());
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(stored(&db, "st-1").await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn future_dated_readings_are_refused() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(add_reading)
+                .service(add_readings),
+        )
+        .await;
+        let skew = Duration::seconds(db.settings().windows.max_clock_skew_seconds);
+        let future = Utc::now() + skew + Duration::minutes(1);
+
+        let payload = sign(&credentials.signing_key, &reading_body("st-1", future, 5.0));
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // Within the clock skew a station's clock may be ahead
+        let ahead = Utc::now() + skew - Duration::minutes(1);
+        let payload = sign(&credentials.signing_key, &reading_body("st-1", ahead, 5.0));
+        let res =
+            test::call_service(&app, put_signed("/reading/st-1/new", &payload).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut item: serde_json::Value =
+            serde_json::from_str(&reading_body("st-1", future, 5.0)).unwrap();
+        item.as_object_mut().unwrap().remove("station_token");
+        let payload = sign(&credentials.signing_key, &format!("[{item}]"));
+        let res = test::call_service(
+            &app,
+            put_signed("/reading/st-1/batch", &payload).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let results: Vec<BatchReadingResult> = test::read_body_json(res).await;
+        assert_eq!(results[0].id, None);
+        assert_eq!(results[0].error.as_deref(), Some("date is in the future"));
+
+        let readings = stored(&db, "st-1").await;
+        assert_eq!(readings.len(), 1);
+        assert_eq!(
+            readings[0].date.timestamp_millis(),
+            ahead.timestamp_millis()
+        );
+    }
+
+    #[actix_web::test]
+    async fn latest_readings_are_paged_with_the_cursor() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(add_readings)
+                .service(get_latest_readings),
+        )
+        .await;
+        let now = Utc::now();
+        let dates: Vec<_> = (1..=5).map(|i| now - Duration::minutes(i)).collect();
+
+        let payload = sign(&credentials.signing_key, &batch_body(&dates));
+        let res = test::call_service(
+            &app,
+            put_signed("/reading/st-1/batch", &payload).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut pages = vec![];
+        let mut uri = "/reading/st-1/latest/2".to_owned();
+        loop {
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let page: Page<Reading> = test::call_and_read_body_json(&app, req).await;
+            pages.push(page.items);
+            match page.next {
+                Some(next) => uri = format!("/reading/st-1/latest/2?cursor={next}"),
+                None => break,
+            }
+        }
+
+        // Every reading once, the latest first, in pages of the requested size
+        assert_eq!(
+            pages.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        let paged: Vec<_> = pages
+            .iter()
+            .flatten()
+            .map(|reading| reading.date.timestamp_millis())
+            .collect();
+        let expected: Vec<_> = dates.iter().map(|date| date.timestamp_millis()).collect();
+        assert_eq!(paged, expected);
+
+        for uri in [
+            "/reading/st-1/latest/0",
+            "/reading/st-1/latest/2?cursor=nothex",
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn batches_get_a_result_per_item() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let app = test::init_service(App::new().app_data(db.data()).service(add_readings)).await;
+        let now = Utc::now();
+        let dates: Vec<_> = (1..=4).map(|i| now - Duration::minutes(i)).collect();
+
+        let mut body: Vec<serde_json::Value> =
+            serde_json::from_str(&batch_body(&[dates[0], dates[1], dates[0], dates[2]])).unwrap();
+        body[1]["pm25"] = (-1.0).into();
+        let body = serde_json::Value::from(body).to_string();
+        let payload = sign(&credentials.signing_key, &body);
+        let res = test::call_service(
+            &app,
+            put_signed("/reading/st-1/batch", &payload).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let results: Vec<BatchReadingResult> = test::read_body_json(res).await;
+        assert_eq!(
+            results.iter().map(|r| r.index).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        let first = results[0].id.expect("the first reading is stored");
+        assert_eq!(results[0].error, None);
+        assert_eq!(results[1].id, None);
+        assert!(results[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .starts_with("pm25 must be between"));
+        // A date that occurs twice in a batch is stored once
+        assert_eq!(results[2].id, Some(first));
+        assert!(results[3].id.is_some());
+        assert_eq!(stored(&db, "st-1").await.len(), 2);
+
+        let station = db.get_station("st-1".into(), false).await.unwrap();
+        assert!(station.last_online >= now);
+
+        // Re-sent readings keep their id, next to new ones
+        let payload = sign(&credentials.signing_key, &batch_body(&[dates[0], dates[3]]));
+        let res = test::call_service(
+            &app,
+            put_signed("/reading/st-1/batch", &payload).to_request(),
+        )
+        .await;
+        let results: Vec<BatchReadingResult> = test::read_body_json(res).await;
+        assert_eq!(results[0].id, Some(first));
+        assert!(results[1].id.is_some());
+        assert_eq!(stored(&db, "st-1").await.len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn batches_are_limited_in_size() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let app = test::init_service(App::new().app_data(db.data()).service(add_readings)).await;
+        let now = Utc::now();
+        let dates: Vec<_> = (0..=MAX_BATCH_SIZE as i64)
+            .map(|i| now - Duration::seconds(i + 1))
+            .collect();
+
+        let payload = sign(&credentials.signing_key, &batch_body(&dates));
+        let res = test::call_service(
+            &app,
+            put_signed("/reading/st-1/batch", &payload).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let results: Vec<BatchReadingResult> = test::read_body_json(res).await;
+        assert_eq!(results.len(), MAX_BATCH_SIZE + 1);
+        assert!(results[..MAX_BATCH_SIZE].iter().all(|r| r.id.is_some()));
+        assert_eq!(results[MAX_BATCH_SIZE].id, None);
+        assert_eq!(
+            results[MAX_BATCH_SIZE].error.as_deref(),
+            Some("batch size limit exceeded")
+        );
+    }
+
+    #[actix_web::test]
+    async fn readings_are_aggregated_into_aligned_buckets() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(add_readings)
+                .service(get_aggregated_readings),
+        )
+        .await;
+        // Two whole hours ago, so every reading is in the past
+        let hour = Utc
+            .timestamp_opt(Utc::now().timestamp() / 3600 * 3600 - 2 * 3600, 0)
+            .unwrap();
+        let readings = [
+            (hour + Duration::minutes(5), 10.0),
+            (hour + Duration::minutes(20), 20.0),
+            (hour + Duration::minutes(50), 30.0),
+            (hour + Duration::minutes(70), 40.0),
+            // At the end of the range, which is left out
+            (hour + Duration::hours(2), 50.0),
+        ];
+
+        let payload = sign(&credentials.signing_key, &pm25_batch_body(&readings));
+        let res = test::call_service(
+            &app,
+            put_signed("/reading/st-1/batch", &payload).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let start = hour.timestamp_millis();
+        let end = (hour + Duration::hours(2)).timestamp_millis();
+        let uri = format!("/reading/st-1/aggregate?start={start}&end={end}&bucket=1h");
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let buckets: Vec<ReadingBucket> = test::call_and_read_body_json(&app, req).await;
+        let summary: Vec<_> = buckets
+            .iter()
+            .map(|b| (b.date, b.pm25.count, b.pm25.avg, b.pm25.min, b.pm25.max))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (hour, 3, 20.0, 10.0, 30.0),
+                (hour + Duration::hours(1), 1, 40.0, 40.0, 40.0),
+            ]
+        );
+        assert_eq!(buckets[0].co2.count, 3);
+
+        // Buckets without readings are left out
+        let uri = format!("/reading/st-1/aggregate?start={start}&end={end}&bucket=5m");
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let buckets: Vec<ReadingBucket> = test::call_and_read_body_json(&app, req).await;
+        let dates: Vec<_> = buckets.iter().map(|b| b.date).collect();
+        assert_eq!(
+            dates,
+            [5, 20, 50, 70].map(|minutes| hour + Duration::minutes(minutes))
+        );
+    }
+
+    #[actix_web::test]
+    async fn aggregations_are_validated() {
+        let db = TestDb::new().await;
+        db.station("st-1").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(get_aggregated_readings),
+        )
+        .await;
+        let end = Utc::now().timestamp_millis();
+        let month_ago = (Utc::now() - Duration::days(30)).timestamp_millis();
+
+        for (uri, status) in [
+            (
+                format!("/reading/st-1/aggregate?start={end}&end={end}&bucket=1h"),
+                StatusCode::BAD_REQUEST,
+            ),
+            // 43201 buckets
+            (
+                format!("/reading/st-1/aggregate?start={month_ago}&end={end}&bucket=1m"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("/reading/st-1/aggregate?start={month_ago}&end={end}&bucket=2h"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("/reading/st-2/aggregate?start={month_ago}&end={end}&bucket=1h"),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                format!("/reading/st-1/aggregate?start={month_ago}&end={end}&bucket=1h"),
+                StatusCode::OK,
+            ),
+        ] {
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), status, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn stations_and_groups_are_compared_in_the_same_buckets() {
+        let db = TestDb::new().await;
+        let (_, first) = db.station("st-1").await;
+        let (_, second) = db.station("st-2").await;
+        db.station("st-3").await;
+        GroupService::new(&db)
+            .put_group("utrecht".into(), vec!["st-2".into(), "st-3".into()])
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(add_readings)
+                .service(compare_readings),
+        )
+        .await;
+        let hour = Utc
+            .timestamp_opt(Utc::now().timestamp() / 3600 * 3600 - 2 * 3600, 0)
+            .unwrap();
+
+        for (token, key, readings) in [
+            (
+                "st-1",
+                &first.signing_key,
+                vec![
+                    (hour + Duration::minutes(10), 10.0),
+                    (hour + Duration::minutes(40), 20.0),
+                ],
+            ),
+            (
+                "st-2",
+                &second.signing_key,
+                vec![(hour + Duration::minutes(70), 40.0)],
+            ),
+        ] {
+            let payload = sign(key, &pm25_batch_body(&readings));
+            let uri = format!("/reading/{token}/batch");
+            let res = test::call_service(&app, put_signed(&uri, &payload).to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        let start = hour.timestamp_millis();
+        let end = (hour + Duration::hours(2)).timestamp_millis();
+        let uri = format!(
+            "/reading/compare?stations=st-1,st-2&group=utrecht&metric=pm25&start={start}&end={end}&bucket=1h"
+        );
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let comparison: ComparisonSeries = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(comparison.unit, "µg/m³");
+        assert_eq!(
+            comparison.dates,
+            vec![start, (hour + Duration::hours(1)).timestamp_millis()]
+        );
+        // Every station once, also the one that is passed and a member of the group
+        assert_eq!(
+            comparison.series.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("st-1".to_owned(), vec![Some(15.0), None]),
+                ("st-2".to_owned(), vec![None, Some(40.0)]),
+                ("st-3".to_owned(), vec![None, None]),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn comparisons_are_validated() {
+        let db = TestDb::new().await;
+        db.station("st-1").await;
+        let app =
+            test::init_service(App::new().app_data(db.data()).service(compare_readings)).await;
+        let range = format!(
+            "metric=pm25&start={}&end={}&bucket=1h",
+            (Utc::now() - Duration::days(1)).timestamp_millis(),
+            Utc::now().timestamp_millis()
+        );
+        let too_many = (0..=MAX_COMPARED_STATIONS)
+            .map(|i| format!("st-{i}"))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        for (query, status) in [
+            ("stations=st-1".to_owned(), StatusCode::OK),
+            ("stations=".to_owned(), StatusCode::BAD_REQUEST),
+            (format!("stations={too_many}"), StatusCode::BAD_REQUEST),
+            ("stations=st-1,st-9".to_owned(), StatusCode::NOT_FOUND),
+            ("group=unknown".to_owned(), StatusCode::NOT_FOUND),
+        ] {
+            let uri = format!("/reading/compare?{query}&{range}");
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), status, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn readings_and_buckets_are_exported_as_csv() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(add_readings)
+                .service(export_readings_between)
+                .service(export_aggregated_readings),
+        )
+        .await;
+        let hour = Utc
+            .timestamp_opt(Utc::now().timestamp() / 3600 * 3600 - 2 * 3600, 0)
+            .unwrap();
+        let readings = [
+            (hour + Duration::minutes(70), 40.0),
+            (hour + Duration::minutes(10), 10.5),
+        ];
+
+        let payload = sign(&credentials.signing_key, &pm25_batch_body(&readings));
+        let res = test::call_service(
+            &app,
+            put_signed("/reading/st-1/batch", &payload).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let start = hour.timestamp_millis();
+        let end = (hour + Duration::hours(2)).timestamp_millis();
+        let uri = format!("/reading/st-1/between.csv?start={start}&end={end}");
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        assert_eq!(
+            res.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"st-1-readings.csv\""
+        );
+        let csv = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        // Oldest first, each line ended by CRLF
+        let minutes_10 = hour + Duration::minutes(10);
+        let minutes_70 = hour + Duration::minutes(70);
+        assert_eq!(
+            csv,
+            format!(
+                "date,temperature (°C),humidity (%),pm10 (µg/m³),pm25 (µg/m³),co2 (ppm),voc (ppb)\r\n\
+                {},21.5,40,12,10.5,450,0.2\r\n\
+                {},21.5,40,12,40,450,0.2\r\n",
+                minutes_10.to_rfc3339_opts(SecondsFormat::Millis, true),
+                minutes_70.to_rfc3339_opts(SecondsFormat::Millis, true),
+            )
+        );
+
+        let uri = format!("/reading/st-1/between.csv?start={start}&end={end}&timestamps=epoch_ms");
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let csv = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+        let dates: Vec<_> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(
+            dates,
+            [minutes_10, minutes_70].map(|date| date.timestamp_millis().to_string())
+        );
+
+        let uri = format!("/reading/st-1/aggregate.csv?start={start}&end={end}&bucket=1h");
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let csv = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("date,temperature_count,temperature_avg (°C),"));
+        let pm25: Vec<Vec<&str>> = lines[1..]
+            .iter()
+            .map(|line| line.split(',').skip(13).take(4).collect())
+            .collect();
+        assert_eq!(
+            pm25,
+            vec![
+                vec!["1", "10.5", "10.5", "10.5"],
+                vec!["1", "40", "40", "40"]
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn csv_exports_are_validated_before_streaming() {
+        let db = TestDb::new().await;
+        db.station("st-1").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(export_readings_between)
+                .service(export_aggregated_readings),
+        )
+        .await;
+        let end = Utc::now().timestamp_millis();
+        let start = (Utc::now() - Duration::days(1)).timestamp_millis();
+
+        for (uri, status) in [
+            (
+                format!("/reading/st-1/between.csv?start={end}&end={start}"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("/reading/st-2/between.csv?start={start}&end={end}"),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                format!("/reading/st-1/between.csv?start={start}&end={end}&timestamps=unix"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("/reading/st-1/aggregate.csv?start={end}&end={start}&bucket=1h"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("/reading/st-2/aggregate.csv?start={start}&end={end}&bucket=1h"),
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), status, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn readings_are_streamed_as_ndjson_when_accepted() {
+        let db = TestDb::new().await;
+        let (_, first) = db.station("st-1").await;
+        let (_, second) = db.station("st-2").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(add_readings)
+                .service(get_readings_between)
+                .service(get_past_hour_readings),
+        )
+        .await;
+        let now = Utc::now();
+        let dates: Vec<_> = (1..=3).map(|i| now - Duration::minutes(i)).collect();
+
+        for (token, key) in [("st-1", &first.signing_key), ("st-2", &second.signing_key)] {
+            let payload = sign(key, &batch_body(&dates));
+            let uri = format!("/reading/{token}/batch");
+            let res = test::call_service(&app, put_signed(&uri, &payload).to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        let start = (now - Duration::hours(1)).timestamp_millis();
+        let end = now.timestamp_millis();
+        let uri = format!("/reading/st-1/between/{start}/{end}?limit=1");
+        for accept in [
+            "application/x-ndjson",
+            "application/json, application/ndjson; q=0.9",
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&uri)
+                .insert_header((header::ACCEPT, accept))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(
+                res.headers().get(header::CONTENT_TYPE).unwrap(),
+                "application/x-ndjson"
+            );
+            let body = test::read_body(res).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            assert!(body.ends_with('\n'));
+            // Every reading, oldest first, regardless of the page size
+            let streamed: Vec<i64> = body
+                .lines()
+                .map(|line| {
+                    serde_json::from_str::<Reading>(line)
+                        .unwrap()
+                        .date
+                        .timestamp_millis()
+                })
+                .collect();
+            let mut expected: Vec<_> = dates.iter().map(|date| date.timestamp_millis()).collect();
+            expected.sort();
+            assert_eq!(streamed, expected, "{accept}");
+        }
+
+        // A page otherwise
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let page: Page<Reading> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page.items.len(), 1);
+        assert!(page.next.is_some());
 
-    if id.is_ok() {
-        HttpResponse::Ok().json(id.unwrap())
-    } else {
-        HttpResponse::InternalServerError().finish()
+        let req = test::TestRequest::get()
+            .uri("/reading/all/past_hour")
+            .insert_header((header::ACCEPT, "application/x-ndjson"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let lines = std::str::from_utf8(&body).unwrap().lines().count();
+        assert_eq!(lines, 6);
     }
 }
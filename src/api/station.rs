@@ -1,13 +1,23 @@
 use crate::{
-    models::reading::Reading, models::station::Station, repository::db::DBRepository,
-    services::reading_service::ReadingService, services::station_service::StationService,
+    api::auth::AuthenticatedStation,
+    api::reading::PageRequest,
+    error::{OptionalExt, Result},
+    models::aqi::Aqi,
+    models::geo::BoundingBox,
+    models::geojson::{Feature, FeatureCollection, Geometry},
+    models::page::Page,
+    models::reading::Reading,
+    models::station::Station,
+    repository::db::DBRepository,
+    services::reading_service::ReadingService,
+    services::station_service::StationService,
 };
 use actix_web::{
     get, post, put,
-    web::{Data, Json, Path},
+    web::{Data, Json, Path, Query},
     HttpResponse,
 };
-use chrono::{DateTime, Utc};
+use chrono::{serde::ts_milliseconds, DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -16,6 +26,46 @@ pub struct GetStationResponse {
     pub last_reading: Option<Reading>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct StationDistanceResponse {
+    #[serde(flatten)]
+    pub station: GetStationResponse,
+    pub distance_km: f64,
+}
+
+/// Properties of a station in the GeoJSON export, kept flat where possible so GIS tools
+/// can show them as columns
+#[derive(Serialize, Deserialize)]
+pub struct StationFeatureProperties {
+    pub token: String,
+    pub uid: String,
+    pub hw_version: i32,
+    pub sw_version: i32,
+    pub country: String,
+    pub province: String,
+    pub city: String,
+    pub street: String,
+    pub number: String,
+    #[serde(with = "ts_milliseconds")]
+    pub last_online: DateTime<Utc>,
+    pub last_reading: Option<Reading>,
+    pub aqi: Aqi,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RadiusRequest {
+    pub lat: f64,
+    pub lon: f64,
+    pub radius_km: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NearestRequest {
+    pub lat: f64,
+    pub lon: f64,
+    pub count: Option<i64>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct AddStationRequest {
     pub token: String,
@@ -24,6 +74,14 @@ pub struct AddStationRequest {
     pub location: Option<AddLocationRequest>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct AddStationResponse {
+    pub id: i32,
+    pub secret: String,
+    /// Key the station signs its readings with
+    pub signing_key: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct AddLocationRequest {
     pub latitude: f32,
@@ -43,102 +101,510 @@ pub struct UpdateStationRequest {
     pub last_online: Option<DateTime<Utc>>,
 }
 
-async fn create_station_response(db: &Data<DBRepository>, station: Station) -> GetStationResponse {
-    let service = ReadingService::new(&db);
-    let last_reading = service.get_latest_reading(station.token.clone()).await.ok();
+async fn create_station_response(
+    db: &Data<DBRepository>,
+    station: Station,
+) -> Result<GetStationResponse> {
+    let service = ReadingService::new(db);
+    let last_reading = service
+        .get_latest_reading(station.token.clone())
+        .await
+        .optional()?;
 
-    GetStationResponse {
+    Ok(GetStationResponse {
         station,
         last_reading,
-    }
+    })
 }
 
 async fn create_station_responses(
     db: Data<DBRepository>,
     stations: Vec<Station>,
-) -> Vec<GetStationResponse> {
+) -> Result<Vec<GetStationResponse>> {
     let service = ReadingService::new(&db);
-    let mut result = Vec::with_capacity(stations.len());
+    let mut last_readings = service.get_latest_reading_of_stations(&stations).await?;
 
-    for station in stations.into_iter() {
-        let res = create_station_response(&db, station).await;
-        result.push(res)
-    }
+    Ok(stations
+        .into_iter()
+        .map(|station| GetStationResponse {
+            last_reading: last_readings.remove(&station.id),
+            station,
+        })
+        .collect())
+}
+
+async fn create_station_page(
+    db: Data<DBRepository>,
+    page: Page<Station>,
+) -> Result<Page<GetStationResponse>> {
+    Ok(Page {
+        items: create_station_responses(db, page.items).await?,
+        next: page.next,
+    })
+}
+
+async fn create_station_distance_responses(
+    db: Data<DBRepository>,
+    stations: Vec<(Station, f64)>,
+) -> Result<Vec<StationDistanceResponse>> {
+    let (stations, distances): (Vec<Station>, Vec<f64>) = stations.into_iter().unzip();
+    let responses = create_station_responses(db, stations).await?;
 
-    result
+    Ok(responses
+        .into_iter()
+        .zip(distances)
+        .map(|(station, distance_km)| StationDistanceResponse {
+            station,
+            distance_km,
+        })
+        .collect())
+}
+
+/// A point feature of the station, None for stations without a location
+fn create_station_feature(
+    station: Station,
+    last_reading: Option<Reading>,
+    aqi: Aqi,
+) -> Result<Option<Feature>> {
+    let Some(location) = station.location else {
+        return Ok(None);
+    };
+
+    let properties = StationFeatureProperties {
+        token: station.token,
+        uid: station.uid,
+        hw_version: station.hw_version,
+        sw_version: station.sw_version,
+        country: location.country,
+        province: location.province,
+        city: location.city,
+        street: location.street,
+        number: location.number,
+        last_online: station.last_online,
+        last_reading,
+        aqi,
+    };
+
+    Ok(Some(Feature {
+        geometry: Geometry::Point {
+            coordinates: [location.longitude.into(), location.latitude.into()],
+        },
+        properties: serde_json::to_value(properties)?,
+    }))
 }
 
 #[get("/station/{station_token}")]
-pub async fn get_station(db: Data<DBRepository>, station_token: Path<String>) -> HttpResponse {
+pub async fn get_station(
+    db: Data<DBRepository>,
+    station_token: Path<String>,
+) -> Result<HttpResponse> {
     let service = StationService::new(&db);
     let token = station_token.into_inner();
-    let station = service.get_station(token).await;
+    let station = service.get_station(token).await?;
+    let res = create_station_response(&db, station).await?;
 
-    if let Ok(station) = station {
-        let res = create_station_response(&db, station).await;
-        HttpResponse::Ok().json(res)
-    } else {
-        HttpResponse::NotFound().finish()
-    }
+    Ok(HttpResponse::Ok().json(res))
 }
 
 #[get("/station/all/active")]
-pub async fn get_active_stations(db: Data<DBRepository>) -> HttpResponse {
+pub async fn get_active_stations(
+    db: Data<DBRepository>,
+    page: Query<PageRequest>,
+) -> Result<HttpResponse> {
+    let service = StationService::new(&db);
+    let stations = service.get_active_stations_page(&page).await?;
+    let res = create_station_page(db, stations).await?;
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/station/all/active.geojson")]
+pub async fn get_active_stations_geojson(db: Data<DBRepository>) -> Result<HttpResponse> {
     let service = StationService::new(&db);
-    let result = service.get_active_stations().await;
+    let stations: Vec<Station> = service
+        .get_active_stations()
+        .await?
+        .into_iter()
+        .filter(|station| station.location.is_some())
+        .collect();
 
-    if let Ok(stations) = result {
-        let res = create_station_responses(db, stations).await;
-        HttpResponse::Ok().json(res)
-    } else {
-        HttpResponse::NoContent().finish()
+    let service = ReadingService::new(&db);
+    let mut last_readings = service.get_latest_reading_of_stations(&stations).await?;
+    let mut aqis = service.get_aqi_of_stations(&stations).await?;
+    let mut features = Vec::with_capacity(stations.len());
+
+    for station in stations.into_iter() {
+        let last_reading = last_readings.remove(&station.id);
+        let aqi = aqis
+            .remove(&station.id)
+            .unwrap_or_else(|| Aqi::from_hourly(&[]));
+        if let Some(feature) = create_station_feature(station, last_reading, aqi)? {
+            features.push(feature)
+        }
     }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/geo+json")
+        .json(FeatureCollection { features }))
+}
+
+#[get("/station/area/bbox")]
+pub async fn get_stations_in_bbox(
+    db: Data<DBRepository>,
+    query: Query<BoundingBox>,
+    page: Query<PageRequest>,
+) -> Result<HttpResponse> {
+    let service = StationService::new(&db);
+    let stations = service
+        .get_stations_in_bbox(query.into_inner(), &page)
+        .await?;
+    let res = create_station_page(db, stations).await?;
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/station/area/radius")]
+pub async fn get_stations_within(
+    db: Data<DBRepository>,
+    query: Query<RadiusRequest>,
+) -> Result<HttpResponse> {
+    let service = StationService::new(&db);
+    let request = query.into_inner();
+    let stations = service
+        .get_stations_within(request.lat, request.lon, request.radius_km)
+        .await?;
+    let res = create_station_distance_responses(db, stations).await?;
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[get("/station/area/nearest")]
+pub async fn get_nearest_stations(
+    db: Data<DBRepository>,
+    query: Query<NearestRequest>,
+) -> Result<HttpResponse> {
+    let service = StationService::new(&db);
+    let request = query.into_inner();
+    let stations = service
+        .get_nearest_stations(request.lat, request.lon, request.count.unwrap_or(1))
+        .await?;
+    let res = create_station_distance_responses(db, stations).await?;
+
+    Ok(HttpResponse::Ok().json(res))
 }
 
 #[put("/station/{station_token}/register")]
-pub async fn add_station(db: Data<DBRepository>, body: Json<AddStationRequest>) -> HttpResponse {
+pub async fn add_station(
+    db: Data<DBRepository>,
+    body: Json<AddStationRequest>,
+) -> Result<HttpResponse> {
     let service = StationService::new(&db);
     let request = body.into_inner();
     let station = Station::from(request);
-    let id = service.put_station(station).await;
+    let (id, credentials) = service.put_station(station).await?;
 
-    if let Ok(id) = id {
-        HttpResponse::Ok().json(id)
-    } else {
-        HttpResponse::InternalServerError().finish()
-    }
+    Ok(HttpResponse::Ok().json(AddStationResponse {
+        id,
+        secret: credentials.secret,
+        signing_key: credentials.signing_key,
+    }))
 }
 
 #[post("/station/{station_token}/update")]
 pub async fn update_station(
     db: Data<DBRepository>,
-    station_token: Path<String>,
+    station: AuthenticatedStation,
     body: Json<UpdateStationRequest>,
-) -> HttpResponse {
+) -> Result<HttpResponse> {
     let service = StationService::new(&db);
-    let token = station_token.into_inner();
     let request = body.into_inner();
-    let result = service.update_station(token, request).await;
+    service.update_station(station.0, request).await?;
 
-    if result.is_ok() {
-        HttpResponse::Ok().finish()
-    } else {
-        HttpResponse::InternalServerError().finish()
-    }
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[post("/station/{station_token}/location/update")]
 pub async fn update_location(
     db: Data<DBRepository>,
+    station: AuthenticatedStation,
     body: Json<AddLocationRequest>,
-) -> HttpResponse {
+) -> Result<HttpResponse> {
     let service = StationService::new(&db);
     let request = body.into_inner();
-    let result = service.update_location(request).await;
+    service.update_location(station.0, request).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, App};
+    use chrono::Duration;
+
+    use super::*;
+    use crate::{
+        api::reading::add_reading,
+        models::station::StationCredentials,
+        testing::{reading_body, sign, signed_request, TestDb},
+    };
+
+    /// Register a station online now, located at the coordinate
+    async fn located_station(
+        db: &TestDb,
+        token: &str,
+        latitude: f32,
+        longitude: f32,
+    ) -> StationCredentials {
+        let (station, credentials) = db.station(token).await;
+        sqlx::query("UPDATE stations SET last_online = now() WHERE id = $1")
+            .bind(station.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let location = AddLocationRequest {
+            latitude,
+            longitude,
+            country: "NL".into(),
+            province: "Utrecht".into(),
+            city: "Utrecht".into(),
+            street: "Domplein".into(),
+            number: "1".into(),
+            station_token: token.into(),
+        };
+
+        StationService::new(db)
+            .update_location(station, location)
+            .await
+            .unwrap();
+
+        credentials
+    }
+
+    /// The tokens of every page of `uri`, following the cursors
+    async fn paged_tokens(db: &TestDb, uri: &str) -> Vec<Vec<String>> {
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(get_active_stations)
+                .service(get_stations_in_bbox),
+        )
+        .await;
+        let mut pages = vec![];
+        let mut next = uri.to_owned();
+        loop {
+            let req = test::TestRequest::get().uri(&next).to_request();
+            let page: Page<GetStationResponse> = test::call_and_read_body_json(&app, req).await;
+            pages.push(page.items.into_iter().map(|s| s.station.token).collect());
+            match page.next {
+                Some(cursor) => next = format!("{uri}&cursor={cursor}"),
+                None => return pages,
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn active_stations_are_paged_by_id() {
+        let db = TestDb::new().await;
+        for token in ["st-1", "st-2", "st-3"] {
+            located_station(&db, token, 52.09, 5.12).await;
+        }
+        // Offline for longer than the active window
+        db.station("st-4").await;
+
+        let pages = paged_tokens(&db, "/station/all/active?limit=2").await;
+        assert_eq!(pages, vec![vec!["st-1", "st-2"], vec!["st-3"]]);
+
+        let pages = paged_tokens(&db, "/station/all/active?limit=3").await;
+        assert_eq!(pages, vec![vec!["st-1", "st-2", "st-3"]]);
+    }
+
+    #[actix_web::test]
+    async fn stations_in_a_bbox_are_paged_by_id() {
+        let db = TestDb::new().await;
+        located_station(&db, "utrecht", 52.09, 5.12).await;
+        located_station(&db, "berlin", 52.52, 13.40).await;
+        located_station(&db, "amsterdam", 52.37, 4.90).await;
+        located_station(&db, "rotterdam", 51.92, 4.48).await;
+
+        let uri = "/station/area/bbox?min_lat=51&min_lon=4&max_lat=53&max_lon=6&limit=2";
+        let pages = paged_tokens(&db, uri).await;
+        assert_eq!(pages, vec![vec!["utrecht", "amsterdam"], vec!["rotterdam"]]);
+
+        let app =
+            test::init_service(App::new().app_data(db.data()).service(get_stations_in_bbox)).await;
+        for uri in [
+            "/station/area/bbox?min_lat=51&min_lon=4&max_lat=53&max_lon=6&limit=0",
+            "/station/area/bbox?min_lat=51&min_lon=4&max_lat=53&max_lon=6&cursor=nothex",
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn stations_within_a_radius_are_nearest_first() {
+        let db = TestDb::new().await;
+        located_station(&db, "berlin", 52.52, 13.40).await;
+        located_station(&db, "amsterdam", 52.37, 4.90).await;
+        located_station(&db, "utrecht", 52.09, 5.12).await;
+        // Without a location
+        db.station("unknown").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(get_stations_within)
+                .service(get_nearest_stations),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/station/area/radius?lat=52.09&lon=5.12&radius_km=40")
+            .to_request();
+        let stations: Vec<StationDistanceResponse> = test::call_and_read_body_json(&app, req).await;
+        let found: Vec<_> = stations
+            .iter()
+            .map(|s| (s.station.station.token.as_str(), s.distance_km.round()))
+            .collect();
+        assert_eq!(found, vec![("utrecht", 0.0), ("amsterdam", 35.0)]);
+
+        // Radii beyond the other side of the earth cover it whole
+        let req = test::TestRequest::get()
+            .uri("/station/area/radius?lat=52.09&lon=5.12&radius_km=100000")
+            .to_request();
+        let stations: Vec<StationDistanceResponse> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(stations.len(), 3);
+
+        let req = test::TestRequest::get()
+            .uri("/station/area/nearest?lat=52.52&lon=13.40&count=2")
+            .to_request();
+        let stations: Vec<StationDistanceResponse> = test::call_and_read_body_json(&app, req).await;
+        let found: Vec<_> = stations
+            .iter()
+            .map(|s| s.station.station.token.as_str())
+            .collect();
+        assert_eq!(found, vec!["berlin", "utrecht"]);
+
+        let req = test::TestRequest::get()
+            .uri("/station/area/nearest?lat=52.52&lon=13.40")
+            .to_request();
+        let stations: Vec<StationDistanceResponse> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(stations.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn bounding_boxes_can_cross_the_antimeridian() {
+        let db = TestDb::new().await;
+        located_station(&db, "fiji", -17.7, 178.0).await;
+        located_station(&db, "samoa", -13.8, -172.1).await;
+        located_station(&db, "galapagos", -0.9, -89.6).await;
+
+        let uri = "/station/area/bbox?min_lat=-20&min_lon=170&max_lat=-10&max_lon=-170&limit=10";
+        let pages = paged_tokens(&db, uri).await;
+        assert_eq!(pages, vec![vec!["fiji", "samoa"]]);
+    }
+
+    #[actix_web::test]
+    async fn spatial_queries_are_validated() {
+        let db = TestDb::new().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(get_stations_in_bbox)
+                .service(get_stations_within)
+                .service(get_nearest_stations),
+        )
+        .await;
+
+        for uri in [
+            "/station/area/bbox?min_lat=10&min_lon=0&max_lat=-10&max_lon=10",
+            "/station/area/bbox?min_lat=-10&min_lon=0&max_lat=95&max_lon=10",
+            "/station/area/radius?lat=52&lon=5&radius_km=0",
+            "/station/area/radius?lat=91&lon=5&radius_km=10",
+            "/station/area/nearest?lat=52&lon=181",
+            "/station/area/nearest?lat=52&lon=5&count=0",
+            "/station/area/nearest?lat=52&lon=5&count=101",
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn active_located_stations_are_exported_as_geojson() {
+        let db = TestDb::new().await;
+        let credentials = located_station(&db, "utrecht", 52.09, 5.12).await;
+        located_station(&db, "amsterdam", 52.37, 4.90).await;
+        located_station(&db, "offline", 51.92, 4.48).await;
+        sqlx::query(
+            "UPDATE stations SET last_online = now() - interval '1 day' WHERE token = 'offline'",
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        // Active, but without a location
+        let (unlocated, _) = db.station("unlocated").await;
+        sqlx::query("UPDATE stations SET last_online = now() WHERE id = $1")
+            .bind(unlocated.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(add_reading)
+                .service(get_active_stations_geojson),
+        )
+        .await;
+
+        let body = reading_body("utrecht", Utc::now() - Duration::minutes(1), 20.0);
+        let payload = sign(&credentials.signing_key, &body);
+        let req = signed_request(
+            test::TestRequest::put().uri("/reading/utrecht/new"),
+            &payload,
+        );
+        test::call_service(&app, req.to_request()).await;
+
+        let req = test::TestRequest::get()
+            .uri("/station/all/active.geojson")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "application/geo+json"
+        );
+        let collection: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().unwrap();
+        let tokens: Vec<_> = features
+            .iter()
+            .map(|f| f["properties"]["token"].as_str().unwrap())
+            .collect();
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens.contains(&"utrecht") && tokens.contains(&"amsterdam"));
+
+        let utrecht = features
+            .iter()
+            .find(|f| f["properties"]["token"] == "utrecht")
+            .unwrap();
+        assert_eq!(utrecht["type"], "Feature");
+        assert_eq!(utrecht["geometry"]["type"], "Point");
+        // Longitude first
+        let coordinates = utrecht["geometry"]["coordinates"].as_array().unwrap();
+        assert!((coordinates[0].as_f64().unwrap() - 5.12).abs() < 1e-5);
+        assert!((coordinates[1].as_f64().unwrap() - 52.09).abs() < 1e-5);
+        let properties = &utrecht["properties"];
+        assert_eq!(properties["city"], "Utrecht");
+        assert_eq!(properties["last_reading"]["pm25"], 20.0);
+        assert!(properties["last_online"].is_i64());
+        assert!(properties["aqi"]["caqi_hourly"]["index"].is_u64());
 
-    if result.is_ok() {
-        HttpResponse::Ok().finish()
-    } else {
-        HttpResponse::InternalServerError().finish()
+        let amsterdam = features
+            .iter()
+            .find(|f| f["properties"]["token"] == "amsterdam")
+            .unwrap();
+        assert!(amsterdam["properties"]["last_reading"].is_null());
+        assert!(amsterdam["properties"]["aqi"]["caqi_hourly"].is_null());
     }
 }
@@ -0,0 +1,11 @@
+use actix_web::{get, web::Data, HttpResponse};
+
+use crate::{error::Result, repository::db::DBRepository, services::schema_service::SchemaService};
+
+#[get("/status")]
+pub async fn get_status(db: Data<DBRepository>) -> Result<HttpResponse> {
+    let service = SchemaService::new(&db);
+    let status = service.get_status().await?;
+
+    Ok(HttpResponse::Ok().json(status))
+}
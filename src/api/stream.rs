@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    rt::time::{interval_at, Instant},
+    web::{Bytes, Data, Path},
+    HttpRequest, HttpResponse,
+};
+use futures_util::{stream, StreamExt};
+
+use crate::{
+    error::{Error, Result},
+    events::{ReadingEvent, ReadingSubscription},
+    repository::db::DBRepository,
+    services::reading_service::ReadingService,
+};
+
+/// Sent when nothing else was for a while, so proxies don't close the connection
+const KEEP_ALIVE: &[u8] = b": keep-alive\n\n";
+
+/// Sent by a reconnecting EventSource, the id of the last event it got
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+
+#[get("/reading/stream")]
+pub async fn stream_readings(db: Data<DBRepository>, req: HttpRequest) -> Result<HttpResponse> {
+    let service = ReadingService::new(&db);
+    let (missed, subscription) = service.subscribe(None, last_event_id(&req)?).await?;
+
+    Ok(sse_response(&db, missed, subscription))
+}
+
+#[get("/reading/{station_token}/stream")]
+pub async fn stream_station_readings(
+    db: Data<DBRepository>,
+    req: HttpRequest,
+    station_token: Path<String>,
+) -> Result<HttpResponse> {
+    let service = ReadingService::new(&db);
+    let (missed, subscription) = service
+        .subscribe(Some(station_token.into_inner()), last_event_id(&req)?)
+        .await?;
+
+    Ok(sse_response(&db, missed, subscription))
+}
+
+fn last_event_id(req: &HttpRequest) -> Result<Option<i32>> {
+    req.headers()
+        .get(LAST_EVENT_ID)
+        .map(|id| {
+            id.to_str()
+                .ok()
+                .and_then(|id| id.trim().parse().ok())
+                .ok_or_else(|| Error::Validation(format!("invalid {LAST_EVENT_ID} header")))
+        })
+        .transpose()
+}
+
+fn reading_frame(event: &ReadingEvent) -> Result<Bytes> {
+    let data = serde_json::to_string(event)?;
+    let frame = format!("event: reading\nid: {}\ndata: {data}\n\n", event.reading.id);
+
+    Ok(Bytes::from(frame))
+}
+
+/// Push the missed readings and then every reading of the subscription as Server-Sent
+/// Events named `reading`, with the reading id as event id. Live readings that were among
+/// the missed ones are not sent twice
+fn sse_response(
+    db: &Data<DBRepository>,
+    missed: Vec<ReadingEvent>,
+    subscription: ReadingSubscription,
+) -> HttpResponse {
+    let period = Duration::from_secs(db.settings().events.keep_alive_seconds);
+    let keep_alive = interval_at(Instant::now() + period, period);
+    let sent_through = missed.last().map(|event| event.reading.id);
+
+    let missed = stream::iter(missed.into_iter().map(|event| reading_frame(&event)));
+    let live = stream::unfold(
+        (subscription, keep_alive),
+        move |(mut subscription, mut keep_alive)| async move {
+            let frame = loop {
+                tokio::select! {
+                    event = subscription.next() => {
+                        let event = event?;
+                        if sent_through.is_some_and(|id| event.reading.id <= id) {
+                            continue;
+                        }
+                        break reading_frame(&event);
+                    }
+                    _ = keep_alive.tick() => break Ok(Bytes::from_static(KEEP_ALIVE)),
+                }
+            };
+
+            Some((frame, (subscription, keep_alive)))
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        // Keeps nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(missed.chain(live))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::poll_fn, pin::Pin, time::Duration as StdDuration};
+
+    use actix_web::{body::MessageBody, http::StatusCode, test, App};
+    use chrono::{Duration, Utc};
+
+    use super::*;
+    use crate::{
+        api::reading::add_reading,
+        testing::{reading_body, settings, sign, signed_request, TestDb},
+    };
+
+    /// The next chunk of a streamed body, failing the test if it takes over a few seconds
+    async fn next_chunk(body: &mut (impl MessageBody + Unpin)) -> String {
+        let chunk = actix_web::rt::time::timeout(
+            StdDuration::from_secs(5),
+            poll_fn(|cx| Pin::new(&mut *body).poll_next(cx)),
+        )
+        .await
+        .expect("no event within 5 seconds");
+        let chunk = chunk
+            .expect("the stream ended")
+            .ok()
+            .expect("the stream failed");
+
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
+    fn event_id(frame: &str) -> i32 {
+        frame
+            .lines()
+            .find_map(|line| line.strip_prefix("id: "))
+            .expect("an event with an id")
+            .parse()
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn reconnecting_streams_get_the_readings_they_missed_first() {
+        let mut settings = settings();
+        settings.events.keep_alive_seconds = 1;
+        let db = TestDb::with_settings(settings).await;
+        let (_, first) = db.station("st-1").await;
+        let (_, second) = db.station("st-2").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(add_reading)
+                .service(stream_station_readings),
+        )
+        .await;
+
+        let put = |token: &'static str, key: String, minutes: i64| {
+            let body = reading_body(token, Utc::now() - Duration::minutes(minutes), 5.0);
+            let payload = sign(&key, &body);
+            signed_request(
+                test::TestRequest::put().uri(&format!("/reading/{token}/new")),
+                &payload,
+            )
+            .to_request()
+        };
+        let mut ids = vec![];
+        for minutes in [3, 2, 1] {
+            let req = put("st-1", first.signing_key.clone(), minutes);
+            ids.push(test::call_and_read_body_json::<_, _, i32>(&app, req).await);
+            let req = put("st-2", second.signing_key.clone(), minutes);
+            test::call_service(&app, req).await;
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/reading/st-1/stream")
+            .insert_header((LAST_EVENT_ID, ids[0].to_string()))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+        let mut body = res.into_body();
+
+        // The readings of the station after the last one seen, oldest first
+        for id in &ids[1..] {
+            let frame = next_chunk(&mut body).await;
+            assert!(frame.starts_with("event: reading\n"), "{frame}");
+            assert!(frame.ends_with("\n\n"));
+            assert_eq!(event_id(&frame), *id);
+            let data = frame
+                .lines()
+                .find_map(|l| l.strip_prefix("data: "))
+                .unwrap();
+            let event: serde_json::Value = serde_json::from_str(data).unwrap();
+            assert_eq!(event["station"], "st-1");
+        }
+
+        // Then the live ones, of this station only
+        let req = put("st-2", second.signing_key.clone(), 0);
+        test::call_service(&app, req).await;
+        let req = put("st-1", first.signing_key.clone(), 0);
+        let live: i32 = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(event_id(&next_chunk(&mut body).await), live);
+
+        // And a comment when nothing happens
+        assert_eq!(next_chunk(&mut body).await, ": keep-alive\n\n");
+    }
+
+    #[actix_web::test]
+    async fn streams_are_validated() {
+        let db = TestDb::new().await;
+        db.station("st-1").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(db.data())
+                .service(stream_readings)
+                .service(stream_station_readings),
+        )
+        .await;
+
+        for (uri, last_event_id, status) in [
+            ("/reading/stream", Some("latest"), StatusCode::BAD_REQUEST),
+            ("/reading/st-1/stream", Some(""), StatusCode::BAD_REQUEST),
+            ("/reading/st-2/stream", None, StatusCode::NOT_FOUND),
+            ("/reading/stream", Some(" 12 "), StatusCode::OK),
+        ] {
+            let mut req = test::TestRequest::get().uri(uri);
+            if let Some(id) = last_event_id {
+                req = req.insert_header((LAST_EVENT_ID, id));
+            }
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), status, "{uri} {last_event_id:?}");
+        }
+    }
+}
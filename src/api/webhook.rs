@@ -0,0 +1,115 @@
+use actix_web::{
+    delete, get, post, put,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::auth::Admin,
+    error::Result,
+    models::webhook::{DeliveryStatus, Webhook, WebhookEvent},
+    repository::db::DBRepository,
+    services::webhook_service::WebhookService,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct WebhookRequest {
+    /// Receives the events as signed POST requests
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Token of the station to limit the webhook to
+    pub station: Option<String>,
+    /// Defaults to true
+    pub enabled: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct AddWebhookResponse {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    /// Key of the request signatures, only shown once
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeliveriesRequest {
+    pub status: Option<DeliveryStatus>,
+    /// The `next` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[get("/webhooks")]
+pub async fn get_webhooks(db: Data<DBRepository>, _admin: Admin) -> Result<HttpResponse> {
+    let service = WebhookService::new(&db);
+    let webhooks = service.get_webhooks().await?;
+
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+#[get("/webhook/{id}")]
+pub async fn get_webhook(
+    db: Data<DBRepository>,
+    _admin: Admin,
+    id: Path<i32>,
+) -> Result<HttpResponse> {
+    let service = WebhookService::new(&db);
+    let webhook = service.get_webhook(id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+#[post("/webhook")]
+pub async fn add_webhook(
+    db: Data<DBRepository>,
+    _admin: Admin,
+    body: Json<WebhookRequest>,
+) -> Result<HttpResponse> {
+    let service = WebhookService::new(&db);
+    let (webhook, secret) = service.put_webhook(body.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(AddWebhookResponse { webhook, secret }))
+}
+
+#[put("/webhook/{id}")]
+pub async fn update_webhook(
+    db: Data<DBRepository>,
+    _admin: Admin,
+    id: Path<i32>,
+    body: Json<WebhookRequest>,
+) -> Result<HttpResponse> {
+    let service = WebhookService::new(&db);
+    let webhook = service
+        .update_webhook(id.into_inner(), body.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+#[delete("/webhook/{id}")]
+pub async fn delete_webhook(
+    db: Data<DBRepository>,
+    _admin: Admin,
+    id: Path<i32>,
+) -> Result<HttpResponse> {
+    let service = WebhookService::new(&db);
+    service.delete_webhook(id.into_inner()).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/webhook/{id}/deliveries")]
+pub async fn get_webhook_deliveries(
+    db: Data<DBRepository>,
+    _admin: Admin,
+    id: Path<i32>,
+    query: Query<DeliveriesRequest>,
+) -> Result<HttpResponse> {
+    let service = WebhookService::new(&db);
+    let deliveries = service
+        .get_deliveries(id.into_inner(), query.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(deliveries))
+}
@@ -0,0 +1,30 @@
+//! Removes readings that were stored more than once for the same station and date,
+//! keeping the oldest row. The `unique_readings` migration does the same, this shows what
+//! it would delete (with `--dry-run`) or removes them ahead of it.
+//!
+//! Usage: `cargo run --bin dedupe_readings [--dry-run]`
+
+use auspex::{
+    config::{Config, Settings},
+    repository::db::DBRepository,
+};
+
+#[actix_web::main]
+async fn main() -> anyhow::Result<()> {
+    let dry_run = std::env::args().any(|arg| arg == "--dry-run");
+
+    let config = Config::new(Settings::load()?).await?;
+    let db = DBRepository::new(config);
+
+    let duplicates = db.count_duplicate_readings().await?;
+    println!("found {duplicates} duplicate readings");
+
+    if dry_run || duplicates == 0 {
+        return Ok(());
+    }
+
+    let deleted = db.delete_duplicate_readings().await?;
+    println!("deleted {deleted} duplicate readings");
+
+    Ok(())
+}
@@ -0,0 +1,40 @@
+//! Issues a new secret and signing key to stations, for stations registered before secrets
+//! existed or to rotate leaked ones. The previous ones stop working immediately.
+//!
+//! Usage: `cargo run --bin station_secret <token>...` or, to issue a secret to every
+//! station that has none, `cargo run --bin station_secret --missing`
+//!
+//! Prints `<token> <secret> <signing key>` per station, they can't be recovered afterwards.
+
+use actix_web::web::Data;
+use anyhow::bail;
+use auspex::{
+    config::{Config, Settings},
+    repository::db::DBRepository,
+    services::station_service::StationService,
+};
+
+#[actix_web::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let missing = args.iter().any(|arg| arg == "--missing");
+    let mut tokens: Vec<String> = args.into_iter().filter(|arg| arg != "--missing").collect();
+
+    let config = Config::new(Settings::load()?).await?;
+    let db = Data::new(DBRepository::new(config));
+    let service = StationService::new(&db);
+
+    if missing {
+        tokens.extend(service.get_stations_without_secret().await?);
+    }
+    if tokens.is_empty() {
+        bail!("usage: station_secret <token>... | --missing");
+    }
+
+    for token in tokens {
+        let credentials = service.rotate_secret(&token).await?;
+        println!("{token} {} {}", credentials.secret, credentials.signing_key);
+    }
+
+    Ok(())
+}
@@ -1,19 +1,821 @@
+use std::{fmt::Display, path::Path, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
 
+use crate::events::EventBus;
+
+/// Used when `AUSPEX_CONFIG` isn't set, it's fine if this file doesn't exist
+const DEFAULT_CONFIG_FILE: &str = "auspex.toml";
+
 #[derive(Clone)]
 pub struct Config {
     pub pool: Pool<Postgres>,
+    pub settings: Settings,
+    pub events: EventBus,
+}
+
+/// Everything that can be configured, read from an optional TOML file and then overridden
+/// by `AUSPEX_*` environment variables (or a `.env` file). See `auspex.example.toml`
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub log_level: String,
+    pub server: ServerSettings,
+    pub database: DatabaseSettings,
+    pub cors: CorsSettings,
+    pub windows: WindowSettings,
+    pub pagination: PaginationSettings,
+    pub events: EventSettings,
+    pub webhooks: WebhookSettings,
+    pub auth: AuthSettings,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    /// Defaults to the number of physical CPU cores
+    pub workers: Option<usize>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSettings {
+    /// Falls back to `DATABASE_URL`
+    pub url: Option<String>,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout_seconds: u64,
+    pub idle_timeout_seconds: Option<u64>,
+    /// Apply pending migrations at startup
+    pub run_migrations: bool,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsSettings {
+    /// `*` allows every origin
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSettings {
+    /// Stations that were online within this many minutes are considered active
+    pub active_station_minutes: i64,
+    /// Window of `/reading/all/past_hour`
+    pub past_hours: i64,
+    /// Window of `/reading/all/past_minutes`
+    pub past_minutes: i64,
+    /// How far the timestamp of signed payloads and readings may be ahead of (or, for
+    /// signatures, behind) the server clock
+    pub max_clock_skew_seconds: i64,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaginationSettings {
+    /// Page size of reading listings when the request doesn't set `limit`
+    pub default_page_size: i64,
+    /// Larger requested page sizes are reduced to this
+    pub max_page_size: i64,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventSettings {
+    /// Share new readings and station status changes with the other instances through
+    /// Postgres LISTEN/NOTIFY instead of only within this process
+    pub postgres_notify: bool,
+    /// Readings a live subscriber may fall behind before it misses some
+    pub buffer: usize,
+    /// Interval of the keep-alive comments on idle event streams and of the pings on live
+    /// sockets
+    pub keep_alive_seconds: u64,
+    /// Interval of the checks for stations that went offline
+    pub status_check_seconds: u64,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookSettings {
+    /// Interval of the checks for webhook deliveries that are due
+    pub poll_seconds: u64,
+    /// Deliveries sent at once by every instance
+    pub batch_size: i64,
+    pub timeout_seconds: u64,
+    /// Deliveries that failed this many times are given up
+    pub max_attempts: i32,
+    /// Wait before the first retry, doubled after every further failure
+    pub backoff_seconds: i64,
+    /// Hosts webhooks may be sent to even though they resolve to a loopback, private or
+    /// link-local address. Every other such host is refused
+    pub allowed_hosts: Vec<String>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSettings {
+    /// Server side key the signing keys of the stations are derived from, so the database
+    /// alone isn't enough to forge signed payloads. Changing it invalidates every signing key
+    pub signing_secret: Option<String>,
+    /// Bearer token of the admin endpoints: changes to station groups and alert rules, and
+    /// webhooks. They refuse every request while it isn't set
+    pub admin_token: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            log_level: "info,sqlx=warn".into(),
+            server: ServerSettings::default(),
+            database: DatabaseSettings::default(),
+            cors: CorsSettings::default(),
+            windows: WindowSettings::default(),
+            pagination: PaginationSettings::default(),
+            events: EventSettings::default(),
+            webhooks: WebhookSettings::default(),
+            auth: AuthSettings::default(),
+        }
+    }
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            host: "0.0.0.0".into(),
+            port: 80,
+            workers: None,
+        }
+    }
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        DatabaseSettings {
+            url: None,
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout_seconds: 30,
+            idle_timeout_seconds: Some(600),
+            run_migrations: false,
+        }
+    }
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        CorsSettings {
+            allowed_origins: vec!["*".into()],
+        }
+    }
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        WindowSettings {
+            active_station_minutes: 60,
+            past_hours: 1,
+            past_minutes: 5,
+            max_clock_skew_seconds: 5 * 60,
+        }
+    }
+}
+
+impl Default for PaginationSettings {
+    fn default() -> Self {
+        PaginationSettings {
+            default_page_size: 100,
+            max_page_size: 1000,
+        }
+    }
+}
+
+impl Default for EventSettings {
+    fn default() -> Self {
+        EventSettings {
+            postgres_notify: false,
+            buffer: 1024,
+            keep_alive_seconds: 15,
+            status_check_seconds: 30,
+        }
+    }
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        WebhookSettings {
+            poll_seconds: 5,
+            batch_size: 20,
+            timeout_seconds: 10,
+            max_attempts: 8,
+            backoff_seconds: 30,
+            allowed_hosts: vec![],
+        }
+    }
+}
+
+impl Settings {
+    /// Read the settings from the file in `AUSPEX_CONFIG` (or `auspex.toml`), apply the
+    /// environment overrides and validate the result
+    pub fn load() -> Result<Self> {
+        let path = dotenvy::var("AUSPEX_CONFIG").ok();
+        let mut settings = match &path {
+            Some(path) => Self::from_file(Path::new(path))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Settings::default(),
+        };
+
+        settings.apply_env()?;
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+
+        toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        env_override(&mut self.log_level, "AUSPEX_LOG_LEVEL")?;
+
+        env_override(&mut self.server.host, "AUSPEX_SERVER_HOST")?;
+        env_override(&mut self.server.port, "AUSPEX_SERVER_PORT")?;
+        env_override_option(&mut self.server.workers, "AUSPEX_SERVER_WORKERS")?;
+
+        env_override_option(&mut self.database.url, "AUSPEX_DATABASE_URL")?;
+        if self.database.url.is_none() {
+            env_override_option(&mut self.database.url, "DATABASE_URL")?;
+        }
+        env_override(
+            &mut self.database.max_connections,
+            "AUSPEX_DATABASE_MAX_CONNECTIONS",
+        )?;
+        env_override(
+            &mut self.database.min_connections,
+            "AUSPEX_DATABASE_MIN_CONNECTIONS",
+        )?;
+        env_override(
+            &mut self.database.acquire_timeout_seconds,
+            "AUSPEX_DATABASE_ACQUIRE_TIMEOUT_SECONDS",
+        )?;
+        env_override_option(
+            &mut self.database.idle_timeout_seconds,
+            "AUSPEX_DATABASE_IDLE_TIMEOUT_SECONDS",
+        )?;
+        env_override(
+            &mut self.database.run_migrations,
+            "AUSPEX_DATABASE_RUN_MIGRATIONS",
+        )?;
+
+        if let Ok(origins) = dotenvy::var("AUSPEX_CORS_ALLOWED_ORIGINS") {
+            self.cors.allowed_origins = origins
+                .split(',')
+                .map(|o| o.trim().to_string())
+                .filter(|o| !o.is_empty())
+                .collect();
+        }
+
+        env_override(
+            &mut self.windows.active_station_minutes,
+            "AUSPEX_WINDOWS_ACTIVE_STATION_MINUTES",
+        )?;
+        env_override(&mut self.windows.past_hours, "AUSPEX_WINDOWS_PAST_HOURS")?;
+        env_override(
+            &mut self.windows.past_minutes,
+            "AUSPEX_WINDOWS_PAST_MINUTES",
+        )?;
+        env_override(
+            &mut self.windows.max_clock_skew_seconds,
+            "AUSPEX_WINDOWS_MAX_CLOCK_SKEW_SECONDS",
+        )?;
+
+        env_override(
+            &mut self.pagination.default_page_size,
+            "AUSPEX_PAGINATION_DEFAULT_PAGE_SIZE",
+        )?;
+        env_override(
+            &mut self.pagination.max_page_size,
+            "AUSPEX_PAGINATION_MAX_PAGE_SIZE",
+        )?;
+
+        env_override(
+            &mut self.events.postgres_notify,
+            "AUSPEX_EVENTS_POSTGRES_NOTIFY",
+        )?;
+        env_override(&mut self.events.buffer, "AUSPEX_EVENTS_BUFFER")?;
+        env_override(
+            &mut self.events.keep_alive_seconds,
+            "AUSPEX_EVENTS_KEEP_ALIVE_SECONDS",
+        )?;
+        env_override(
+            &mut self.events.status_check_seconds,
+            "AUSPEX_EVENTS_STATUS_CHECK_SECONDS",
+        )?;
+
+        env_override(
+            &mut self.webhooks.poll_seconds,
+            "AUSPEX_WEBHOOKS_POLL_SECONDS",
+        )?;
+        env_override(&mut self.webhooks.batch_size, "AUSPEX_WEBHOOKS_BATCH_SIZE")?;
+        env_override(
+            &mut self.webhooks.timeout_seconds,
+            "AUSPEX_WEBHOOKS_TIMEOUT_SECONDS",
+        )?;
+        env_override(
+            &mut self.webhooks.max_attempts,
+            "AUSPEX_WEBHOOKS_MAX_ATTEMPTS",
+        )?;
+        env_override(
+            &mut self.webhooks.backoff_seconds,
+            "AUSPEX_WEBHOOKS_BACKOFF_SECONDS",
+        )?;
+        if let Ok(hosts) = dotenvy::var("AUSPEX_WEBHOOKS_ALLOWED_HOSTS") {
+            self.webhooks.allowed_hosts = hosts
+                .split(',')
+                .map(|h| h.trim().to_string())
+                .filter(|h| !h.is_empty())
+                .collect();
+        }
+
+        env_override_option(&mut self.auth.signing_secret, "AUSPEX_AUTH_SIGNING_SECRET")?;
+        env_override_option(&mut self.auth.admin_token, "AUSPEX_AUTH_ADMIN_TOKEN")?;
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.log_level.trim().is_empty() {
+            bail!("log_level must not be empty, e.g. \"info\" or \"auspex=debug,sqlx=warn\"");
+        }
+        if self.server.host.trim().is_empty() {
+            bail!("server.host must not be empty");
+        }
+        if self.server.workers == Some(0) {
+            bail!("server.workers must be at least 1");
+        }
+        if self.database.url.is_none() {
+            bail!("no database configured, set database.url or DATABASE_URL");
+        }
+        if self.database.max_connections == 0 {
+            bail!("database.max_connections must be at least 1");
+        }
+        if self.database.min_connections > self.database.max_connections {
+            bail!(
+                "database.min_connections ({}) must not exceed database.max_connections ({})",
+                self.database.min_connections,
+                self.database.max_connections
+            );
+        }
+        if self.database.acquire_timeout_seconds == 0 {
+            bail!("database.acquire_timeout_seconds must be at least 1");
+        }
+        if self.cors.allowed_origins.is_empty() {
+            bail!("cors.allowed_origins must not be empty, use [\"*\"] to allow every origin");
+        }
+        for origin in &self.cors.allowed_origins {
+            if origin != "*" && !(origin.starts_with("http://") || origin.starts_with("https://")) {
+                bail!("cors.allowed_origins: {origin:?} must be \"*\" or start with http(s)://");
+            }
+        }
+
+        let windows = [
+            (
+                "active_station_minutes",
+                self.windows.active_station_minutes,
+            ),
+            ("past_hours", self.windows.past_hours),
+            ("past_minutes", self.windows.past_minutes),
+            (
+                "max_clock_skew_seconds",
+                self.windows.max_clock_skew_seconds,
+            ),
+        ];
+        for (name, value) in windows {
+            if value <= 0 {
+                bail!("windows.{name} must be positive, got {value}");
+            }
+        }
+
+        let pagination = &self.pagination;
+        if pagination.max_page_size < 1 {
+            bail!("pagination.max_page_size must be at least 1");
+        }
+        if !(1..=pagination.max_page_size).contains(&pagination.default_page_size) {
+            bail!(
+                "pagination.default_page_size must be between 1 and pagination.max_page_size ({})",
+                pagination.max_page_size
+            );
+        }
+        if self.events.buffer == 0 {
+            bail!("events.buffer must be at least 1");
+        }
+        if self.events.keep_alive_seconds == 0 {
+            bail!("events.keep_alive_seconds must be at least 1");
+        }
+        if self.events.status_check_seconds == 0 {
+            bail!("events.status_check_seconds must be at least 1");
+        }
+        let webhooks = &self.webhooks;
+        if webhooks.poll_seconds == 0 {
+            bail!("webhooks.poll_seconds must be at least 1");
+        }
+        if webhooks.batch_size < 1 {
+            bail!("webhooks.batch_size must be at least 1");
+        }
+        if webhooks.timeout_seconds == 0 {
+            bail!("webhooks.timeout_seconds must be at least 1");
+        }
+        if webhooks.max_attempts < 1 {
+            bail!("webhooks.max_attempts must be at least 1");
+        }
+        if webhooks.backoff_seconds < 1 {
+            bail!("webhooks.backoff_seconds must be at least 1");
+        }
+        match &self.auth.signing_secret {
+            None => bail!("no signing secret configured, set auth.signing_secret"),
+            Some(secret) if secret.len() < 32 => {
+                bail!("auth.signing_secret must be at least 32 characters long")
+            }
+            Some(_) => {}
+        }
+        if matches!(&self.auth.admin_token, Some(token) if token.len() < 32) {
+            bail!("auth.admin_token must be at least 32 characters long");
+        }
+
+        Ok(())
+    }
 }
 
 impl Config {
-    pub async fn new() -> Self {
-        let database_url = dotenvy::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    pub async fn new(settings: Settings) -> Result<Self> {
+        let database = &settings.database;
+        let database_url = database
+            .url
+            .as_deref()
+            .context("no database configured, set database.url or DATABASE_URL")?;
+
         let pool = PgPoolOptions::new()
-            .max_connections(5)
-            .connect(&database_url)
+            .max_connections(database.max_connections)
+            .min_connections(database.min_connections)
+            .acquire_timeout(Duration::from_secs(database.acquire_timeout_seconds))
+            .idle_timeout(database.idle_timeout_seconds.map(Duration::from_secs))
+            .connect(database_url)
             .await
-            .expect("Failed to create database pool");
+            .context("failed to connect to the database")?;
+
+        let events = EventBus::new(settings.events.buffer);
+
+        Ok(Config {
+            pool,
+            settings,
+            events,
+        })
+    }
+}
+
+fn env_override<T>(value: &mut T, name: &str) -> Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(raw) = dotenvy::var(name) {
+        *value = raw
+            .parse()
+            .map_err(|e| anyhow!("{name}: invalid value {raw:?}: {e}"))?;
+    }
+
+    Ok(())
+}
+
+fn env_override_option<T>(value: &mut Option<T>, name: &str) -> Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(raw) = dotenvy::var(name) {
+        *value = Some(
+            raw.parse()
+                .map_err(|e| anyhow!("{name}: invalid value {raw:?}: {e}"))?,
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Change = Box<dyn FnOnce(&mut Settings)>;
+
+    fn valid() -> Settings {
+        let mut settings = Settings::default();
+        settings.database.url = Some("postgres://localhost/auspex".into());
+        settings.auth.signing_secret = Some("s".repeat(32));
+        settings
+    }
+
+    fn invalid(change: impl FnOnce(&mut Settings)) -> String {
+        let mut settings = valid();
+        change(&mut settings);
+
+        settings
+            .validate()
+            .expect_err("settings should be invalid")
+            .to_string()
+    }
+
+    #[test]
+    fn defaults_are_valid_once_the_database_and_signing_secret_are_set() {
+        assert!(valid().validate().is_ok());
+        assert!(invalid(|s| s.database.url = None).contains("no database configured"));
+        assert!(invalid(|s| s.auth.signing_secret = None).contains("no signing secret"));
+    }
+
+    #[test]
+    fn signing_secret_must_be_long_enough() {
+        // The signing keys are derived with it, a shorter one is refused at startup
+        let error = invalid(|s| s.auth.signing_secret = Some("s".repeat(31)));
+        assert!(error.contains("auth.signing_secret"), "{error}");
+
+        let error = invalid(|s| s.auth.signing_secret = Some(String::new()));
+        assert!(error.contains("auth.signing_secret"), "{error}");
+    }
+
+    #[test]
+    fn admin_token_is_optional_but_must_be_long_enough() {
+        let mut settings = valid();
+        settings.auth.admin_token = Some("a".repeat(32));
+        assert!(settings.validate().is_ok());
+
+        let error = invalid(|s| s.auth.admin_token = Some("a".repeat(31)));
+        assert!(error.contains("auth.admin_token"), "{error}");
+    }
+
+    #[test]
+    fn server_and_database_rules() {
+        let cases: Vec<(&str, Change)> = vec![
+            ("log_level", Box::new(|s| s.log_level = " ".into())),
+            ("server.host", Box::new(|s| s.server.host = "".into())),
+            ("server.workers", Box::new(|s| s.server.workers = Some(0))),
+            (
+                "database.max_connections",
+                Box::new(|s| s.database.max_connections = 0),
+            ),
+            (
+                "database.min_connections",
+                Box::new(|s| s.database.min_connections = 6),
+            ),
+            (
+                "database.acquire_timeout_seconds",
+                Box::new(|s| s.database.acquire_timeout_seconds = 0),
+            ),
+        ];
+
+        for (name, change) in cases {
+            let error = invalid(change);
+            assert!(error.contains(name), "{name}: {error}");
+        }
+    }
+
+    #[test]
+    fn cors_origins_must_be_a_wildcard_or_http_origins() {
+        let mut settings = valid();
+        settings.cors.allowed_origins =
+            vec!["https://example.com".into(), "http://localhost:8080".into()];
+        assert!(settings.validate().is_ok());
+
+        let error = invalid(|s| s.cors.allowed_origins = vec![]);
+        assert!(error.contains("cors.allowed_origins"), "{error}");
+
+        let error = invalid(|s| s.cors.allowed_origins = vec!["example.com".into()]);
+        assert!(error.contains("\"example.com\""), "{error}");
+    }
+
+    #[test]
+    fn windows_must_be_positive() {
+        let cases: Vec<(&str, Change)> = vec![
+            (
+                "windows.active_station_minutes",
+                Box::new(|s| s.windows.active_station_minutes = 0),
+            ),
+            (
+                "windows.past_hours",
+                Box::new(|s| s.windows.past_hours = -1),
+            ),
+            (
+                "windows.past_minutes",
+                Box::new(|s| s.windows.past_minutes = 0),
+            ),
+            (
+                "windows.max_clock_skew_seconds",
+                Box::new(|s| s.windows.max_clock_skew_seconds = 0),
+            ),
+        ];
+
+        for (name, change) in cases {
+            let error = invalid(change);
+            assert!(error.contains(name), "{name}: {error}");
+        }
+    }
+
+    #[test]
+    fn pagination_rules() {
+        let error = invalid(|s| s.pagination.max_page_size = 0);
+        assert!(error.contains("pagination.max_page_size"), "{error}");
+
+        let error = invalid(|s| s.pagination.default_page_size = 0);
+        assert!(error.contains("pagination.default_page_size"), "{error}");
+
+        let error = invalid(|s| s.pagination.default_page_size = 1001);
+        assert!(error.contains("pagination.default_page_size"), "{error}");
+    }
+
+    #[test]
+    fn event_and_webhook_minimums() {
+        let cases: Vec<(&str, Change)> = vec![
+            ("events.buffer", Box::new(|s| s.events.buffer = 0)),
+            (
+                "events.keep_alive_seconds",
+                Box::new(|s| s.events.keep_alive_seconds = 0),
+            ),
+            (
+                "events.status_check_seconds",
+                Box::new(|s| s.events.status_check_seconds = 0),
+            ),
+            (
+                "webhooks.poll_seconds",
+                Box::new(|s| s.webhooks.poll_seconds = 0),
+            ),
+            (
+                "webhooks.batch_size",
+                Box::new(|s| s.webhooks.batch_size = 0),
+            ),
+            (
+                "webhooks.timeout_seconds",
+                Box::new(|s| s.webhooks.timeout_seconds = 0),
+            ),
+            (
+                "webhooks.max_attempts",
+                Box::new(|s| s.webhooks.max_attempts = 0),
+            ),
+            (
+                "webhooks.backoff_seconds",
+                Box::new(|s| s.webhooks.backoff_seconds = 0),
+            ),
+        ];
+
+        for (name, change) in cases {
+            let error = invalid(change);
+            assert!(error.contains(name), "{name}: {error}");
+        }
+    }
+
+    #[test]
+    fn files_set_what_they_name_and_keep_the_other_defaults() {
+        let settings: Settings = toml::from_str(
+            r#"
+            log_level = "debug"
+
+            [server]
+            port = 8080
+            workers = 2
+
+            [database]
+            url = "postgres://localhost/auspex"
+            idle_timeout_seconds = 60
+
+            [webhooks]
+            allowed_hosts = ["hooks.internal"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.log_level, "debug");
+        assert_eq!(settings.server.host, "0.0.0.0");
+        assert_eq!(settings.server.port, 8080);
+        assert_eq!(settings.server.workers, Some(2));
+        assert_eq!(settings.database.max_connections, 5);
+        assert_eq!(settings.database.idle_timeout_seconds, Some(60));
+        assert_eq!(settings.webhooks.allowed_hosts, ["hooks.internal"]);
+        assert_eq!(settings.windows.max_clock_skew_seconds, 300);
+    }
+
+    #[test]
+    fn files_with_unknown_options_are_refused() {
+        assert!(toml::from_str::<Settings>("[server]\nprot = 8080").is_err());
+        assert!(toml::from_str::<Settings>("[servers]\nport = 8080").is_err());
+        assert!(toml::from_str::<Settings>("[server]\nport = \"eighty\"").is_err());
+    }
+
+    #[test]
+    fn the_example_file_holds_the_defaults() {
+        let example: Settings = toml::from_str(include_str!("../auspex.example.toml")).unwrap();
+        let defaults = Settings::default();
+
+        assert_eq!(example.log_level, defaults.log_level);
+        assert_eq!(example.server.port, defaults.server.port);
+        assert_eq!(
+            example.database.max_connections,
+            defaults.database.max_connections
+        );
+        assert_eq!(
+            example.windows.max_clock_skew_seconds,
+            defaults.windows.max_clock_skew_seconds
+        );
+        assert_eq!(
+            example.pagination.max_page_size,
+            defaults.pagination.max_page_size
+        );
+        assert_eq!(
+            example.webhooks.max_attempts,
+            defaults.webhooks.max_attempts
+        );
+    }
+
+    // The only test that touches the environment, so the overrides can't race
+    #[test]
+    fn environment_overrides() {
+        let vars = [
+            ("AUSPEX_LOG_LEVEL", "warn"),
+            ("AUSPEX_SERVER_PORT", "8088"),
+            ("AUSPEX_SERVER_WORKERS", "3"),
+            ("AUSPEX_DATABASE_URL", "postgres://db/auspex"),
+            ("AUSPEX_DATABASE_RUN_MIGRATIONS", "true"),
+            ("AUSPEX_DATABASE_IDLE_TIMEOUT_SECONDS", "120"),
+            (
+                "AUSPEX_CORS_ALLOWED_ORIGINS",
+                "https://a.example, ,https://b.example",
+            ),
+            ("AUSPEX_WINDOWS_MAX_CLOCK_SKEW_SECONDS", "60"),
+            ("AUSPEX_PAGINATION_MAX_PAGE_SIZE", "50"),
+            ("AUSPEX_EVENTS_POSTGRES_NOTIFY", "true"),
+            ("AUSPEX_WEBHOOKS_ALLOWED_HOSTS", "hooks.internal,localhost"),
+            (
+                "AUSPEX_AUTH_SIGNING_SECRET",
+                "0123456789abcdef0123456789abcdef",
+            ),
+            (
+                "AUSPEX_AUTH_ADMIN_TOKEN",
+                "adminadminadminadminadminadminadmin",
+            ),
+        ];
+        for (name, value) in vars {
+            std::env::set_var(name, value);
+        }
+
+        let mut settings = Settings::default();
+        let applied = settings.apply_env();
+
+        std::env::set_var("AUSPEX_SERVER_PORT", "eighty");
+        let invalid = Settings::default().apply_env();
+
+        for (name, _) in vars {
+            std::env::remove_var(name);
+        }
+
+        // Without AUSPEX_DATABASE_URL the url is taken from DATABASE_URL
+        let mut fallback = Settings::default();
+        fallback.apply_env().unwrap();
+        assert_eq!(fallback.database.url, dotenvy::var("DATABASE_URL").ok());
+
+        applied.unwrap();
+        assert_eq!(settings.log_level, "warn");
+        assert_eq!(settings.server.port, 8088);
+        assert_eq!(settings.server.workers, Some(3));
+        assert_eq!(
+            settings.database.url.as_deref(),
+            Some("postgres://db/auspex")
+        );
+        assert!(settings.database.run_migrations);
+        assert_eq!(settings.database.idle_timeout_seconds, Some(120));
+        assert_eq!(
+            settings.cors.allowed_origins,
+            ["https://a.example", "https://b.example"]
+        );
+        assert_eq!(settings.windows.max_clock_skew_seconds, 60);
+        assert_eq!(settings.pagination.max_page_size, 50);
+        assert!(settings.events.postgres_notify);
+        assert_eq!(
+            settings.webhooks.allowed_hosts,
+            ["hooks.internal", "localhost"]
+        );
+        assert!(
+            settings.validate().is_err(),
+            "the default page size is above 50"
+        );
 
-        Config { pool }
+        let error = invalid.unwrap_err().to_string();
+        assert!(error.contains("AUSPEX_SERVER_PORT"), "{error}");
+        assert!(error.contains("eighty"), "{error}");
     }
 }
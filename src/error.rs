@@ -0,0 +1,188 @@
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    HttpResponse, ResponseError,
+};
+use log::error;
+use serde::Serialize;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("storage error: {0}")]
+    Storage(#[source] sqlx::Error),
+}
+
+/// JSON body of an error response, following RFC 7807
+#[derive(Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let detail = match self {
+            Error::Storage(e) => {
+                error!("{e}");
+                "the request could not be completed due to a storage error".to_string()
+            }
+            _ => self.to_string(),
+        };
+
+        let problem = Problem {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail,
+        };
+
+        HttpResponse::build(status)
+            .insert_header(ContentType(
+                "application/problem+json".parse().expect("valid mime type"),
+            ))
+            .json(problem)
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::RowNotFound => Error::NotFound("resource not found".into()),
+            sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+                Error::Conflict("resource already exists".into())
+            }
+            _ => Error::Storage(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Validation(e.to_string())
+    }
+}
+
+pub trait OptionalExt<T> {
+    /// Turn a `NotFound` error into `None`, keeping every other error
+    fn optional(self) -> Result<Option<T>>;
+}
+
+impl<T> OptionalExt<T> for Result<T> {
+    fn optional(self) -> Result<Option<T>> {
+        match self {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, error::Error as StdError, fmt};
+
+    use sqlx::error::DatabaseError;
+
+    use super::*;
+
+    /// A database error with just a SQLSTATE code
+    #[derive(Debug)]
+    struct SqlState(&'static str);
+
+    impl fmt::Display for SqlState {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "sqlstate {}", self.0)
+        }
+    }
+
+    impl StdError for SqlState {}
+
+    impl DatabaseError for SqlState {
+        fn message(&self) -> &str {
+            "database error"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(self.0.into())
+        }
+
+        fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    fn database_error(code: &'static str) -> Error {
+        sqlx::Error::Database(Box::new(SqlState(code))).into()
+    }
+
+    #[test]
+    fn unique_violation_is_a_conflict() {
+        let e = database_error("23505");
+
+        assert!(matches!(e, Error::Conflict(_)));
+        assert_eq!(e.status_code(), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn other_database_errors_are_storage_errors() {
+        for code in ["23503", "40001", "57P01"] {
+            let e = database_error(code);
+
+            assert!(matches!(e, Error::Storage(_)), "{code}");
+            assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    #[test]
+    fn row_not_found_is_not_found() {
+        let e = Error::from(sqlx::Error::RowNotFound);
+
+        assert!(matches!(e, Error::NotFound(_)));
+        assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn optional_only_swallows_not_found() {
+        assert_eq!(Ok::<_, Error>(1).optional().unwrap(), Some(1));
+        assert_eq!(
+            Err::<i32, _>(Error::NotFound("x".into()))
+                .optional()
+                .unwrap(),
+            None
+        );
+        assert!(Err::<i32, _>(Error::Conflict("x".into()))
+            .optional()
+            .is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Live feed of what happens to the stations: new readings, online/offline transitions and
+//! alerts that fire or resolve.
+//! Events are broadcast to the subscribers of this process, or, with
+//! `events.postgres_notify`, sent through Postgres NOTIFY and relayed to the subscribers of
+//! every instance that shares the database
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, Pool, Postgres};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::models::{alert::Alert, reading::Reading};
+
+/// Postgres channel the events of every instance are sent to
+pub const NOTIFY_CHANNEL: &str = "auspex_events";
+
+/// Wait before listening again after the listening connection failed
+const RELISTEN_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Reading(Arc<ReadingEvent>),
+    StationStatus(Arc<StationStatusEvent>),
+    Alert(Arc<Alert>),
+}
+
+/// A reading as it was stored, together with the token of its station
+#[derive(Serialize, Deserialize)]
+pub struct ReadingEvent {
+    pub station: String,
+    #[serde(flatten)]
+    pub reading: Reading,
+}
+
+/// A station came online after being inactive, or became inactive
+#[derive(Serialize, Deserialize)]
+pub struct StationStatusEvent {
+    pub station: String,
+    pub online: bool,
+    #[serde(with = "ts_milliseconds")]
+    pub last_online: DateTime<Utc>,
+}
+
+impl Event {
+    pub fn station(&self) -> &str {
+        match self {
+            Event::Reading(event) => &event.station,
+            Event::StationStatus(event) => &event.station,
+            Event::Alert(alert) => &alert.station,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    /// Subscribers that fall behind by more than `capacity` events miss the oldest ones
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        EventBus { sender }
+    }
+
+    /// Hand the event to the subscribers of this process
+    pub fn send(&self, event: Event) {
+        // Having no subscribers at all is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Every event from now on
+    pub fn subscribe(&self) -> Subscription {
+        Subscription {
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    /// The readings stored from now on, of one station or of every station
+    pub fn subscribe_readings(&self, station_id: Option<i32>) -> ReadingSubscription {
+        ReadingSubscription {
+            events: self.subscribe(),
+            station_id,
+        }
+    }
+
+    /// Relay the events sent to `NOTIFY_CHANNEL` by every instance to the subscribers of
+    /// this process. Runs until the process ends, listening again when the connection fails
+    pub async fn relay_notifications(self, pool: Pool<Postgres>) {
+        loop {
+            if let Err(e) = self.relay(&pool).await {
+                error!("listening for events on {NOTIFY_CHANNEL} failed: {e}");
+            }
+            actix_web::rt::time::sleep(RELISTEN_DELAY).await;
+        }
+    }
+
+    async fn relay(&self, pool: &Pool<Postgres>) -> sqlx::Result<()> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(NOTIFY_CHANNEL).await?;
+
+        loop {
+            let notification = listener.recv().await?;
+            match serde_json::from_str(notification.payload()) {
+                Ok(event) => self.send(event),
+                Err(e) => warn!("ignoring invalid event notification: {e}"),
+            }
+        }
+    }
+}
+
+pub struct Subscription {
+    receiver: broadcast::Receiver<Event>,
+}
+
+impl Subscription {
+    /// The next event, None once the bus is gone. Events missed by falling behind are
+    /// skipped
+    pub async fn next(&mut self) -> Option<Event> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("event subscriber fell behind, skipped {missed} events")
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+pub struct ReadingSubscription {
+    events: Subscription,
+    station_id: Option<i32>,
+}
+
+impl ReadingSubscription {
+    /// The next reading of the subscribed station(s), None once the bus is gone
+    pub async fn next(&mut self) -> Option<Arc<ReadingEvent>> {
+        loop {
+            if let Event::Reading(event) = self.events.next().await? {
+                if self
+                    .station_id
+                    .is_none_or(|id| id == event.reading.station_id)
+                {
+                    return Some(event);
+                }
+            }
+        }
+    }
+}
@@ -2,6 +2,12 @@
 
 pub mod api;
 pub mod config;
+pub mod error;
+pub mod events;
+pub mod metrics;
 pub mod models;
 pub mod repository;
 pub mod services;
+
+#[cfg(test)]
+mod testing;
@@ -1,22 +1,86 @@
+use std::time::Instant;
+
 use actix_cors::Cors;
-use actix_web::{middleware::Logger, web::Data, App, HttpServer};
+use actix_web::{
+    dev::Service,
+    middleware::Logger,
+    web::{Data, JsonConfig, PathConfig, QueryConfig},
+    App, HttpServer,
+};
 use auspex::api::reading::{
-    add_reading, get_average_reading, get_latest_reading, get_latest_readings,
-    get_past_hour_readings, get_past_minutes_readings, get_readings_between,
+    add_reading, add_readings, compare_readings, export_aggregated_readings,
+    export_readings_between, get_aggregated_readings, get_aqi, get_average_reading,
+    get_latest_reading, get_latest_readings, get_past_hour_readings, get_past_minutes_readings,
+    get_readings_between,
+};
+use auspex::api::station::{
+    add_station, get_active_stations, get_active_stations_geojson, get_nearest_stations,
+    get_station, get_stations_in_bbox, get_stations_within, update_location, update_station,
+};
+use auspex::api::group::{get_group, put_group};
+use auspex::api::health::{get_health, get_readiness, get_version};
+use auspex::api::status::get_status;
+use auspex::api::metrics::get_metrics;
+use auspex::api::heatmap::{get_heatmap, get_heatmap_contours};
+use auspex::api::stream::{stream_readings, stream_station_readings};
+use auspex::api::live::live;
+use auspex::api::alert::{
+    add_alert_rule, delete_alert_rule, get_alert_rule, get_alert_rules, get_alerts,
+    update_alert_rule,
 };
-use auspex::api::station::{add_station, get_station, update_location, update_station, get_active_stations};
-use auspex::{config::Config, repository::db::DBRepository};
+use auspex::api::webhook::{
+    add_webhook, delete_webhook, get_webhook, get_webhook_deliveries, get_webhooks,
+    update_webhook,
+};
+use auspex::{
+    config::{Config, CorsSettings, Settings},
+    error::Error,
+    metrics,
+    repository::db::DBRepository,
+    services::{
+        presence_service::PresenceService, schema_service::SchemaService,
+        webhook_service::WebhookService,
+    },
+};
+
+fn cors(settings: &CorsSettings) -> Cors {
+    if settings.allowed_origins.iter().any(|origin| origin == "*") {
+        return Cors::permissive();
+    }
+
+    settings
+        .allowed_origins
+        .iter()
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+        .allow_any_method()
+        .allow_any_header()
+}
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    std::env::set_var("RUST_LOG", "debug");
+async fn main() -> anyhow::Result<()> {
     std::env::set_var("RUST_BACKTRACE", "1");
-    env_logger::init();
+    let settings = Settings::load()?;
+    env_logger::Builder::new()
+        .parse_filters(&settings.log_level)
+        .init();
+
+    let config = Config::new(settings.clone()).await?;
 
-    let config = Config::new().await;
+    let db = Data::new(DBRepository::new(config.clone()));
+    SchemaService::new(&db)
+        .prepare(settings.database.run_migrations)
+        .await?;
 
-    HttpServer::new(move || {
-        let cors = Cors::permissive();
+    if settings.events.postgres_notify {
+        actix_web::rt::spawn(config.events.clone().relay_notifications(config.pool.clone()));
+    }
+    let presence_db = db.clone();
+    actix_web::rt::spawn(async move { PresenceService::new(&presence_db).watch().await });
+    let webhook_db = db.clone();
+    actix_web::rt::spawn(async move { WebhookService::new(&webhook_db).deliver().await });
+
+    let mut server = HttpServer::new(move || {
+        let cors = cors(&config.settings.cors);
         let logger = Logger::default();
         let db_repo = DBRepository::new(config.clone());
         let db_data = Data::new(db_repo);
@@ -24,21 +88,85 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .wrap(cors)
             .wrap(logger)
+            .wrap_fn(|req, srv| {
+                let start = Instant::now();
+                let method = req.method().to_string();
+                let route = req
+                    .match_pattern()
+                    .unwrap_or_else(|| metrics::UNMATCHED_ROUTE.into());
+
+                let res = srv.call(req);
+                async move {
+                    let res = res.await;
+                    let status = match &res {
+                        Ok(res) => res.status(),
+                        Err(e) => e.as_response_error().status_code(),
+                    };
+                    metrics::observe_request(&method, &route, status.as_u16(), start.elapsed());
+
+                    res
+                }
+            })
             .app_data(db_data)
+            .app_data(JsonConfig::default().error_handler(|e, _| Error::Validation(e.to_string()).into()))
+            .app_data(PathConfig::default().error_handler(|e, _| Error::Validation(e.to_string()).into()))
+            .app_data(QueryConfig::default().error_handler(|e, _| Error::Validation(e.to_string()).into()))
             .service(add_station)
             .service(get_station)
             .service(get_active_stations)
+            .service(get_active_stations_geojson)
+            .service(get_stations_in_bbox)
+            .service(get_stations_within)
+            .service(get_nearest_stations)
             .service(update_station)
             .service(update_location)
             .service(get_latest_reading)
             .service(get_latest_readings)
             .service(get_average_reading)
+            .service(get_aqi)
             .service(get_past_hour_readings)
             .service(get_past_minutes_readings)
             .service(get_readings_between)
+            .service(get_aggregated_readings)
+            .service(export_readings_between)
+            .service(export_aggregated_readings)
+            .service(compare_readings)
+            .service(stream_readings)
+            .service(stream_station_readings)
+            .service(live)
+            .service(get_heatmap)
+            .service(get_heatmap_contours)
+            .service(get_group)
+            .service(put_group)
+            .service(get_alert_rules)
+            .service(get_alert_rule)
+            .service(add_alert_rule)
+            .service(update_alert_rule)
+            .service(delete_alert_rule)
+            .service(get_alerts)
+            .service(get_webhooks)
+            .service(get_webhook)
+            .service(add_webhook)
+            .service(update_webhook)
+            .service(delete_webhook)
+            .service(get_webhook_deliveries)
             .service(add_reading)
-    })
-    .bind(("192.168.0.190", 80))?
-    .run()
-    .await
+            .service(add_readings)
+            .service(get_status)
+            .service(get_health)
+            .service(get_readiness)
+            .service(get_version)
+            .service(get_metrics)
+    });
+
+    if let Some(workers) = settings.server.workers {
+        server = server.workers(workers);
+    }
+
+    server
+        .bind((settings.server.host.as_str(), settings.server.port))?
+        .run()
+        .await?;
+
+    Ok(())
 }
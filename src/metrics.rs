@@ -0,0 +1,166 @@
+//! Prometheus metrics of the service and the station fleet, exposed at `/metrics`
+
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter_vec, register_int_gauge,
+    Encoder, GaugeVec, HistogramVec, IntCounterVec, IntGauge, TextEncoder,
+};
+
+use crate::repository::queries::station::ActiveStationReadingRecord;
+
+/// Label used for requests that didn't match any route, so unknown paths can't blow up
+/// the number of series
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "auspex_http_requests_total",
+        "Number of HTTP requests handled, per route and status",
+        &["method", "route", "status"]
+    )
+    .expect("valid metric")
+});
+
+static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "auspex_http_request_duration_seconds",
+        "Time spent handling HTTP requests, per route",
+        &["method", "route"]
+    )
+    .expect("valid metric")
+});
+
+static DB_POOL_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "auspex_db_pool_connections",
+        "Number of open database connections"
+    )
+    .expect("valid metric")
+});
+
+static DB_POOL_IDLE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "auspex_db_pool_idle_connections",
+        "Number of open database connections that are not in use"
+    )
+    .expect("valid metric")
+});
+
+static DB_POOL_MAX_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "auspex_db_pool_max_connections",
+        "Maximum number of database connections the pool may open"
+    )
+    .expect("valid metric")
+});
+
+static READINGS_INGESTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "auspex_readings_ingested_total",
+        "Number of new readings stored, per station, not counting duplicates",
+        &["station"]
+    )
+    .expect("valid metric")
+});
+
+static ACTIVE_STATIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "auspex_active_stations",
+        "Number of stations that were online recently"
+    )
+    .expect("valid metric")
+});
+
+static STATION_LATEST_PM25: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "auspex_station_latest_pm25",
+        "PM2.5 of the latest reading of each active station, in µg/m³",
+        &["station"]
+    )
+    .expect("valid metric")
+});
+
+static STATION_LATEST_CO2: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "auspex_station_latest_co2",
+        "CO2 of the latest reading of each active station, in ppm",
+        &["station"]
+    )
+    .expect("valid metric")
+});
+
+pub fn observe_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    HTTP_REQUESTS
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[method, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_readings(station: &str, count: usize) {
+    READINGS_INGESTED
+        .with_label_values(&[station])
+        .inc_by(count as u64);
+}
+
+pub fn set_pool_usage(size: u32, idle: usize, max: u32) {
+    DB_POOL_CONNECTIONS.set(size.into());
+    DB_POOL_IDLE_CONNECTIONS.set(idle as i64);
+    DB_POOL_MAX_CONNECTIONS.set(max.into());
+}
+
+/// Replace the fleet gauges, stations that are no longer active disappear
+pub fn set_fleet(stations: &[ActiveStationReadingRecord]) {
+    ACTIVE_STATIONS.set(stations.len() as i64);
+    STATION_LATEST_PM25.reset();
+    STATION_LATEST_CO2.reset();
+
+    for station in stations {
+        if let Some(pm25) = station.pm25 {
+            STATION_LATEST_PM25
+                .with_label_values(&[&station.token])
+                .set(pm25.into());
+        }
+        if let Some(co2) = station.co2 {
+            STATION_LATEST_CO2
+                .with_label_values(&[&station.token])
+                .set(co2.into());
+        }
+    }
+}
+
+/// Render every registered metric in the Prometheus text format
+pub fn encode() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("metrics can be encoded");
+
+    String::from_utf8(buffer).expect("metrics are valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_counted_per_route_and_status() {
+        let route = "/metrics-test/{id}";
+        observe_request("GET", route, 200, Duration::from_millis(5));
+        observe_request("GET", route, 200, Duration::from_millis(5));
+        observe_request("GET", route, 404, Duration::from_millis(5));
+
+        let metrics = encode();
+        let lines: Vec<&str> = metrics.lines().collect();
+        for expected in [
+            r#"auspex_http_requests_total{method="GET",route="/metrics-test/{id}",status="200"} 2"#,
+            r#"auspex_http_requests_total{method="GET",route="/metrics-test/{id}",status="404"} 1"#,
+            r#"auspex_http_request_duration_seconds_count{method="GET",route="/metrics-test/{id}"} 3"#,
+        ] {
+            assert!(lines.contains(&expected), "{expected} in {metrics}");
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    error::{Error, Result},
+    repository::queries::reading::{ReadingBucketRecord, SeriesPointRecord},
+};
+
+use super::metric::Metric;
+
+/// Width of the buckets readings are aggregated into. Buckets are aligned to the Unix
+/// epoch, so daily buckets start at midnight UTC
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Bucket {
+    #[serde(rename = "1m")]
+    Minute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "1d")]
+    Day,
+}
+
+impl Bucket {
+    pub fn seconds(self) -> i64 {
+        match self {
+            Bucket::Minute => 60,
+            Bucket::FiveMinutes => 5 * 60,
+            Bucket::Hour => 60 * 60,
+            Bucket::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+/// A period of time ending now, written as a number and a unit, e.g. `15m`, `8h` or `7d`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub seconds: i64,
+}
+
+const WINDOW_UNITS: [(char, i64); 4] = [('d', 24 * 60 * 60), ('h', 60 * 60), ('m', 60), ('s', 1)];
+
+impl Window {
+    pub const HOUR: Window = Window { seconds: 60 * 60 };
+    pub const DAY: Window = Window {
+        seconds: 24 * 60 * 60,
+    };
+
+    /// Parse a comma separated list like `1h,8h,24h`
+    pub fn parse_list(list: &str) -> Result<Vec<Window>> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|window| !window.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+impl FromStr for Window {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            Error::Validation(format!(
+                "invalid window {s:?}, expected a number followed by s, m, h or d"
+            ))
+        };
+
+        let unit = s.chars().last().ok_or_else(invalid)?;
+        let (_, multiplier) = WINDOW_UNITS
+            .iter()
+            .find(|(u, _)| *u == unit)
+            .ok_or_else(invalid)?;
+        let value: i64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+        if value <= 0 {
+            return Err(invalid());
+        }
+
+        value
+            .checked_mul(*multiplier)
+            .map(|seconds| Window { seconds })
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (unit, multiplier) = WINDOW_UNITS
+            .iter()
+            .find(|(_, m)| self.seconds % m == 0)
+            .unwrap_or(&('s', 1));
+
+        write!(f, "{}{unit}", self.seconds / multiplier)
+    }
+}
+
+impl Serialize for Window {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Window {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MetricAggregate {
+    pub count: i64,
+    pub avg: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+/// The readings of a station within one bucket, summarized per metric
+#[derive(Serialize, Deserialize)]
+pub struct ReadingBucket {
+    /// Start of the bucket
+    #[serde(with = "ts_milliseconds")]
+    pub date: DateTime<Utc>,
+    pub temperature: MetricAggregate,
+    pub humidity: MetricAggregate,
+    pub pm10: MetricAggregate,
+    pub pm25: MetricAggregate,
+    pub co2: MetricAggregate,
+    pub voc: MetricAggregate,
+}
+
+impl ReadingBucket {
+    pub fn aggregate(&self, metric: Metric) -> &MetricAggregate {
+        match metric {
+            Metric::Temperature => &self.temperature,
+            Metric::Humidity => &self.humidity,
+            Metric::Pm10 => &self.pm10,
+            Metric::Pm25 => &self.pm25,
+            Metric::Co2 => &self.co2,
+            Metric::Voc => &self.voc,
+        }
+    }
+}
+
+impl From<&ReadingBucketRecord> for ReadingBucket {
+    fn from(rec: &ReadingBucketRecord) -> Self {
+        ReadingBucket {
+            date: rec.date,
+            temperature: MetricAggregate {
+                count: rec.temperature_count,
+                avg: rec.temperature_avg,
+                min: rec.temperature_min,
+                max: rec.temperature_max,
+            },
+            humidity: MetricAggregate {
+                count: rec.humidity_count,
+                avg: rec.humidity_avg,
+                min: rec.humidity_min,
+                max: rec.humidity_max,
+            },
+            pm10: MetricAggregate {
+                count: rec.pm10_count,
+                avg: rec.pm10_avg,
+                min: rec.pm10_min,
+                max: rec.pm10_max,
+            },
+            pm25: MetricAggregate {
+                count: rec.pm25_count,
+                avg: rec.pm25_avg,
+                min: rec.pm25_min,
+                max: rec.pm25_max,
+            },
+            co2: MetricAggregate {
+                count: rec.co2_count,
+                avg: rec.co2_avg,
+                min: rec.co2_min,
+                max: rec.co2_max,
+            },
+            voc: MetricAggregate {
+                count: rec.voc_count,
+                avg: rec.voc_avg,
+                min: rec.voc_min,
+                max: rec.voc_max,
+            },
+        }
+    }
+}
+
+/// One metric of several stations, aggregated into the same buckets so they can be
+/// plotted together
+#[derive(Serialize, Deserialize)]
+pub struct ComparisonSeries {
+    pub metric: Metric,
+    pub unit: String,
+    pub bucket: Bucket,
+    /// Start of every bucket, in milliseconds
+    pub dates: Vec<i64>,
+    /// The mean of every bucket per station token, None when the station has no readings
+    /// in that bucket
+    pub series: BTreeMap<String, Vec<Option<f32>>>,
+}
+
+impl ComparisonSeries {
+    /// `records` must hold every bucket for every station, ordered by station and date
+    pub fn new(metric: Metric, bucket: Bucket, records: Vec<SeriesPointRecord>) -> Self {
+        let first = records.first().map(|rec| rec.token.clone());
+        let mut dates = vec![];
+        let mut series: BTreeMap<String, Vec<Option<f32>>> = BTreeMap::new();
+
+        for rec in records {
+            if Some(&rec.token) == first.as_ref() {
+                dates.push(rec.date.timestamp_millis());
+            }
+            series.entry(rec.token).or_default().push(rec.value);
+        }
+
+        ComparisonSeries {
+            metric,
+            unit: metric.unit().into(),
+            bucket,
+            dates,
+            series,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(s: &str) -> i64 {
+        s.parse::<Window>().expect("valid window").seconds
+    }
+
+    #[test]
+    fn parses_every_unit() {
+        assert_eq!(window("90s"), 90);
+        assert_eq!(window("15m"), 15 * 60);
+        assert_eq!(window("8h"), 8 * 60 * 60);
+        assert_eq!(window("7d"), 7 * 24 * 60 * 60);
+    }
+
+    #[test]
+    fn rejects_invalid_windows() {
+        for s in [
+            "",
+            "h",
+            "0h",
+            "-1h",
+            "5",
+            "5x",
+            "1.5h",
+            "h5",
+            "106751991167301d",
+        ] {
+            assert!(s.parse::<Window>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn displays_the_largest_whole_unit() {
+        assert_eq!(Window { seconds: 90 }.to_string(), "90s");
+        assert_eq!(Window { seconds: 5400 }.to_string(), "90m");
+        assert_eq!(Window::HOUR.to_string(), "1h");
+        assert_eq!(window("24h"), Window::DAY.seconds);
+        assert_eq!(Window::DAY.to_string(), "1d");
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        for s in ["45s", "15m", "8h", "7d"] {
+            assert_eq!(s.parse::<Window>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parses_lists() {
+        let windows = Window::parse_list("1h, 8h,,24h").unwrap();
+        assert_eq!(
+            windows,
+            [
+                Window::HOUR,
+                Window {
+                    seconds: 8 * 60 * 60
+                },
+                Window::DAY
+            ]
+        );
+
+        assert!(Window::parse_list("1h,2x").is_err());
+        assert!(Window::parse_list("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn serializes_as_a_string() {
+        assert_eq!(serde_json::to_string(&Window::HOUR).unwrap(), r#""1h""#);
+        assert_eq!(
+            serde_json::from_str::<Window>(r#""15m""#).unwrap(),
+            Window { seconds: 15 * 60 }
+        );
+        assert!(serde_json::from_str::<Window>(r#""15""#).is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use std::str::FromStr;
+
+use chrono::{
+    serde::{ts_milliseconds, ts_milliseconds_option},
+    DateTime, Duration, Utc,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    repository::queries::alert::{AlertRecord, AlertRuleRecord, AlertRuleStateRecord},
+};
+
+use super::metric::Metric;
+
+/// Side of the threshold on which a rule is breached
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+impl Comparison {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Comparison::Above => "above",
+            Comparison::Below => "below",
+        }
+    }
+
+    /// Whether the value lies strictly beyond the threshold
+    pub fn exceeds(self, value: f32, threshold: f32) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::Below => value < threshold,
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "above" => Ok(Comparison::Above),
+            "below" => Ok(Comparison::Below),
+            _ => Err(Error::Validation(format!("unknown comparison {s:?}"))),
+        }
+    }
+}
+
+/// A condition like "pm25 above 35 for 15 minutes", checked at one station, at the
+/// stations of a group or, without either, at every station
+#[derive(Serialize, Deserialize)]
+pub struct AlertRule {
+    pub id: i32,
+    pub name: String,
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: f32,
+    /// A firing alert resolves once the value is back at or past this threshold, so values
+    /// hovering around `threshold` don't make it flap
+    pub clear_threshold: f32,
+    /// How long the threshold must stay breached before the alert fires
+    pub duration_seconds: i32,
+    /// Token of the station the rule is limited to
+    pub station: Option<String>,
+    /// Name of the group the rule is limited to
+    pub group: Option<String>,
+    pub enabled: bool,
+}
+
+/// Where a rule stands at one station
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct AlertState {
+    /// Date of the first reading of the ongoing breach
+    pub breaching_since: Option<DateTime<Utc>>,
+    pub firing: bool,
+    /// Date of the latest reading evaluated, readings that arrive later with an earlier
+    /// date are ignored
+    pub last_date: Option<DateTime<Utc>>,
+}
+
+/// The rule fired or resolved with the reading of `date`
+#[derive(PartialEq, Debug)]
+pub enum AlertTransition {
+    Fire {
+        since: DateTime<Utc>,
+        date: DateTime<Utc>,
+        value: f32,
+    },
+    Resolve {
+        date: DateTime<Utc>,
+        value: f32,
+    },
+}
+
+/// An alert of a rule at a station, firing until it is resolved
+#[derive(Serialize, Deserialize)]
+pub struct Alert {
+    pub id: i32,
+    pub rule_id: i32,
+    pub rule: String,
+    pub station: String,
+    pub metric: Metric,
+    /// Date of the first reading that breached the threshold
+    #[serde(with = "ts_milliseconds")]
+    pub since: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    pub fired_at: DateTime<Utc>,
+    /// The value that made the alert fire
+    pub value: f32,
+    #[serde(with = "ts_milliseconds_option")]
+    pub resolved_at: Option<DateTime<Utc>>,
+    /// The value that resolved the alert, left out when it was resolved because its rule
+    /// changed
+    pub resolved_value: Option<f32>,
+}
+
+impl AlertRule {
+    /// Move the state along with a new value of the rule's metric, telling whether the
+    /// alert fired or resolved. Values older than the last one evaluated are ignored
+    pub fn evaluate(
+        &self,
+        state: &mut AlertState,
+        date: DateTime<Utc>,
+        value: f32,
+    ) -> Option<AlertTransition> {
+        if state.last_date.is_some_and(|last| date < last) {
+            return None;
+        }
+        state.last_date = Some(date);
+
+        if state.firing {
+            if self.comparison.exceeds(value, self.clear_threshold) {
+                return None;
+            }
+
+            state.firing = false;
+            state.breaching_since = None;
+            return Some(AlertTransition::Resolve { date, value });
+        }
+
+        if !self.comparison.exceeds(value, self.threshold) {
+            state.breaching_since = None;
+            return None;
+        }
+
+        let since = *state.breaching_since.get_or_insert(date);
+        if date - since < Duration::seconds(self.duration_seconds.into()) {
+            return None;
+        }
+
+        state.firing = true;
+        Some(AlertTransition::Fire { since, date, value })
+    }
+}
+
+impl TryFrom<AlertRuleRecord> for AlertRule {
+    type Error = Error;
+
+    fn try_from(rec: AlertRuleRecord) -> Result<Self> {
+        Ok(AlertRule {
+            id: rec.id,
+            name: rec.name,
+            metric: rec.metric.parse()?,
+            comparison: rec.comparison.parse()?,
+            threshold: rec.threshold,
+            clear_threshold: rec.clear_threshold,
+            duration_seconds: rec.duration_seconds,
+            station: rec.station,
+            group: rec.group,
+            enabled: rec.enabled,
+        })
+    }
+}
+
+impl TryFrom<AlertRuleStateRecord> for (AlertRule, AlertState) {
+    type Error = Error;
+
+    fn try_from(rec: AlertRuleStateRecord) -> Result<Self> {
+        let rule = AlertRule {
+            id: rec.id,
+            name: rec.name,
+            metric: rec.metric.parse()?,
+            comparison: rec.comparison.parse()?,
+            threshold: rec.threshold,
+            clear_threshold: rec.clear_threshold,
+            duration_seconds: rec.duration_seconds,
+            station: None,
+            group: None,
+            enabled: true,
+        };
+        let state = AlertState {
+            breaching_since: rec.breaching_since,
+            firing: rec.firing,
+            last_date: rec.last_date,
+        };
+
+        Ok((rule, state))
+    }
+}
+
+impl TryFrom<AlertRecord> for Alert {
+    type Error = Error;
+
+    fn try_from(rec: AlertRecord) -> Result<Self> {
+        Ok(Alert {
+            id: rec.id,
+            rule_id: rec.rule_id,
+            rule: rec.rule,
+            station: rec.station,
+            metric: rec.metric.parse()?,
+            since: rec.since,
+            fired_at: rec.fired_at,
+            value: rec.value,
+            resolved_at: rec.resolved_at,
+            resolved_value: rec.resolved_value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn rule(threshold: f32, clear_threshold: f32, duration_seconds: i32) -> AlertRule {
+        AlertRule {
+            id: 1,
+            name: "pm25".into(),
+            metric: Metric::Pm25,
+            comparison: Comparison::Above,
+            threshold,
+            clear_threshold,
+            duration_seconds,
+            station: None,
+            group: None,
+            enabled: true,
+        }
+    }
+
+    fn at(minute: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000, 0).unwrap() + Duration::minutes(minute)
+    }
+
+    /// Evaluate a value every minute, returning the minutes at which the alert fired and
+    /// resolved
+    fn run(rule: &AlertRule, state: &mut AlertState, values: &[f32]) -> Vec<(i64, bool)> {
+        values
+            .iter()
+            .enumerate()
+            .filter_map(|(minute, &value)| {
+                let minute = minute as i64;
+                rule.evaluate(state, at(minute), value)
+                    .map(|transition| (minute, matches!(transition, AlertTransition::Fire { .. })))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fires_as_soon_as_the_threshold_is_exceeded_without_duration() {
+        let mut state = AlertState::default();
+        let transition = rule(35.0, 35.0, 0).evaluate(&mut state, at(0), 40.0);
+
+        assert_eq!(
+            transition,
+            Some(AlertTransition::Fire {
+                since: at(0),
+                date: at(0),
+                value: 40.0
+            })
+        );
+        assert!(state.firing);
+    }
+
+    #[test]
+    fn the_threshold_itself_is_no_breach() {
+        let mut state = AlertState::default();
+
+        assert!(run(&rule(35.0, 35.0, 0), &mut state, &[35.0]).is_empty());
+        assert!(!state.firing);
+    }
+
+    #[test]
+    fn stays_firing_until_past_the_clear_threshold() {
+        let mut state = AlertState::default();
+        let transitions = run(
+            &rule(35.0, 25.0, 0),
+            &mut state,
+            &[40.0, 30.0, 34.0, 26.0, 25.0],
+        );
+
+        assert_eq!(transitions, [(0, true), (4, false)]);
+        assert_eq!(state.breaching_since, None);
+    }
+
+    #[test]
+    fn values_between_the_thresholds_do_not_fire_again() {
+        let mut state = AlertState::default();
+        let transitions = run(&rule(35.0, 25.0, 0), &mut state, &[40.0, 20.0, 30.0, 36.0]);
+
+        assert_eq!(transitions, [(0, true), (1, false), (3, true)]);
+    }
+
+    #[test]
+    fn below_rules_use_the_other_side_of_the_thresholds() {
+        let mut rule = rule(10.0, 15.0, 0);
+        rule.comparison = Comparison::Below;
+        let mut state = AlertState::default();
+
+        assert_eq!(
+            run(&rule, &mut state, &[12.0, 9.0, 14.0, 16.0]),
+            [(1, true), (3, false)]
+        );
+    }
+
+    #[test]
+    fn fires_once_the_breach_lasted_the_duration() {
+        let mut state = AlertState::default();
+        let transitions = run(
+            &rule(35.0, 35.0, 180),
+            &mut state,
+            &[40.0, 41.0, 42.0, 43.0],
+        );
+
+        assert_eq!(transitions, [(3, true)]);
+        assert_eq!(state.breaching_since, Some(at(0)));
+    }
+
+    #[test]
+    fn an_interrupted_breach_starts_over() {
+        let mut state = AlertState::default();
+        let transitions = run(
+            &rule(35.0, 35.0, 120),
+            &mut state,
+            &[40.0, 41.0, 30.0, 40.0, 41.0, 42.0],
+        );
+
+        assert_eq!(transitions, [(5, true)]);
+    }
+
+    #[test]
+    fn fired_alerts_tell_when_the_breach_began() {
+        let mut state = AlertState::default();
+        let rule = rule(35.0, 35.0, 60);
+        rule.evaluate(&mut state, at(0), 40.0);
+        let transition = rule.evaluate(&mut state, at(1), 45.0);
+
+        assert_eq!(
+            transition,
+            Some(AlertTransition::Fire {
+                since: at(0),
+                date: at(1),
+                value: 45.0
+            })
+        );
+    }
+
+    #[test]
+    fn late_values_are_ignored() {
+        let rule = rule(35.0, 35.0, 60);
+        let mut state = AlertState::default();
+        rule.evaluate(&mut state, at(5), 40.0);
+
+        assert_eq!(rule.evaluate(&mut state, at(3), 40.0), None);
+        assert_eq!(rule.evaluate(&mut state, at(4), 20.0), None);
+        assert_eq!(state.breaching_since, Some(at(5)));
+        assert_eq!(state.last_date, Some(at(5)));
+        assert!(rule.evaluate(&mut state, at(6), 40.0).is_some());
+    }
+
+    #[test]
+    fn late_values_do_not_resolve() {
+        let rule = rule(35.0, 35.0, 0);
+        let mut state = AlertState::default();
+        rule.evaluate(&mut state, at(5), 40.0);
+
+        assert_eq!(rule.evaluate(&mut state, at(4), 20.0), None);
+        assert!(state.firing);
+    }
+}
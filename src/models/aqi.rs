@@ -0,0 +1,419 @@
+//! Air quality indices computed from particulate matter concentrations (µg/m³).
+//!
+//! - US EPA AQI, using the breakpoints revised in 2024, from 24 hour averages (daily AQI)
+//!   and from the NowCast (current AQI)
+//! - EU CAQI (Common Air Quality Index), using the hourly and the daily grid
+
+use serde::{Deserialize, Serialize};
+
+use crate::repository::queries::reading::HourlyAverageRecord;
+
+use super::{nowcast::NowCast, reading::AverageReadingValues};
+use AqiCategory::*;
+
+/// Number of hourly averages the indices are computed from
+pub const AQI_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pollutant {
+    Pm25,
+    Pm10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AqiCategory {
+    // US EPA
+    Good,
+    Moderate,
+    #[serde(rename = "Unhealthy for Sensitive Groups")]
+    UnhealthyForSensitiveGroups,
+    Unhealthy,
+    #[serde(rename = "Very Unhealthy")]
+    VeryUnhealthy,
+    Hazardous,
+    // EU CAQI
+    #[serde(rename = "Very Low")]
+    VeryLow,
+    Low,
+    Medium,
+    High,
+    #[serde(rename = "Very High")]
+    VeryHigh,
+}
+
+/// Concentrations in `low..=high` map linearly onto `index_low..=index_high`
+struct Breakpoint {
+    low: f32,
+    high: f32,
+    index_low: f32,
+    index_high: f32,
+    category: AqiCategory,
+}
+
+const fn bp(
+    low: f32,
+    high: f32,
+    index_low: f32,
+    index_high: f32,
+    category: AqiCategory,
+) -> Breakpoint {
+    Breakpoint {
+        low,
+        high,
+        index_low,
+        index_high,
+        category,
+    }
+}
+
+struct Scale {
+    breakpoints: &'static [Breakpoint],
+    /// Concentrations are truncated to a multiple of this before the lookup
+    precision: f32,
+    /// Whether concentrations above the last breakpoint extend its line, instead of
+    /// being capped at its highest index
+    extrapolate: bool,
+    beyond: AqiCategory,
+}
+
+const EPA_PM25: Scale = Scale {
+    breakpoints: &[
+        bp(0.0, 9.0, 0.0, 50.0, Good),
+        bp(9.1, 35.4, 51.0, 100.0, Moderate),
+        bp(35.5, 55.4, 101.0, 150.0, UnhealthyForSensitiveGroups),
+        bp(55.5, 125.4, 151.0, 200.0, Unhealthy),
+        bp(125.5, 225.4, 201.0, 300.0, VeryUnhealthy),
+        bp(225.5, 325.4, 301.0, 500.0, Hazardous),
+    ],
+    precision: 0.1,
+    extrapolate: false,
+    beyond: Hazardous,
+};
+
+const EPA_PM10: Scale = Scale {
+    breakpoints: &[
+        bp(0.0, 54.0, 0.0, 50.0, Good),
+        bp(55.0, 154.0, 51.0, 100.0, Moderate),
+        bp(155.0, 254.0, 101.0, 150.0, UnhealthyForSensitiveGroups),
+        bp(255.0, 354.0, 151.0, 200.0, Unhealthy),
+        bp(355.0, 424.0, 201.0, 300.0, VeryUnhealthy),
+        bp(425.0, 604.0, 301.0, 500.0, Hazardous),
+    ],
+    precision: 1.0,
+    extrapolate: false,
+    beyond: Hazardous,
+};
+
+const CAQI_HOURLY_PM25: Scale = Scale {
+    breakpoints: &[
+        bp(0.0, 15.0, 0.0, 25.0, VeryLow),
+        bp(15.0, 30.0, 25.0, 50.0, Low),
+        bp(30.0, 55.0, 50.0, 75.0, Medium),
+        bp(55.0, 110.0, 75.0, 100.0, High),
+    ],
+    precision: 0.1,
+    extrapolate: true,
+    beyond: VeryHigh,
+};
+
+const CAQI_HOURLY_PM10: Scale = Scale {
+    breakpoints: &[
+        bp(0.0, 25.0, 0.0, 25.0, VeryLow),
+        bp(25.0, 50.0, 25.0, 50.0, Low),
+        bp(50.0, 90.0, 50.0, 75.0, Medium),
+        bp(90.0, 180.0, 75.0, 100.0, High),
+    ],
+    precision: 1.0,
+    extrapolate: true,
+    beyond: VeryHigh,
+};
+
+const CAQI_DAILY_PM25: Scale = Scale {
+    breakpoints: &[
+        bp(0.0, 10.0, 0.0, 25.0, VeryLow),
+        bp(10.0, 20.0, 25.0, 50.0, Low),
+        bp(20.0, 30.0, 50.0, 75.0, Medium),
+        bp(30.0, 60.0, 75.0, 100.0, High),
+    ],
+    precision: 0.1,
+    extrapolate: true,
+    beyond: VeryHigh,
+};
+
+const CAQI_DAILY_PM10: Scale = Scale {
+    breakpoints: &[
+        bp(0.0, 15.0, 0.0, 25.0, VeryLow),
+        bp(15.0, 30.0, 25.0, 50.0, Low),
+        bp(30.0, 50.0, 50.0, 75.0, Medium),
+        bp(50.0, 100.0, 75.0, 100.0, High),
+    ],
+    precision: 1.0,
+    extrapolate: true,
+    beyond: VeryHigh,
+};
+
+impl Scale {
+    fn sub_index(&self, pollutant: Pollutant, concentration: f32) -> SubIndex {
+        // Round first so values like 9.1 that are stored as 9.0999 don't truncate to 9.0
+        let scaled = (concentration.max(0.0) / self.precision * 1000.0).round() / 1000.0;
+        let truncated = scaled.floor() * self.precision;
+
+        let (index, category) = match self.breakpoints.iter().find(|bp| truncated <= bp.high) {
+            Some(bp) => (bp.interpolate(truncated), bp.category),
+            None => {
+                let last = self.breakpoints.last().expect("scale has breakpoints");
+                let index = if self.extrapolate {
+                    last.interpolate(truncated)
+                } else {
+                    last.index_high
+                };
+                (index, self.beyond)
+            }
+        };
+
+        SubIndex {
+            pollutant,
+            concentration,
+            index: index.round() as u32,
+            category,
+        }
+    }
+}
+
+impl Breakpoint {
+    fn interpolate(&self, concentration: f32) -> f32 {
+        let concentration = concentration.max(self.low);
+
+        (self.index_high - self.index_low) / (self.high - self.low) * (concentration - self.low)
+            + self.index_low
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubIndex {
+    pub pollutant: Pollutant,
+    /// Averaged concentration the index was computed from, in µg/m³
+    pub concentration: f32,
+    pub index: u32,
+    pub category: AqiCategory,
+}
+
+/// The overall index is the highest sub-index, `dominant` is the pollutant it belongs to
+#[derive(Serialize, Deserialize)]
+pub struct AqiIndex {
+    pub index: u32,
+    pub category: AqiCategory,
+    pub dominant: Pollutant,
+    pub pollutants: Vec<SubIndex>,
+}
+
+impl AqiIndex {
+    /// US EPA AQI from 24 hour averages or the NowCast
+    pub fn epa(pm25: f32, pm10: f32) -> Self {
+        Self::from_sub_indices(vec![
+            EPA_PM25.sub_index(Pollutant::Pm25, pm25),
+            EPA_PM10.sub_index(Pollutant::Pm10, pm10),
+        ])
+    }
+
+    /// EU CAQI from hourly averages
+    pub fn caqi_hourly(pm25: f32, pm10: f32) -> Self {
+        Self::from_sub_indices(vec![
+            CAQI_HOURLY_PM25.sub_index(Pollutant::Pm25, pm25),
+            CAQI_HOURLY_PM10.sub_index(Pollutant::Pm10, pm10),
+        ])
+    }
+
+    /// EU CAQI from 24 hour averages
+    pub fn caqi_daily(pm25: f32, pm10: f32) -> Self {
+        Self::from_sub_indices(vec![
+            CAQI_DAILY_PM25.sub_index(Pollutant::Pm25, pm25),
+            CAQI_DAILY_PM10.sub_index(Pollutant::Pm10, pm10),
+        ])
+    }
+
+    fn from_sub_indices(pollutants: Vec<SubIndex>) -> Self {
+        let (index, category, dominant) = pollutants
+            .iter()
+            .max_by_key(|sub| sub.index)
+            .map(|sub| (sub.index, sub.category, sub.pollutant))
+            .expect("at least one pollutant");
+
+        AqiIndex {
+            index,
+            category,
+            dominant,
+            pollutants,
+        }
+    }
+}
+
+/// Every index of a station, left out when there are no readings in its averaging period
+#[derive(Serialize, Deserialize)]
+pub struct Aqi {
+    pub epa: Option<AqiIndex>,
+    pub epa_nowcast: Option<AqiIndex>,
+    pub caqi_hourly: Option<AqiIndex>,
+    pub caqi_daily: Option<AqiIndex>,
+}
+
+impl Aqi {
+    pub fn new(
+        hour: &AverageReadingValues,
+        day: &AverageReadingValues,
+        nowcast: Option<&NowCast>,
+    ) -> Self {
+        Self::from_averages(hour.pm25.zip(hour.pm10), day.pm25.zip(day.pm10), nowcast)
+    }
+
+    /// Every index from the hourly averages of the last `AQI_HOURS` hours
+    pub fn from_hourly(hours: &[HourlyAverageRecord]) -> Self {
+        let hour = hours.iter().find(|h| h.hour == 0).map(|h| (h.pm25, h.pm10));
+
+        let count: i64 = hours.iter().map(|h| h.count).sum();
+        let day = (count > 0).then(|| {
+            let mean = |value: fn(&HourlyAverageRecord) -> f32| {
+                hours.iter().map(|h| value(h) * h.count as f32).sum::<f32>() / count as f32
+            };
+            (mean(|h| h.pm25), mean(|h| h.pm10))
+        });
+
+        Self::from_averages(hour, day, NowCast::new(hours).as_ref())
+    }
+
+    /// `hour` and `day` are the mean PM2.5 and PM10 of their period
+    fn from_averages(
+        hour: Option<(f32, f32)>,
+        day: Option<(f32, f32)>,
+        nowcast: Option<&NowCast>,
+    ) -> Self {
+        Aqi {
+            epa: day.map(|(pm25, pm10)| AqiIndex::epa(pm25, pm10)),
+            epa_nowcast: nowcast.map(|nowcast| AqiIndex::epa(nowcast.pm25, nowcast.pm10)),
+            caqi_hourly: hour.map(|(pm25, pm10)| AqiIndex::caqi_hourly(pm25, pm10)),
+            caqi_daily: day.map(|(pm25, pm10)| AqiIndex::caqi_daily(pm25, pm10)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sub_index(scale: &Scale, concentration: f32) -> (u32, AqiCategory) {
+        let sub = scale.sub_index(Pollutant::Pm25, concentration);
+        (sub.index, sub.category)
+    }
+
+    fn hour(hour: i32, count: i64, pm25: f32, pm10: f32) -> HourlyAverageRecord {
+        HourlyAverageRecord {
+            hour,
+            count,
+            pm25,
+            pm10,
+        }
+    }
+
+    #[test]
+    fn epa_pm25_breakpoints() {
+        assert_eq!(sub_index(&EPA_PM25, 0.0), (0, Good));
+        assert_eq!(sub_index(&EPA_PM25, 9.0), (50, Good));
+        assert_eq!(sub_index(&EPA_PM25, 9.1), (51, Moderate));
+        assert_eq!(sub_index(&EPA_PM25, 12.0), (56, Moderate));
+        assert_eq!(sub_index(&EPA_PM25, 35.4), (100, Moderate));
+        assert_eq!(
+            sub_index(&EPA_PM25, 35.5),
+            (101, UnhealthyForSensitiveGroups)
+        );
+        assert_eq!(sub_index(&EPA_PM25, 55.5), (151, Unhealthy));
+        assert_eq!(sub_index(&EPA_PM25, 125.5), (201, VeryUnhealthy));
+        assert_eq!(sub_index(&EPA_PM25, 225.5), (301, Hazardous));
+        assert_eq!(sub_index(&EPA_PM25, 325.4), (500, Hazardous));
+    }
+
+    #[test]
+    fn epa_pm10_breakpoints() {
+        assert_eq!(sub_index(&EPA_PM10, 54.0), (50, Good));
+        assert_eq!(sub_index(&EPA_PM10, 55.0), (51, Moderate));
+        assert_eq!(sub_index(&EPA_PM10, 154.0), (100, Moderate));
+        assert_eq!(
+            sub_index(&EPA_PM10, 155.0),
+            (101, UnhealthyForSensitiveGroups)
+        );
+        assert_eq!(sub_index(&EPA_PM10, 604.0), (500, Hazardous));
+    }
+
+    #[test]
+    fn epa_truncates_to_its_precision() {
+        assert_eq!(sub_index(&EPA_PM25, 9.05), (50, Good));
+        assert_eq!(sub_index(&EPA_PM10, 54.9), (50, Good));
+    }
+
+    #[test]
+    fn epa_is_capped_beyond_the_last_breakpoint() {
+        assert_eq!(sub_index(&EPA_PM25, 400.0), (500, Hazardous));
+        assert_eq!(sub_index(&EPA_PM10, 700.0), (500, Hazardous));
+    }
+
+    #[test]
+    fn negative_concentrations_count_as_zero() {
+        assert_eq!(sub_index(&EPA_PM25, -3.0), (0, Good));
+        assert_eq!(sub_index(&CAQI_HOURLY_PM10, -3.0), (0, VeryLow));
+    }
+
+    #[test]
+    fn caqi_breakpoints() {
+        assert_eq!(sub_index(&CAQI_HOURLY_PM25, 15.0), (25, VeryLow));
+        assert_eq!(sub_index(&CAQI_HOURLY_PM25, 15.1), (25, Low));
+        assert_eq!(sub_index(&CAQI_HOURLY_PM25, 110.0), (100, High));
+        assert_eq!(sub_index(&CAQI_HOURLY_PM10, 180.0), (100, High));
+        assert_eq!(sub_index(&CAQI_DAILY_PM25, 60.0), (100, High));
+        assert_eq!(sub_index(&CAQI_DAILY_PM10, 40.0), (63, Medium));
+    }
+
+    #[test]
+    fn caqi_extrapolates_beyond_the_last_breakpoint() {
+        assert_eq!(sub_index(&CAQI_HOURLY_PM25, 220.0), (150, VeryHigh));
+        assert_eq!(sub_index(&CAQI_HOURLY_PM10, 270.0), (125, VeryHigh));
+    }
+
+    #[test]
+    fn overall_index_is_the_highest_sub_index() {
+        let aqi = AqiIndex::epa(40.0, 20.0);
+
+        assert_eq!(aqi.index, 112);
+        assert_eq!(aqi.category, UnhealthyForSensitiveGroups);
+        assert_eq!(aqi.dominant, Pollutant::Pm25);
+        assert_eq!(aqi.pollutants[1].index, 19);
+    }
+
+    #[test]
+    fn from_hourly_weighs_the_daily_mean_by_count() {
+        let aqi = Aqi::from_hourly(&[hour(0, 2, 10.0, 20.0), hour(1, 6, 30.0, 40.0)]);
+
+        // Daily means of 25 PM2.5 and 35 PM10
+        let daily = aqi.caqi_daily.unwrap();
+        assert_eq!(daily.pollutants[0].concentration, 25.0);
+        assert_eq!(daily.pollutants[1].concentration, 35.0);
+        assert_eq!((daily.index, daily.dominant), (63, Pollutant::Pm25));
+
+        // Hourly means of the last hour only
+        let hourly = aqi.caqi_hourly.unwrap();
+        assert_eq!((hourly.index, hourly.dominant), (20, Pollutant::Pm10));
+
+        assert!(aqi.epa.is_some());
+        assert!(aqi.epa_nowcast.is_some());
+    }
+
+    #[test]
+    fn from_hourly_leaves_out_periods_without_readings() {
+        let aqi = Aqi::from_hourly(&[]);
+        assert!(aqi.epa.is_none() && aqi.epa_nowcast.is_none());
+        assert!(aqi.caqi_hourly.is_none() && aqi.caqi_daily.is_none());
+
+        let aqi = Aqi::from_hourly(&[hour(5, 3, 10.0, 20.0)]);
+        assert!(aqi.caqi_hourly.is_none() && aqi.epa_nowcast.is_none());
+        assert!(aqi.epa.is_some() && aqi.caqi_daily.is_some());
+    }
+}
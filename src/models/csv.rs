@@ -0,0 +1,83 @@
+//! CSV (RFC 4180) exports of readings. Values are numbers and dates and headers never
+//! contain separators, so nothing needs quoting
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{aggregate::ReadingBucket, metric::Metric, reading::Reading};
+
+const LINE_END: &str = "\r\n";
+
+/// How dates are written in an export
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// ISO 8601 in UTC with milliseconds, e.g. `2026-10-18T09:00:00.000Z`
+    #[default]
+    Iso,
+    /// Milliseconds since the Unix epoch, like the JSON API
+    EpochMs,
+}
+
+impl TimestampFormat {
+    pub fn format(self, date: DateTime<Utc>) -> String {
+        match self {
+            TimestampFormat::Iso => date.to_rfc3339_opts(SecondsFormat::Millis, true),
+            TimestampFormat::EpochMs => date.timestamp_millis().to_string(),
+        }
+    }
+}
+
+/// A type that can be exported as a line of CSV
+pub trait CsvRow {
+    /// The header line, columns of measured values mention their unit
+    fn csv_header() -> String;
+
+    fn csv_row(&self, timestamps: TimestampFormat) -> String;
+}
+
+impl CsvRow for Reading {
+    fn csv_header() -> String {
+        let mut columns = vec!["date".to_string()];
+        columns.extend(Metric::ALL.map(|metric| with_unit(metric.column(), metric)));
+
+        columns.join(",") + LINE_END
+    }
+
+    fn csv_row(&self, timestamps: TimestampFormat) -> String {
+        let mut values = vec![timestamps.format(self.date)];
+        values.extend(Metric::ALL.map(|metric| self.value(metric).to_string()));
+
+        values.join(",") + LINE_END
+    }
+}
+
+impl CsvRow for ReadingBucket {
+    fn csv_header() -> String {
+        let mut columns = vec!["date".to_string()];
+        for metric in Metric::ALL {
+            let name = metric.column();
+            columns.push(format!("{name}_count"));
+            for stat in ["avg", "min", "max"] {
+                columns.push(with_unit(&format!("{name}_{stat}"), metric));
+            }
+        }
+
+        columns.join(",") + LINE_END
+    }
+
+    fn csv_row(&self, timestamps: TimestampFormat) -> String {
+        let mut values = vec![timestamps.format(self.date)];
+        for metric in Metric::ALL {
+            let aggregate = self.aggregate(metric);
+            values.push(aggregate.count.to_string());
+            values.extend([aggregate.avg, aggregate.min, aggregate.max].map(|v| v.to_string()));
+        }
+
+        values.join(",") + LINE_END
+    }
+}
+
+fn with_unit(column: &str, metric: Metric) -> String {
+    format!("{column} ({})", metric.unit())
+}
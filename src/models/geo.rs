@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Mean radius of the earth, as used by the haversine formula
+pub const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// An area on the map. `min_lon` is larger than `max_lon` for boxes that cross the
+/// antimeridian
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl BoundingBox {
+    pub fn validate(&self) -> Result<()> {
+        validate_coordinate(self.min_lat, self.min_lon)?;
+        validate_coordinate(self.max_lat, self.max_lon)?;
+        if self.min_lat > self.max_lat {
+            return Err(Error::Validation(
+                "min_lat must not be larger than max_lat".into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn crosses_antimeridian(&self) -> bool {
+        self.min_lon > self.max_lon
+    }
+
+    /// Width in degrees of longitude
+    pub fn lon_span(&self) -> f64 {
+        if self.crosses_antimeridian() {
+            self.max_lon + 360.0 - self.min_lon
+        } else {
+            self.max_lon - self.min_lon
+        }
+    }
+
+    /// Grow the box by `factor` times its height and width on every side
+    pub fn expand(&self, factor: f64) -> BoundingBox {
+        let lat_margin = (self.max_lat - self.min_lat) * factor;
+        let lon_margin = self.lon_span() * factor;
+        let (min_lon, max_lon) = if self.lon_span() + 2.0 * lon_margin >= 360.0 {
+            (-180.0, 180.0)
+        } else {
+            (
+                normalize_lon(self.min_lon - lon_margin),
+                normalize_lon(self.max_lon + lon_margin),
+            )
+        };
+
+        BoundingBox {
+            min_lat: (self.min_lat - lat_margin).max(-90.0),
+            min_lon,
+            max_lat: (self.max_lat + lat_margin).min(90.0),
+            max_lon,
+        }
+    }
+}
+
+pub fn validate_coordinate(lat: f64, lon: f64) -> Result<()> {
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(Error::Validation(format!(
+            "latitude {lat} must be between -90 and 90"
+        )));
+    }
+    if !(-180.0..=180.0).contains(&lon) {
+        return Err(Error::Validation(format!(
+            "longitude {lon} must be between -180 and 180"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Wrap a longitude into `[-180, 180)`
+pub fn normalize_lon(lon: f64) -> f64 {
+    (lon + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Great-circle distance between two coordinates, in kilometres
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+
+    // Rounding can push `a` just above 1 for antipodal points, where asin isn't defined
+    2.0 * EARTH_RADIUS_KM * a.min(1.0).sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn haversine_of_the_same_point_is_zero() {
+        assert_eq!(haversine_km(52.37, 4.89, 52.37, 4.89), 0.0);
+    }
+
+    #[test]
+    fn haversine_of_a_degree_along_the_equator() {
+        let km = haversine_km(0.0, 0.0, 0.0, 1.0);
+        assert!((km - 111.195).abs() < 0.001, "{km}");
+    }
+
+    #[test]
+    fn haversine_of_antipodal_points_is_half_the_circumference() {
+        let half = std::f64::consts::PI * EARTH_RADIUS_KM;
+        // Rounding pushes the haversine of all but the first just above 1
+        for (lat, lon) in [
+            (0.0, 90.0),
+            (-2.347304257388629, 55.712417309578946),
+            (-76.45043823944611, 126.35992617210232),
+            (-0.39567168499695526, 171.7455183758761),
+        ] {
+            let km = haversine_km(lat, lon, -lat, lon - 180.0);
+            assert!((km - half).abs() < 1e-6, "{lat},{lon}: {km}");
+        }
+    }
+
+    #[test]
+    fn haversine_across_the_antimeridian() {
+        let km = haversine_km(0.0, 179.5, 0.0, -179.5);
+        assert!((km - 111.195).abs() < 0.001, "{km}");
+    }
+
+    #[test]
+    fn bounding_box_across_the_antimeridian() {
+        let bbox = BoundingBox {
+            min_lat: -10.0,
+            min_lon: 170.0,
+            max_lat: 10.0,
+            max_lon: -170.0,
+        };
+        assert!(bbox.crosses_antimeridian());
+        assert_eq!(bbox.lon_span(), 20.0);
+
+        let expanded = bbox.expand(0.5);
+        assert_eq!(expanded.min_lat, -20.0);
+        assert_eq!(expanded.max_lat, 20.0);
+        assert_eq!(expanded.min_lon, 160.0);
+        assert_eq!(expanded.max_lon, -160.0);
+    }
+
+    #[test]
+    fn bounding_box_expands_to_the_whole_world_at_most() {
+        let bbox = BoundingBox {
+            min_lat: -60.0,
+            min_lon: -120.0,
+            max_lat: 60.0,
+            max_lon: 120.0,
+        };
+
+        let expanded = bbox.expand(1.0);
+        assert_eq!(expanded.min_lat, -90.0);
+        assert_eq!(expanded.max_lat, 90.0);
+        assert_eq!(expanded.min_lon, -180.0);
+        assert_eq!(expanded.max_lon, 180.0);
+    }
+
+    #[test]
+    fn bounding_box_validation() {
+        let valid = BoundingBox {
+            min_lat: 50.0,
+            min_lon: 3.0,
+            max_lat: 54.0,
+            max_lon: 7.0,
+        };
+        assert!(valid.validate().is_ok());
+
+        let inverted = BoundingBox {
+            min_lat: 54.0,
+            max_lat: 50.0,
+            ..valid
+        };
+        assert!(matches!(inverted.validate(), Err(Error::Validation(_))));
+
+        let outside = BoundingBox {
+            max_lon: 181.0,
+            ..valid
+        };
+        assert!(matches!(outside.validate(), Err(Error::Validation(_))));
+    }
+}
@@ -0,0 +1,24 @@
+//! The parts of GeoJSON (RFC 7946) the API produces. Coordinates are `[longitude, latitude]`
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub struct Feature {
+    pub geometry: Geometry,
+    pub properties: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    Point { coordinates: [f64; 2] },
+    MultiLineString { coordinates: Vec<Vec<[f64; 2]>> },
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+use crate::repository::queries::group::StationGroupRecord;
+
+/// A named set of stations, e.g. the stations of a city, that can be queried together
+#[derive(Serialize, Deserialize)]
+pub struct StationGroup {
+    pub id: i32,
+    pub name: String,
+    /// Tokens of the member stations
+    pub stations: Vec<String>,
+}
+
+impl From<StationGroupRecord> for StationGroup {
+    fn from(rec: StationGroupRecord) -> Self {
+        StationGroup {
+            id: rec.id,
+            name: rec.name,
+            stations: rec.stations,
+        }
+    }
+}
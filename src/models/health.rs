@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// Whether the database answered within the readiness timeout
+    pub database: bool,
+    /// Whether every migration of this build is applied
+    pub migrations: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Version {
+    pub version: String,
+    pub git_hash: String,
+    pub schema_version: Option<i64>,
+}
@@ -0,0 +1,401 @@
+//! Surfaces of a metric over an area, interpolated from the stations around it by inverse
+//! distance weighting, and the contour lines of such a surface
+
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{
+    geo::{haversine_km, normalize_lon, BoundingBox},
+    geojson::{Feature, FeatureCollection, Geometry},
+    metric::Metric,
+};
+use crate::repository::queries::reading::StationValueRecord;
+
+/// Power of the inverse distance weights
+const IDW_POWER: i32 = 2;
+
+/// A cell this close to a station takes the station's value
+const SAME_POINT_KM: f64 = 0.001;
+
+#[derive(Serialize, Deserialize)]
+pub struct HeatmapGrid {
+    pub metric: Metric,
+    pub unit: String,
+    pub bbox: BoundingBox,
+    pub rows: usize,
+    pub cols: usize,
+    /// Number of stations the surface was interpolated from
+    pub stations: usize,
+    /// The value at the centre of every cell, row by row from south to north and west to
+    /// east. None for cells further than the maximum distance from every station
+    pub values: Vec<Vec<Option<f32>>>,
+}
+
+/// A point where a contour line crosses the line between two neighbouring cell centres,
+/// identified by the first of the two and the direction towards the other
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Edge {
+    East(usize, usize),
+    North(usize, usize),
+}
+
+type Segment = [(Edge, [f64; 2]); 2];
+
+impl HeatmapGrid {
+    pub fn new(
+        metric: Metric,
+        bbox: BoundingBox,
+        rows: usize,
+        cols: usize,
+        stations: &[StationValueRecord],
+        max_distance_km: Option<f64>,
+    ) -> Self {
+        let mut grid = HeatmapGrid {
+            metric,
+            unit: metric.unit().into(),
+            bbox,
+            rows,
+            cols,
+            stations: stations.len(),
+            values: vec![],
+        };
+
+        grid.values = (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| {
+                        let [lon, lat] = grid.coordinate(row as f64, col as f64);
+                        idw(stations, lat, lon, max_distance_km)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        grid
+    }
+
+    /// Evenly spaced levels between the lowest and highest value of the grid
+    pub fn default_levels(&self, count: usize) -> Vec<f32> {
+        let values = self.values.iter().flatten().flatten();
+        let min = values.clone().copied().fold(f32::INFINITY, f32::min);
+        let max = values.copied().fold(f32::NEG_INFINITY, f32::max);
+        if min >= max {
+            return vec![];
+        }
+
+        let step = (max - min) / (count + 1) as f32;
+        (1..=count).map(|i| min + step * i as f32).collect()
+    }
+
+    /// Contour lines of every level, as a feature per level. Longitudes run on eastwards
+    /// from the west of the box, so they exceed 180 for boxes that cross the antimeridian
+    pub fn contours(&self, levels: &[f32]) -> FeatureCollection {
+        let features = levels
+            .iter()
+            .filter_map(|&level| {
+                let lines = self.contour_lines(level);
+                if lines.is_empty() {
+                    return None;
+                }
+
+                Some(Feature {
+                    geometry: Geometry::MultiLineString { coordinates: lines },
+                    properties: json!({
+                        "metric": self.metric,
+                        "unit": self.unit,
+                        "level": level,
+                    }),
+                })
+            })
+            .collect();
+
+        FeatureCollection { features }
+    }
+
+    /// `[longitude, latitude]` of the centre of a cell, rows and columns may be fractional
+    fn coordinate(&self, row: f64, col: f64) -> [f64; 2] {
+        let lat_step = (self.bbox.max_lat - self.bbox.min_lat) / self.rows as f64;
+        let lon_step = self.bbox.lon_span() / self.cols as f64;
+
+        [
+            self.bbox.min_lon + (col + 0.5) * lon_step,
+            self.bbox.min_lat + (row + 0.5) * lat_step,
+        ]
+    }
+
+    /// Marching squares over the cell centres, skipping squares with a missing value
+    fn contour_lines(&self, level: f32) -> Vec<Vec<[f64; 2]>> {
+        let mut segments: Vec<Segment> = vec![];
+
+        for row in 0..self.rows.saturating_sub(1) {
+            for col in 0..self.cols.saturating_sub(1) {
+                let (Some(bl), Some(br), Some(tr), Some(tl)) = (
+                    self.values[row][col],
+                    self.values[row][col + 1],
+                    self.values[row + 1][col + 1],
+                    self.values[row + 1][col],
+                ) else {
+                    continue;
+                };
+
+                let t = |a: f32, b: f32| {
+                    if a == b {
+                        0.5
+                    } else {
+                        ((level - a) / (b - a)) as f64
+                    }
+                };
+                let (r, c) = (row as f64, col as f64);
+                let bottom = (Edge::East(row, col), self.coordinate(r, c + t(bl, br)));
+                let top = (
+                    Edge::East(row + 1, col),
+                    self.coordinate(r + 1.0, c + t(tl, tr)),
+                );
+                let left = (Edge::North(row, col), self.coordinate(r + t(bl, tl), c));
+                let right = (
+                    Edge::North(row, col + 1),
+                    self.coordinate(r + t(br, tr), c + 1.0),
+                );
+
+                let case = [tl, tr, br, bl]
+                    .iter()
+                    .fold(0u8, |case, &value| case << 1 | (value >= level) as u8);
+                let centre_above = (bl + br + tr + tl) / 4.0 >= level;
+
+                match case {
+                    1 | 14 => segments.push([left, bottom]),
+                    2 | 13 => segments.push([bottom, right]),
+                    3 | 12 => segments.push([left, right]),
+                    4 | 11 => segments.push([right, top]),
+                    6 | 9 => segments.push([bottom, top]),
+                    7 | 8 => segments.push([left, top]),
+                    // Saddles, the centre decides which corners are connected
+                    5 | 10 if (case == 5) == centre_above => {
+                        segments.push([bottom, right]);
+                        segments.push([left, top]);
+                    }
+                    5 | 10 => {
+                        segments.push([left, bottom]);
+                        segments.push([right, top]);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        stitch(&segments)
+    }
+}
+
+/// Interpolate the value at a coordinate from the stations within `max_distance_km`
+fn idw(
+    stations: &[StationValueRecord],
+    lat: f64,
+    lon: f64,
+    max_distance_km: Option<f64>,
+) -> Option<f32> {
+    let lon = normalize_lon(lon);
+    let mut sum = 0.0;
+    let mut weights = 0.0;
+
+    for station in stations {
+        let distance = haversine_km(lat, lon, station.latitude.into(), station.longitude.into());
+        if max_distance_km.is_some_and(|max| distance > max) {
+            continue;
+        }
+        if distance < SAME_POINT_KM {
+            return Some(station.value);
+        }
+
+        let weight = 1.0 / distance.powi(IDW_POWER);
+        sum += weight * station.value as f64;
+        weights += weight;
+    }
+
+    (weights > 0.0).then(|| (sum / weights) as f32)
+}
+
+/// Join the segments that share an edge into lines. Every edge is shared by at most two
+/// segments, lines that close on themselves end where they start
+fn stitch(segments: &[Segment]) -> Vec<Vec<[f64; 2]>> {
+    let mut by_edge: HashMap<Edge, Vec<usize>> = HashMap::new();
+    for (i, segment) in segments.iter().enumerate() {
+        for (edge, _) in segment {
+            by_edge.entry(*edge).or_default().push(i);
+        }
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut lines = vec![];
+
+    for start in 0..segments.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        let mut line: VecDeque<(Edge, [f64; 2])> = segments[start].iter().copied().collect();
+
+        for at_front in [false, true] {
+            loop {
+                let end = if at_front { line.front() } else { line.back() };
+                let Some(&(edge, _)) = end else { break };
+                let Some(next) = by_edge[&edge].iter().copied().find(|&i| !used[i]) else {
+                    break;
+                };
+                used[next] = true;
+
+                let [a, b] = segments[next];
+                let other = if a.0 == edge { b } else { a };
+                if at_front {
+                    line.push_front(other);
+                } else {
+                    line.push_back(other);
+                }
+            }
+        }
+
+        lines.push(line.into_iter().map(|(_, point)| point).collect());
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid over a box of one degree per cell, so cell centres lie at `x.5`
+    fn grid(values: Vec<Vec<Option<f32>>>) -> HeatmapGrid {
+        let rows = values.len();
+        let cols = values[0].len();
+
+        HeatmapGrid {
+            metric: Metric::Pm25,
+            unit: Metric::Pm25.unit().into(),
+            bbox: BoundingBox {
+                min_lat: 0.0,
+                min_lon: 0.0,
+                max_lat: rows as f64,
+                max_lon: cols as f64,
+            },
+            rows,
+            cols,
+            stations: 0,
+            values,
+        }
+    }
+
+    fn values(rows: &[&[f32]]) -> Vec<Vec<Option<f32>>> {
+        rows.iter()
+            .map(|row| row.iter().copied().map(Some).collect())
+            .collect()
+    }
+
+    /// Values are interpolated in f32, so points are compared with some tolerance
+    fn contains(line: &[[f64; 2]], point: [f64; 2]) -> bool {
+        line.iter()
+            .any(|p| (p[0] - point[0]).abs() < 1e-6 && (p[1] - point[1]).abs() < 1e-6)
+    }
+
+    fn station(latitude: f32, longitude: f32, value: f32) -> StationValueRecord {
+        StationValueRecord {
+            latitude,
+            longitude,
+            value,
+        }
+    }
+
+    #[test]
+    fn straight_line_between_columns() {
+        let lines = grid(values(&[&[0.0, 10.0], &[0.0, 10.0]])).contour_lines(5.0);
+
+        assert_eq!(lines, [vec![[1.0, 0.5], [1.0, 1.5]]]);
+    }
+
+    #[test]
+    fn crossings_are_interpolated_linearly() {
+        let lines = grid(values(&[&[0.0, 4.0], &[0.0, 4.0]])).contour_lines(1.0);
+
+        assert_eq!(lines, [vec![[0.75, 0.5], [0.75, 1.5]]]);
+    }
+
+    #[test]
+    fn peak_is_enclosed_by_a_closed_line() {
+        let lines = grid(values(&[
+            &[0.0, 0.0, 0.0],
+            &[0.0, 10.0, 0.0],
+            &[0.0, 0.0, 0.0],
+        ]))
+        .contour_lines(5.0);
+
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert_eq!(line.len(), 5);
+        assert_eq!(line.first(), line.last());
+        for point in [[1.0, 1.5], [1.5, 1.0], [2.0, 1.5], [1.5, 2.0]] {
+            assert!(contains(line, point), "{point:?}");
+        }
+    }
+
+    #[test]
+    fn saddles_follow_the_centre_value() {
+        // Diagonal corners above, the centre averages 5
+        let saddle = grid(values(&[&[10.0, 0.0], &[0.0, 10.0]]));
+
+        // Centre above the level: the high corners are connected, cutting off the low ones
+        let lines = saddle.contour_lines(4.0);
+        assert_eq!(lines.len(), 2);
+        assert!(lines
+            .iter()
+            .any(|line| contains(line, [1.1, 0.5]) && contains(line, [1.5, 0.9])));
+
+        // Centre below the level: the high corners are cut off
+        let lines = saddle.contour_lines(6.0);
+        assert_eq!(lines.len(), 2);
+        assert!(lines
+            .iter()
+            .any(|line| contains(line, [0.9, 0.5]) && contains(line, [0.5, 0.9])));
+    }
+
+    #[test]
+    fn squares_with_missing_values_are_skipped() {
+        let mut values = values(&[&[0.0, 10.0], &[0.0, 10.0]]);
+        values[1][0] = None;
+
+        assert!(grid(values).contour_lines(5.0).is_empty());
+    }
+
+    #[test]
+    fn levels_outside_of_the_values_have_no_feature() {
+        let grid = grid(values(&[&[0.0, 10.0], &[0.0, 10.0]]));
+
+        assert!(grid.contours(&[20.0, -1.0]).features.is_empty());
+        assert_eq!(grid.contours(&[2.0, 20.0, 8.0]).features.len(), 2);
+    }
+
+    #[test]
+    fn default_levels_split_the_range_evenly() {
+        let grid_values = values(&[&[0.0, 10.0], &[4.0, 6.0]]);
+
+        assert_eq!(grid(grid_values).default_levels(4), [2.0, 4.0, 6.0, 8.0]);
+        assert!(grid(values(&[&[3.0, 3.0]])).default_levels(4).is_empty());
+    }
+
+    #[test]
+    fn idw_weighs_by_inverse_squared_distance() {
+        let stations = [station(0.0, 0.0, 10.0), station(0.0, 2.0, 40.0)];
+
+        // Equally far from both
+        let value = idw(&stations, 0.0, 1.0, None).unwrap();
+        assert!((value - 25.0).abs() < 1e-3);
+
+        // A station at the point itself decides
+        assert_eq!(idw(&stations, 0.0, 2.0, None), Some(40.0));
+
+        // Stations beyond the maximum distance are ignored
+        assert_eq!(idw(&stations, 0.0, 0.5, Some(100.0)), Some(10.0));
+        assert_eq!(idw(&stations, 10.0, 10.0, Some(100.0)), None);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{ops::RangeInclusive, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// One of the values measured by a station
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    Temperature,
+    Humidity,
+    Pm10,
+    Pm25,
+    Co2,
+    Voc,
+}
+
+impl Metric {
+    /// Every metric, in the order of the columns of the `readings` table
+    pub const ALL: [Metric; 6] = [
+        Metric::Temperature,
+        Metric::Humidity,
+        Metric::Pm10,
+        Metric::Pm25,
+        Metric::Co2,
+        Metric::Voc,
+    ];
+
+    /// Name of the column in the `readings` table
+    pub fn column(self) -> &'static str {
+        match self {
+            Metric::Temperature => "temperature",
+            Metric::Humidity => "humidity",
+            Metric::Pm10 => "pm10",
+            Metric::Pm25 => "pm25",
+            Metric::Co2 => "co2",
+            Metric::Voc => "voc",
+        }
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self {
+            Metric::Temperature => "°C",
+            Metric::Humidity => "%",
+            Metric::Pm10 | Metric::Pm25 => "µg/m³",
+            Metric::Co2 => "ppm",
+            Metric::Voc => "ppb",
+        }
+    }
+
+    /// Values a sensor can plausibly report, anything outside is a sensor fault
+    pub fn range(self) -> RangeInclusive<f32> {
+        match self {
+            Metric::Temperature => -100.0..=100.0,
+            Metric::Humidity => 0.0..=100.0,
+            Metric::Pm10 | Metric::Pm25 => 0.0..=10_000.0,
+            Metric::Co2 | Metric::Voc => 0.0..=100_000.0,
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = Error;
+
+    /// Parse the name of a column of the `readings` table
+    fn from_str(s: &str) -> Result<Self> {
+        Metric::ALL
+            .into_iter()
+            .find(|metric| metric.column() == s)
+            .ok_or_else(|| Error::Validation(format!("unknown metric {s:?}")))
+    }
+}
@@ -1,3 +1,19 @@
 pub mod location;
+pub mod health;
+pub mod schema;
 pub mod station;
-pub mod reading;
\ No newline at end of file
+pub mod reading;
+pub mod aggregate;
+pub mod aqi;
+pub mod nowcast;
+pub mod regulatory;
+pub mod metric;
+pub mod group;
+pub mod geo;
+pub mod geojson;
+pub mod heatmap;
+pub mod csv;
+pub mod page;
+pub mod alert;
+pub mod webhook;
+pub mod nonce;
\ No newline at end of file
@@ -0,0 +1,10 @@
+use chrono::{DateTime, Utc};
+
+/// The nonce of a verified signed payload. It is stored together with what the payload
+/// carried, so a payload that failed to be stored can be sent again
+pub struct Nonce {
+    pub station_id: i32,
+    pub value: String,
+    /// The station's nonces used before this are forgotten
+    pub expire_before: DateTime<Utc>,
+}
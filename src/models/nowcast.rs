@@ -0,0 +1,122 @@
+//! The US EPA NowCast, a weighted average of the last 12 hours that follows sudden changes
+//! in particulate matter much closer than a plain mean
+
+use serde::{Deserialize, Serialize};
+
+use crate::repository::queries::reading::HourlyAverageRecord;
+
+/// Number of hourly averages the NowCast is computed from
+pub const NOWCAST_HOURS: i64 = 12;
+
+/// Lowest weight factor allowed for particulate matter
+const MIN_WEIGHT: f32 = 0.5;
+
+#[derive(Serialize, Deserialize)]
+pub struct NowCast {
+    pub pm25: f32,
+    pub pm10: f32,
+}
+
+impl NowCast {
+    /// Returns None unless at least two of the three most recent hours have readings
+    pub fn new(hours: &[HourlyAverageRecord]) -> Option<Self> {
+        let recent = hours.iter().filter(|h| (0..3).contains(&h.hour)).count();
+        if recent < 2 {
+            return None;
+        }
+
+        let pm25 = weighted_average(hours, |h| h.pm25);
+        let pm10 = weighted_average(hours, |h| h.pm10);
+
+        Some(NowCast {
+            pm25: (pm25 * 10.0).floor() / 10.0,
+            pm10: pm10.floor(),
+        })
+    }
+}
+
+/// Weigh every hour by `w^hour`, where `w` is the ratio of the lowest to the highest
+/// hourly average, but at least `MIN_WEIGHT`
+fn weighted_average(
+    hours: &[HourlyAverageRecord],
+    value: impl Fn(&HourlyAverageRecord) -> f32,
+) -> f32 {
+    let hours: Vec<_> = hours
+        .iter()
+        .filter(|h| (0..NOWCAST_HOURS as i32).contains(&h.hour))
+        .map(|h| (h.hour, value(h).max(0.0)))
+        .collect();
+
+    let min = hours.iter().map(|(_, v)| *v).fold(f32::INFINITY, f32::min);
+    let max = hours.iter().map(|(_, v)| *v).fold(0.0, f32::max);
+    let weight = if max > 0.0 {
+        (min / max).max(MIN_WEIGHT)
+    } else {
+        1.0
+    };
+
+    let (sum, weights) = hours.iter().fold((0.0, 0.0), |(sum, weights), (hour, v)| {
+        let factor = weight.powi(*hour);
+        (sum + factor * v, weights + factor)
+    });
+
+    sum / weights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hours(values: &[(i32, f32)]) -> Vec<HourlyAverageRecord> {
+        values
+            .iter()
+            .map(|&(hour, value)| HourlyAverageRecord {
+                hour,
+                count: 60,
+                pm25: value,
+                pm10: value,
+            })
+            .collect()
+    }
+
+    fn nowcast(values: &[(i32, f32)]) -> (f32, f32) {
+        let nowcast = NowCast::new(&hours(values)).expect("enough recent hours");
+        (nowcast.pm25, nowcast.pm10)
+    }
+
+    #[test]
+    fn needs_two_of_the_three_most_recent_hours() {
+        assert!(NowCast::new(&hours(&[])).is_none());
+        assert!(NowCast::new(&hours(&[(0, 10.0)])).is_none());
+        assert!(NowCast::new(&hours(&[(0, 10.0), (3, 10.0), (4, 10.0)])).is_none());
+        assert!(NowCast::new(&hours(&[(1, 10.0), (2, 10.0)])).is_some());
+    }
+
+    #[test]
+    fn steady_concentrations_average_plainly() {
+        assert_eq!(nowcast(&[(0, 12.0), (1, 12.0), (2, 12.0)]), (12.0, 12.0));
+    }
+
+    #[test]
+    fn recent_hours_weigh_more() {
+        // Weight 20 / 40 = 0.5: (40 + 0.5 * 20) / 1.5
+        assert_eq!(nowcast(&[(0, 40.0), (1, 20.0)]), (33.3, 33.0));
+    }
+
+    #[test]
+    fn weight_is_at_least_one_half() {
+        // 10 / 100 would weigh the older hour at 0.1
+        assert_eq!(nowcast(&[(0, 100.0), (1, 10.0)]), (70.0, 70.0));
+    }
+
+    #[test]
+    fn missing_hours_keep_their_place_in_the_weights() {
+        // (30 + 0.5^2 * 10) / (1 + 0.5^2)
+        assert_eq!(nowcast(&[(0, 30.0), (2, 10.0)]), (26.0, 26.0));
+    }
+
+    #[test]
+    fn hours_beyond_the_window_are_ignored() {
+        assert_eq!(nowcast(&[(0, 10.0), (1, 10.0), (12, 500.0)]), (10.0, 10.0));
+    }
+}
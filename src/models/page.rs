@@ -0,0 +1,177 @@
+//! Keyset pagination of listings. Pages are ordered by `(date, id)` and a cursor holds the
+//! key of the last item of a page, so pages stay stable while items are added
+
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::PaginationSettings,
+    error::{Error, Result},
+};
+
+use super::{alert::Alert, reading::Reading, station::Station, webhook::WebhookDelivery};
+
+/// Position after the last item of a page, opaque to clients. The date keeps the
+/// microseconds Postgres stores, so no item shares the key of another by rounding
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cursor {
+    pub date: DateTime<Utc>,
+    pub id: i32,
+}
+
+impl From<&Reading> for Cursor {
+    fn from(reading: &Reading) -> Self {
+        Cursor {
+            date: reading.date,
+            id: reading.id,
+        }
+    }
+}
+
+impl From<&Alert> for Cursor {
+    fn from(alert: &Alert) -> Self {
+        Cursor {
+            date: alert.fired_at,
+            id: alert.id,
+        }
+    }
+}
+
+impl From<&WebhookDelivery> for Cursor {
+    fn from(delivery: &WebhookDelivery) -> Self {
+        Cursor {
+            date: delivery.created,
+            id: delivery.id,
+        }
+    }
+}
+
+/// Stations are paged by id alone, the date of their cursors is left at the epoch. Their
+/// `last_online` changes with every reading, it can't keep pages stable
+impl From<&Station> for Cursor {
+    fn from(station: &Station) -> Self {
+        Cursor {
+            date: Utc.timestamp_opt(0, 0).unwrap(),
+            id: station.id,
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key = format!("{}:{}", self.date.timestamp_micros(), self.id);
+        f.write_str(&hex::encode(key))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Validation(format!("invalid cursor {s:?}"));
+        let key = hex::decode(s).map_err(|_| invalid())?;
+        let key = String::from_utf8(key).map_err(|_| invalid())?;
+        let (micros, id) = key.split_once(':').ok_or_else(invalid)?;
+        let micros: i64 = micros.parse().map_err(|_| invalid())?;
+        let nanos = micros.rem_euclid(1_000_000) as u32 * 1000;
+
+        Ok(Cursor {
+            date: Utc
+                .timestamp_opt(micros.div_euclid(1_000_000), nanos)
+                .single()
+                .ok_or_else(invalid)?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A page of a listing, `next` is None on the last page
+#[derive(Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Pass as `cursor` to get the next page
+    pub next: Option<String>,
+}
+
+impl<T> Page<T>
+where
+    for<'a> Cursor: From<&'a T>,
+{
+    /// `items` holds up to `limit + 1` items, the extra one only tells there is another
+    /// page
+    pub fn new(mut items: Vec<T>, limit: i64) -> Self {
+        let more = items.len() as i64 > limit;
+        items.truncate(limit as usize);
+        let next = more
+            .then(|| items.last().map(|last| Cursor::from(last).to_string()))
+            .flatten();
+
+        Page { items, next }
+    }
+}
+
+/// The cursor and page size of a request for a page, page sizes above the configured
+/// maximum are reduced to it
+pub fn page_params(
+    cursor: Option<&str>,
+    limit: Option<i64>,
+    settings: &PaginationSettings,
+) -> Result<(Option<Cursor>, i64)> {
+    let limit = limit.unwrap_or(settings.default_page_size);
+    if limit < 1 {
+        return Err(Error::Validation("limit must be positive".into()));
+    }
+    let cursor = cursor.map(str::parse).transpose()?;
+
+    Ok((cursor, limit.min(settings.max_page_size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips_with_microseconds() {
+        let cursor = Cursor {
+            date: Utc.timestamp_opt(1_700_000_000, 123_456_000).unwrap(),
+            id: 42,
+        };
+
+        assert_eq!(cursor.to_string().parse::<Cursor>().unwrap(), cursor);
+    }
+
+    #[test]
+    fn cursors_within_one_millisecond_differ() {
+        let cursor = |micros: u32| Cursor {
+            date: Utc.timestamp_opt(1_700_000_000, micros * 1000).unwrap(),
+            id: 1,
+        };
+
+        assert_ne!(cursor(123_001).to_string(), cursor(123_002).to_string());
+    }
+
+    #[test]
+    fn cursor_before_the_epoch_round_trips() {
+        let cursor = Cursor {
+            date: Utc.timestamp_opt(-1, 999_999_000).unwrap(),
+            id: 7,
+        };
+
+        assert_eq!(cursor.to_string().parse::<Cursor>().unwrap(), cursor);
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for s in [
+            "",
+            "zz",
+            &hex::encode("123"),
+            &hex::encode("abc:1"),
+            &hex::encode("1:x"),
+        ] {
+            assert!(matches!(s.parse::<Cursor>(), Err(Error::Validation(_))));
+        }
+    }
+}
@@ -1,9 +1,15 @@
-use std::iter::Sum;
-
-use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use chrono::{serde::ts_milliseconds, DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::api::reading::AddReadingRequest;
+use crate::{
+    api::reading::{AddReadingBatchItem, AddReadingRequest},
+    error::{Error, Result},
+    repository::queries::reading::WindowStatisticsRecord,
+};
+
+use super::{
+    aggregate::Window, aqi::Aqi, metric::Metric, nowcast::NowCast, regulatory::RegulatoryAverages,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct Reading {
@@ -20,20 +26,58 @@ pub struct Reading {
     pub voc: f32,
 }
 
+/// The readings within a window, the means and statistics are left out when there
+/// are none
 #[derive(Serialize, Deserialize)]
 pub struct AverageReadingValues {
-    pub temperature: f32,
-    pub humidity: f32,
-    pub pm10: f32,
-    pub pm25: f32,
-    pub co2: f32,
-    pub voc: f32,
+    pub count: i64,
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pm10: Option<f32>,
+    pub pm25: Option<f32>,
+    pub co2: Option<f32>,
+    pub voc: Option<f32>,
+    pub statistics: Option<ReadingStatistics>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReadingStatistics {
+    pub temperature: MetricStatistics,
+    pub humidity: MetricStatistics,
+    pub pm10: MetricStatistics,
+    pub pm25: MetricStatistics,
+    pub co2: MetricStatistics,
+    pub voc: MetricStatistics,
+}
+
+/// The standard deviation is the population standard deviation of the window
+#[derive(Serialize, Deserialize)]
+pub struct MetricStatistics {
+    pub mean: f32,
+    pub min: f32,
+    pub max: f32,
+    pub median: f32,
+    pub p95: f32,
+    pub stddev: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WindowReadingValues {
+    pub window: Window,
+    #[serde(flatten)]
+    pub values: AverageReadingValues,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct AverageReading {
     pub hour: AverageReadingValues,
     pub day: AverageReadingValues,
+    /// The windows the caller asked for, in the order they were given
+    pub windows: Vec<WindowReadingValues>,
+    /// Left out when there are too few readings in the last three hours
+    pub nowcast: Option<NowCast>,
+    pub regulatory: RegulatoryAverages,
+    pub aqi: Aqi,
 }
 
 impl Reading {
@@ -61,6 +105,17 @@ impl Reading {
             voc,
         }
     }
+
+    pub fn value(&self, metric: Metric) -> f32 {
+        match metric {
+            Metric::Temperature => self.temperature,
+            Metric::Humidity => self.humidity,
+            Metric::Pm10 => self.pm10,
+            Metric::Pm25 => self.pm25,
+            Metric::Co2 => self.co2,
+            Metric::Voc => self.voc,
+        }
+    }
 }
 
 impl From<AddReadingRequest> for Reading {
@@ -69,7 +124,7 @@ impl From<AddReadingRequest> for Reading {
             id: 0,
             station_id: 0,
             location_id: None,
-            date: request.date.unwrap_or(Utc::now()),
+            date: request.date,
             temperature: request.temperature,
             humidity: request.humidity,
             pm10: request.pm10,
@@ -80,51 +135,168 @@ impl From<AddReadingRequest> for Reading {
     }
 }
 
-impl AverageReading {
-    pub fn new(hour_readings: Vec<Reading>, day_readings: Vec<Reading>) -> Self {
-        let mut hour = AverageReadingValues::new(hour_readings);
-        let mut day = AverageReadingValues::new(day_readings);
-
-        AverageReading { hour, day }
+impl From<AddReadingBatchItem> for Reading {
+    fn from(item: AddReadingBatchItem) -> Self {
+        Reading::new(
+            0,
+            None,
+            item.date,
+            item.temperature,
+            item.humidity,
+            item.pm10,
+            item.pm25,
+            item.co2,
+            item.voc,
+        )
     }
 }
 
-impl AverageReadingValues {
-    pub fn new(values: Vec<Reading>) -> Self {
-        let mut result = AverageReadingValues::default();
-
-        for val in &values {
-            result.temperature += val.temperature;
-            result.humidity += val.humidity;
-            result.pm10 += val.pm10;
-            result.pm25 += val.pm25;
-            result.co2 += val.co2;
-            result.voc += val.co2;
+impl Reading {
+    /// Fails if a measured value lies outside of the range of its metric
+    pub fn validate(&self) -> Result<()> {
+        for metric in Metric::ALL {
+            let range = metric.range();
+            if !range.contains(&self.value(metric)) {
+                return Err(Error::Validation(format!(
+                    "{} must be between {} and {} {}",
+                    metric.column(),
+                    range.start(),
+                    range.end(),
+                    metric.unit()
+                )));
+            }
         }
 
-        if !values.is_empty() {
-            let size = values.len() as f32;
-            result.temperature /= size;
-            result.humidity /= size;
-            result.pm10 /= size;
-            result.pm25 /= size;
-            result.co2 /= size;
-            result.voc /= size;
+        Ok(())
+    }
+
+    /// Fails if the reading is dated further ahead of `now` than the clock of a station may
+    /// be off. Such a reading would stay the latest one of its station
+    pub fn validate_date(&self, now: DateTime<Utc>, max_skew: Duration) -> Result<()> {
+        if self.date > now + max_skew {
+            return Err(Error::Validation("date is in the future".into()));
         }
 
-        result
+        Ok(())
     }
 }
 
-impl Default for AverageReadingValues {
-    fn default() -> Self {
+impl AverageReading {
+    pub fn new(
+        hour: AverageReadingValues,
+        day: AverageReadingValues,
+        windows: Vec<WindowReadingValues>,
+        nowcast: Option<NowCast>,
+        regulatory: RegulatoryAverages,
+    ) -> Self {
+        let aqi = Aqi::new(&hour, &day, nowcast.as_ref());
+
+        AverageReading {
+            hour,
+            day,
+            windows,
+            nowcast,
+            regulatory,
+            aqi,
+        }
+    }
+}
+
+impl AverageReadingValues {
+    pub fn empty() -> Self {
+        AverageReadingValues {
+            count: 0,
+            temperature: None,
+            humidity: None,
+            pm10: None,
+            pm25: None,
+            co2: None,
+            voc: None,
+            statistics: None,
+        }
+    }
+}
+
+impl From<&WindowStatisticsRecord> for AverageReadingValues {
+    fn from(rec: &WindowStatisticsRecord) -> Self {
+        let metric = |mean: Option<f32>,
+                      min: Option<f32>,
+                      max: Option<f32>,
+                      median: Option<f32>,
+                      p95: Option<f32>,
+                      stddev: Option<f32>| {
+            Some(MetricStatistics {
+                mean: mean?,
+                min: min?,
+                max: max?,
+                median: median?,
+                p95: p95?,
+                stddev: stddev?,
+            })
+        };
+
+        let statistics = || {
+            Some(ReadingStatistics {
+                temperature: metric(
+                    rec.temperature_mean,
+                    rec.temperature_min,
+                    rec.temperature_max,
+                    rec.temperature_median,
+                    rec.temperature_p95,
+                    rec.temperature_stddev,
+                )?,
+                humidity: metric(
+                    rec.humidity_mean,
+                    rec.humidity_min,
+                    rec.humidity_max,
+                    rec.humidity_median,
+                    rec.humidity_p95,
+                    rec.humidity_stddev,
+                )?,
+                pm10: metric(
+                    rec.pm10_mean,
+                    rec.pm10_min,
+                    rec.pm10_max,
+                    rec.pm10_median,
+                    rec.pm10_p95,
+                    rec.pm10_stddev,
+                )?,
+                pm25: metric(
+                    rec.pm25_mean,
+                    rec.pm25_min,
+                    rec.pm25_max,
+                    rec.pm25_median,
+                    rec.pm25_p95,
+                    rec.pm25_stddev,
+                )?,
+                co2: metric(
+                    rec.co2_mean,
+                    rec.co2_min,
+                    rec.co2_max,
+                    rec.co2_median,
+                    rec.co2_p95,
+                    rec.co2_stddev,
+                )?,
+                voc: metric(
+                    rec.voc_mean,
+                    rec.voc_min,
+                    rec.voc_max,
+                    rec.voc_median,
+                    rec.voc_p95,
+                    rec.voc_stddev,
+                )?,
+            })
+        };
+
         AverageReadingValues {
-            temperature: 0.0,
-            humidity: 0.0,
-            pm10: 0.0,
-            pm25: 0.0,
-            co2: 0.0,
-            voc: 0.0,
+            count: rec.count,
+            temperature: rec.temperature_mean,
+            humidity: rec.humidity_mean,
+            pm10: rec.pm10_mean,
+            pm25: rec.pm25_mean,
+            co2: rec.co2_mean,
+            voc: rec.voc_mean,
+            statistics: statistics(),
         }
     }
 }
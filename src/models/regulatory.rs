@@ -0,0 +1,159 @@
+//! Averages following the regulatory definitions used in reports: the mean of hourly
+//! values over complete clock hours, valid only when at least 75% of the hours have data
+//! (EU Directive 2008/50/EC, Annex VII)
+
+use chrono::{serde::ts_milliseconds, DateTime, Duration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::repository::queries::reading::ClockHourAverageRecord;
+
+/// Hours in the rolling CO2 window
+pub const CO2_ROLLING_HOURS: i64 = 8;
+
+/// Hours in the daily particulate matter window
+pub const PM_DAILY_HOURS: i64 = 24;
+
+/// Share of the hours in a window that need readings for its average to be valid
+const MIN_COVERAGE: f64 = 0.75;
+
+#[derive(Serialize, Deserialize)]
+pub struct RegulatoryAverage {
+    #[serde(with = "ts_milliseconds")]
+    pub start: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    pub end: DateTime<Utc>,
+    /// Number of hours in the window that have readings
+    pub hours: i64,
+    /// Mean of the hourly means, left out when too few hours have readings
+    pub value: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RegulatoryAverages {
+    /// 8-hour running mean of CO2, ending at the last complete hour
+    pub co2_8h: RegulatoryAverage,
+    /// 24-hour mean of PM2.5, ending at the last complete hour
+    pub pm25_24h: RegulatoryAverage,
+    /// 24-hour mean of PM10, ending at the last complete hour
+    pub pm10_24h: RegulatoryAverage,
+}
+
+impl RegulatoryAverages {
+    /// The start of the first clock hour needed to compute the averages ending before `now`
+    pub fn start(now: DateTime<Utc>) -> DateTime<Utc> {
+        Self::end(now) - Duration::hours(CO2_ROLLING_HOURS.max(PM_DAILY_HOURS))
+    }
+
+    /// The end of the last complete clock hour
+    pub fn end(now: DateTime<Utc>) -> DateTime<Utc> {
+        now.duration_trunc(Duration::hours(1)).unwrap_or(now)
+    }
+
+    /// `hours` holds the hourly means since `start(now)`
+    pub fn new(hours: &[ClockHourAverageRecord], now: DateTime<Utc>) -> Self {
+        let end = Self::end(now);
+
+        RegulatoryAverages {
+            co2_8h: RegulatoryAverage::new(hours, end, CO2_ROLLING_HOURS, |h| h.co2),
+            pm25_24h: RegulatoryAverage::new(hours, end, PM_DAILY_HOURS, |h| h.pm25),
+            pm10_24h: RegulatoryAverage::new(hours, end, PM_DAILY_HOURS, |h| h.pm10),
+        }
+    }
+}
+
+impl RegulatoryAverage {
+    fn new(
+        hours: &[ClockHourAverageRecord],
+        end: DateTime<Utc>,
+        window: i64,
+        value: impl Fn(&ClockHourAverageRecord) -> f32,
+    ) -> Self {
+        let start = end - Duration::hours(window);
+        let values: Vec<f32> = hours
+            .iter()
+            .filter(|h| h.hour >= start && h.hour < end)
+            .map(value)
+            .collect();
+
+        let count = values.len() as i64;
+        let covered = count as f64 >= (window as f64 * MIN_COVERAGE).ceil();
+
+        RegulatoryAverage {
+            start,
+            end,
+            hours: count,
+            value: covered.then(|| values.iter().sum::<f32>() / count as f32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 18, 13, 47, 12).unwrap()
+    }
+
+    /// One record per hour, `hours_ago` counted from the end of the last complete hour
+    fn hours(hours_ago: impl IntoIterator<Item = i64>, value: f32) -> Vec<ClockHourAverageRecord> {
+        let end = RegulatoryAverages::end(now());
+        hours_ago
+            .into_iter()
+            .map(|ago| ClockHourAverageRecord {
+                hour: end - Duration::hours(ago),
+                pm25: value,
+                pm10: value * 2.0,
+                co2: value * 100.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn windows_end_at_the_last_complete_hour() {
+        let end = Utc.with_ymd_and_hms(2026, 10, 18, 13, 0, 0).unwrap();
+
+        assert_eq!(RegulatoryAverages::end(now()), end);
+        assert_eq!(RegulatoryAverages::end(end), end);
+        assert_eq!(RegulatoryAverages::start(now()), end - Duration::hours(24));
+    }
+
+    #[test]
+    fn needs_three_quarters_of_the_hours() {
+        let averages = RegulatoryAverages::new(&hours(1..=6, 4.0), now());
+        assert_eq!(averages.co2_8h.hours, 6);
+        assert_eq!(averages.co2_8h.value, Some(400.0));
+        assert_eq!(averages.pm25_24h.value, None);
+
+        let averages = RegulatoryAverages::new(&hours(1..=5, 4.0), now());
+        assert_eq!(averages.co2_8h.value, None);
+
+        let averages = RegulatoryAverages::new(&hours(1..=18, 4.0), now());
+        assert_eq!(averages.pm25_24h.value, Some(4.0));
+        assert_eq!(averages.pm10_24h.value, Some(8.0));
+    }
+
+    #[test]
+    fn averages_the_hourly_means() {
+        let mut records = hours(1..=4, 10.0);
+        records.extend(hours(5..=8, 20.0));
+
+        let co2 = RegulatoryAverages::new(&records, now()).co2_8h;
+        assert_eq!(co2.hours, 8);
+        assert_eq!(co2.value, Some(1500.0));
+    }
+
+    #[test]
+    fn ignores_hours_outside_of_the_window() {
+        // The current, incomplete hour and the hour before the window start
+        let mut records = hours(1..=6, 10.0);
+        records.extend(hours([0, 9], 1000.0));
+
+        let co2 = RegulatoryAverages::new(&records, now()).co2_8h;
+        assert_eq!(co2.hours, 6);
+        assert_eq!(co2.value, Some(1000.0));
+        assert_eq!(co2.end - co2.start, Duration::hours(8));
+    }
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct SchemaStatus {
+    /// Newest migration applied to the database
+    pub schema_version: Option<i64>,
+    /// Newest migration embedded in this build
+    pub latest_version: Option<i64>,
+    pub pending_migrations: Vec<i64>,
+    /// Applied migrations this build doesn't know about
+    pub unknown_migrations: Vec<i64>,
+    /// Migration that failed halfway and has to be fixed by hand
+    pub dirty_version: Option<i64>,
+}
+
+impl SchemaStatus {
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending_migrations.is_empty()
+            && self.unknown_migrations.is_empty()
+            && self.dirty_version.is_none()
+    }
+}
@@ -22,6 +22,14 @@ pub struct Station {
     pub last_online: DateTime<Utc>,
 }
 
+/// What a station authenticates with, only shown when it is issued. The secret is sent
+/// as a bearer token, the signing key signs readings
+#[derive(Serialize, Deserialize)]
+pub struct StationCredentials {
+    pub secret: String,
+    pub signing_key: String,
+}
+
 impl Station {
     pub fn new(token: impl Into<String>, hw_version: i32, sw_version: i32) -> Self {
         let mut station = Station::default();
@@ -0,0 +1,362 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use chrono::{
+    serde::{ts_milliseconds, ts_milliseconds_option},
+    DateTime, Utc,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    events::{Event, ReadingEvent, StationStatusEvent},
+    repository::queries::webhook::{DueDeliveryRecord, WebhookDeliveryRecord, WebhookRecord},
+};
+
+use super::alert::Alert;
+
+/// What a webhook can be notified of
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Reading,
+    AlertFired,
+    AlertResolved,
+    StationOnline,
+    StationOffline,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 5] = [
+        WebhookEvent::Reading,
+        WebhookEvent::AlertFired,
+        WebhookEvent::AlertResolved,
+        WebhookEvent::StationOnline,
+        WebhookEvent::StationOffline,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Reading => "reading",
+            WebhookEvent::AlertFired => "alert_fired",
+            WebhookEvent::AlertResolved => "alert_resolved",
+            WebhookEvent::StationOnline => "station_online",
+            WebhookEvent::StationOffline => "station_offline",
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        WebhookEvent::ALL
+            .into_iter()
+            .find(|event| event.as_str() == s)
+            .ok_or_else(|| Error::Validation(format!("unknown webhook event {s:?}")))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// Given up after `webhooks.max_attempts` failed attempts
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(DeliveryStatus::Pending),
+            "delivered" => Ok(DeliveryStatus::Delivered),
+            "failed" => Ok(DeliveryStatus::Failed),
+            _ => Err(Error::Validation(format!("unknown delivery status {s:?}"))),
+        }
+    }
+}
+
+/// A URL that is sent the events it subscribed to, of one station or of every station.
+/// The signing secret is only shown when the webhook is created
+#[derive(Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Token of the station the webhook is limited to
+    pub station: Option<String>,
+    pub enabled: bool,
+    #[serde(with = "ts_milliseconds")]
+    pub created: DateTime<Utc>,
+}
+
+/// Body of a webhook request, e.g. `{"event": "alert_fired", "data": {...}}`
+#[derive(Serialize)]
+pub struct WebhookPayload<'a> {
+    pub event: WebhookEvent,
+    pub data: WebhookData<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum WebhookData<'a> {
+    Reading(&'a ReadingEvent),
+    StationStatus(&'a StationStatusEvent),
+    Alert(&'a Alert),
+}
+
+/// An attempt to send an event to a webhook, as shown to its owner
+#[derive(Serialize)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: WebhookEvent,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    /// Left out unless the delivery is pending
+    #[serde(with = "ts_milliseconds_option")]
+    pub next_attempt: Option<DateTime<Utc>>,
+    #[serde(with = "ts_milliseconds_option")]
+    pub last_attempt: Option<DateTime<Utc>>,
+    /// Status code of the last response
+    pub response_status: Option<i32>,
+    /// Why the last attempt failed
+    pub error: Option<String>,
+    #[serde(with = "ts_milliseconds")]
+    pub created: DateTime<Utc>,
+    pub payload: serde_json::Value,
+}
+
+/// An event to send to the webhooks that subscribed to it
+pub struct QueuedEvent {
+    pub event: WebhookEvent,
+    pub dedupe_key: String,
+    pub payload: String,
+    /// Token of the station of the event
+    pub station: String,
+}
+
+/// A delivery that is due, claimed by this instance for sending
+pub struct DueDelivery {
+    pub id: i32,
+    pub event: WebhookEvent,
+    pub payload: String,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+impl<'a> WebhookPayload<'a> {
+    pub fn new(event: &'a Event) -> Self {
+        match event {
+            Event::Reading(event) => WebhookPayload {
+                event: WebhookEvent::Reading,
+                data: WebhookData::Reading(event),
+            },
+            Event::StationStatus(event) => WebhookPayload {
+                event: if event.online {
+                    WebhookEvent::StationOnline
+                } else {
+                    WebhookEvent::StationOffline
+                },
+                data: WebhookData::StationStatus(event),
+            },
+            Event::Alert(alert) => WebhookPayload {
+                event: if alert.resolved_at.is_some() {
+                    WebhookEvent::AlertResolved
+                } else {
+                    WebhookEvent::AlertFired
+                },
+                data: WebhookData::Alert(alert),
+            },
+        }
+    }
+
+    pub fn queued(&self, station: &str) -> Result<QueuedEvent> {
+        Ok(QueuedEvent {
+            event: self.event,
+            dedupe_key: self.dedupe_key(),
+            payload: serde_json::to_string(self)?,
+            station: station.to_string(),
+        })
+    }
+
+    /// Identifies the event, so it is queued once even when several instances see it
+    pub fn dedupe_key(&self) -> String {
+        match &self.data {
+            WebhookData::Reading(event) => format!("reading:{}", event.reading.id),
+            WebhookData::StationStatus(event) => format!(
+                "{}:{}:{}",
+                self.event.as_str(),
+                event.station,
+                event.last_online.timestamp_millis()
+            ),
+            WebhookData::Alert(alert) => format!("{}:{}", self.event.as_str(), alert.id),
+        }
+    }
+}
+
+/// Whether the address is reachable on the internet, as opposed to loopback, private,
+/// link-local (cloud metadata services among them), shared or reserved addresses
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network" 0.0.0.0/8, shared address space 100.64.0.0/10 and reserved 240.0.0.0/4
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local fc00::/7, link-local fe80::/10 and documentation 2001:db8::/32
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+impl TryFrom<WebhookRecord> for Webhook {
+    type Error = Error;
+
+    fn try_from(rec: WebhookRecord) -> Result<Self> {
+        Ok(Webhook {
+            id: rec.id,
+            url: rec.url,
+            events: rec
+                .events
+                .iter()
+                .map(|event| event.parse())
+                .collect::<Result<_>>()?,
+            station: rec.station,
+            enabled: rec.enabled,
+            created: rec.created,
+        })
+    }
+}
+
+impl TryFrom<WebhookDeliveryRecord> for WebhookDelivery {
+    type Error = Error;
+
+    fn try_from(rec: WebhookDeliveryRecord) -> Result<Self> {
+        let status: DeliveryStatus = rec.status.parse()?;
+
+        Ok(WebhookDelivery {
+            id: rec.id,
+            webhook_id: rec.webhook_id,
+            event: rec.event.parse()?,
+            status,
+            attempts: rec.attempts,
+            next_attempt: (status == DeliveryStatus::Pending).then_some(rec.next_attempt),
+            last_attempt: rec.last_attempt,
+            response_status: rec.response_status,
+            error: rec.error,
+            created: rec.created,
+            payload: serde_json::from_str(&rec.payload)?,
+        })
+    }
+}
+
+impl TryFrom<DueDeliveryRecord> for DueDelivery {
+    type Error = Error;
+
+    fn try_from(rec: DueDeliveryRecord) -> Result<Self> {
+        Ok(DueDelivery {
+            id: rec.id,
+            event: rec.event.parse()?,
+            payload: rec.payload,
+            attempts: rec.attempts,
+            url: rec.url,
+            secret: rec.secret,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public_address(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for ip in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "100.128.0.1",
+            "2606:4700:4700::1111",
+        ] {
+            assert!(public(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn internal_v4_addresses_are_not_public() {
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "255.255.255.255",
+            "224.0.0.1",
+            "192.0.2.1",
+            "240.0.0.1",
+        ] {
+            assert!(!public(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn internal_v6_addresses_are_not_public() {
+        for ip in ["::", "::1", "fd00::1", "fe80::1", "ff02::1", "2001:db8::1"] {
+            assert!(!public(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn mapped_v4_addresses_are_checked_as_v4() {
+        assert!(!public("::ffff:127.0.0.1"));
+        assert!(!public("::ffff:169.254.169.254"));
+        assert!(public("::ffff:1.1.1.1"));
+    }
+}
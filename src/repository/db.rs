@@ -1,22 +1,50 @@
 use crate::{
-    config::Config,
+    config::{Config, Settings},
+    error::Result,
+    events::{Event, EventBus, ReadingEvent, ReadingSubscription, Subscription, NOTIFY_CHANNEL},
     models::{
+        aggregate::{Bucket, ComparisonSeries, ReadingBucket, Window},
+        alert::{Alert, AlertRule, AlertState, AlertTransition},
+        aqi::{Aqi, AQI_HOURS},
+        geo::BoundingBox,
+        group::StationGroup,
+        heatmap::HeatmapGrid,
         location::Location,
+        metric::Metric,
+        nonce::Nonce,
+        page::{Cursor, Page},
         reading::{AverageReading, Reading},
         station::Station,
+        webhook::{
+            DeliveryStatus, DueDelivery, QueuedEvent, Webhook, WebhookDelivery, WebhookEvent,
+        },
     },
 };
-use anyhow::Result;
 use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
-use log::debug;
+use futures_util::{StreamExt, TryStreamExt};
+use log::{debug, error};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
 
-use super::query::Query;
+use super::{
+    queries::{
+        migration::AppliedMigrationsRecord,
+        reading::{HourlyAverageRecord, PutReadingRequest, PutReadingsRecord},
+        station::ActiveStationReadingRecord,
+    },
+    query::{Query, RowStream},
+};
+
+/// Part of the height and width of a heatmap that is added on every side when looking for
+/// the stations to interpolate from
+const HEATMAP_MARGIN: f64 = 0.5;
 
 pub struct DBRepository {
     pool: Pool<Postgres>,
     query: Query,
+    settings: Settings,
+    events: EventBus,
 }
 
 impl DBRepository {
@@ -24,9 +52,36 @@ impl DBRepository {
         DBRepository {
             pool: config.pool.clone(),
             query: Query::new(config.pool),
+            settings: config.settings,
+            events: config.events,
         }
     }
 
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Number of open connections, idle connections and the maximum the pool may open
+    pub fn pool_usage(&self) -> (u32, usize, u32) {
+        (
+            self.pool.size(),
+            self.pool.num_idle(),
+            self.settings.database.max_connections,
+        )
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        self.query.ping().await
+    }
+
+    pub async fn get_applied_migrations(&self) -> Result<AppliedMigrationsRecord> {
+        self.query.get_applied_migrations().await
+    }
+
+    pub async fn run_migrations(&self) -> Result<()> {
+        self.query.run_migrations().await
+    }
+
     pub async fn get_station(&self, token: String, include_location: bool) -> Result<Station> {
         let rec = self.query.get_station(token).await?;
 
@@ -39,7 +94,68 @@ impl DBRepository {
     }
 
     pub async fn get_active_stations(&self) -> Result<Vec<Station>> {
-        let records = self.query.get_active_stations().await?;
+        let since = Utc::now() - Duration::minutes(self.settings.windows.active_station_minutes);
+        let records = self.query.get_active_stations(since, None, None).await?;
+        let mut result = vec![];
+
+        for rec in records {
+            let mut station = Station::from(&rec);
+            station.location = self.location_or_none(rec.location_id).await;
+
+            result.push(station)
+        }
+
+        Ok(result)
+    }
+
+    /// A page of the active stations, ordered by id
+    pub async fn get_active_stations_page(
+        &self,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Page<Station>> {
+        let since = Utc::now() - Duration::minutes(self.settings.windows.active_station_minutes);
+        let records = self
+            .query
+            .get_active_stations(since, cursor.map(|c| c.id), Some(limit + 1))
+            .await?;
+        let mut result = vec![];
+
+        for rec in records {
+            let mut station = Station::from(&rec);
+            station.location = self.location_or_none(rec.location_id).await;
+
+            result.push(station)
+        }
+
+        Ok(Page::new(result, limit))
+    }
+
+    /// Stations that were last online in `(after, until]`, without their location
+    pub async fn get_stations_last_online_between(
+        &self,
+        after: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Vec<Station>> {
+        let records = self
+            .query
+            .get_stations_last_online_between(after, until)
+            .await?;
+
+        Ok(records.iter().map(Station::from).collect())
+    }
+
+    /// A page of the stations within the box, ordered by id
+    pub async fn get_stations_in_bbox(
+        &self,
+        bbox: &BoundingBox,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Page<Station>> {
+        let records = self
+            .query
+            .get_stations_in_bbox(bbox, cursor.map(|c| c.id), limit + 1)
+            .await?;
         let mut result = vec![];
 
         for rec in records {
@@ -49,9 +165,53 @@ impl DBRepository {
             result.push(station)
         }
 
+        Ok(Page::new(result, limit))
+    }
+
+    /// Stations within `radius_km` of the coordinate, nearest first, together with their
+    /// distance in kilometres
+    pub async fn get_stations_near(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_km: f64,
+        limit: i64,
+    ) -> Result<Vec<(Station, f64)>> {
+        let records = self
+            .query
+            .get_stations_near(lat, lon, radius_km, limit)
+            .await?;
+        let mut result = vec![];
+
+        for rec in records {
+            let (rec, distance_km) = rec.split();
+            let mut station = Station::from(&rec);
+            station.location = self.location_or_none(rec.location_id).await;
+
+            result.push((station, distance_km))
+        }
+
         Ok(result)
     }
 
+    /// The latest pm25 and co2 of every active station, using the same window as
+    /// `get_active_stations`
+    pub async fn get_active_station_readings(&self) -> Result<Vec<ActiveStationReadingRecord>> {
+        let since = Utc::now() - Duration::minutes(self.settings.windows.active_station_minutes);
+
+        self.query.get_active_station_readings(since).await
+    }
+
+    /// Returns the station together with the stored hash of its secret, if it has one
+    pub async fn get_station_credentials(
+        &self,
+        token: String,
+    ) -> Result<(Station, Option<String>)> {
+        let rec = self.query.get_station(token).await?;
+
+        Ok((Station::from(&rec), rec.secret_hash))
+    }
+
     async fn location_or_none(&self, location_id: Option<i32>) -> Option<Location> {
         match location_id {
             Some(id) => self.get_location(id).await.ok(),
@@ -59,17 +219,27 @@ impl DBRepository {
         }
     }
 
-    pub async fn put_station(&self, mut station: Station) -> Result<i32> {
+    pub async fn put_station(&self, mut station: Station, secret_hash: String) -> Result<i32> {
         if let Some(location) = &station.location {
             let id = self.put_location(location).await?;
             station.location_id = Some(id);
         }
 
-        let rec = self.query.put_station(station).await?;
+        let rec = self.query.put_station(station, secret_hash).await?;
 
         Ok(rec.id)
     }
 
+    /// Replace the stored hash of the station's secret
+    pub async fn update_station_secret(&self, token: &str, secret_hash: &str) -> Result<()> {
+        self.query.update_station_secret(token, secret_hash).await
+    }
+
+    /// Tokens of the stations that were registered before secrets were issued
+    pub async fn get_station_tokens_without_secret(&self) -> Result<Vec<String>> {
+        self.query.get_station_tokens_without_secret().await
+    }
+
     pub async fn update_station(&self, station: &Station) -> Result<()> {
         self.query.update_station(station).await?;
 
@@ -94,18 +264,363 @@ impl DBRepository {
         Ok(rec.id)
     }
 
+    pub async fn get_group(&self, name: &str) -> Result<StationGroup> {
+        let rec = self.query.get_group(name).await?;
+
+        Ok(StationGroup::from(rec))
+    }
+
+    /// Create the group or replace its members
+    pub async fn put_group(&self, name: &str, tokens: &[String]) -> Result<StationGroup> {
+        self.query.put_group(name, tokens).await?;
+
+        self.get_group(name).await
+    }
+
+    pub async fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        let rec = self.query.get_webhooks().await?;
+
+        rec.into_iter().map(Webhook::try_from).collect()
+    }
+
+    pub async fn get_webhook(&self, id: i32) -> Result<Webhook> {
+        let rec = self.query.get_webhook(id).await?;
+
+        Webhook::try_from(rec)
+    }
+
+    pub async fn put_webhook(
+        &self,
+        url: &str,
+        events: &[WebhookEvent],
+        station: Option<&str>,
+        secret: &str,
+        enabled: bool,
+    ) -> Result<Webhook> {
+        let events: Vec<String> = events.iter().map(|e| e.as_str().to_string()).collect();
+        let id = self
+            .query
+            .put_webhook(url, &events, station, secret, enabled)
+            .await?;
+
+        self.get_webhook(id).await
+    }
+
+    pub async fn update_webhook(
+        &self,
+        id: i32,
+        url: &str,
+        events: &[WebhookEvent],
+        station: Option<&str>,
+        enabled: bool,
+    ) -> Result<Webhook> {
+        let events: Vec<String> = events.iter().map(|e| e.as_str().to_string()).collect();
+        self.query
+            .update_webhook(id, url, &events, station, enabled)
+            .await?;
+
+        self.get_webhook(id).await
+    }
+
+    pub async fn delete_webhook(&self, id: i32) -> Result<()> {
+        self.query.delete_webhook(id).await
+    }
+
+    pub async fn get_webhook_deliveries(
+        &self,
+        webhook_id: i32,
+        status: Option<DeliveryStatus>,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Page<WebhookDelivery>> {
+        let rec = self
+            .query
+            .get_webhook_deliveries(
+                webhook_id,
+                status.map(DeliveryStatus::as_str),
+                cursor,
+                limit + 1,
+            )
+            .await?;
+        let deliveries = rec
+            .into_iter()
+            .map(WebhookDelivery::try_from)
+            .collect::<Result<_>>()?;
+
+        Ok(Page::new(deliveries, limit))
+    }
+
+    /// Claim up to `limit` due deliveries, hidden from the other instances until
+    /// `lease_until`
+    pub async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Vec<DueDelivery>> {
+        let rec = self
+            .query
+            .claim_webhook_deliveries(limit, lease_until)
+            .await?;
+
+        rec.into_iter().map(DueDelivery::try_from).collect()
+    }
+
+    /// Queue the events for the webhooks that subscribed to them. Returns the number of
+    /// queued deliveries
+    pub async fn put_webhook_deliveries(&self, events: &[QueuedEvent]) -> Result<u64> {
+        let names: Vec<String> = events.iter().map(|e| e.event.as_str().into()).collect();
+        let dedupe_keys: Vec<String> = events.iter().map(|e| e.dedupe_key.clone()).collect();
+        let payloads: Vec<String> = events.iter().map(|e| e.payload.clone()).collect();
+        let stations: Vec<String> = events.iter().map(|e| e.station.clone()).collect();
+
+        self.query
+            .put_webhook_deliveries(&names, &dedupe_keys, &payloads, &stations)
+            .await
+    }
+
+    pub async fn update_webhook_delivery(
+        &self,
+        delivery: &DueDelivery,
+        status: DeliveryStatus,
+        next_attempt: DateTime<Utc>,
+        response_status: Option<i32>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        self.query
+            .update_webhook_delivery(
+                delivery.id,
+                status.as_str(),
+                delivery.attempts,
+                next_attempt,
+                response_status,
+                error,
+            )
+            .await
+    }
+
+    pub async fn get_alert_rules(&self) -> Result<Vec<AlertRule>> {
+        let rec = self.query.get_alert_rules().await?;
+
+        rec.into_iter().map(AlertRule::try_from).collect()
+    }
+
+    pub async fn get_alert_rule(&self, id: i32) -> Result<AlertRule> {
+        let rec = self.query.get_alert_rule(id).await?;
+
+        AlertRule::try_from(rec)
+    }
+
+    pub async fn put_alert_rule(&self, rule: &AlertRule) -> Result<AlertRule> {
+        let id = self.query.put_alert_rule(rule).await?;
+
+        self.get_alert_rule(id).await
+    }
+
+    /// Replace the rule, resolving its firing alerts. Returns the resolved alerts
+    pub async fn update_alert_rule(&self, rule: &AlertRule) -> Result<Vec<Alert>> {
+        let rec = self.query.update_alert_rule(rule, Utc::now()).await?;
+
+        rec.into_iter().map(Alert::try_from).collect()
+    }
+
+    pub async fn delete_alert_rule(&self, id: i32) -> Result<()> {
+        self.query.delete_alert_rule(id).await
+    }
+
+    /// Evaluate the rules that apply to the station, one station at a time. Returns the
+    /// alerts that fired or resolved
+    pub async fn evaluate_alert_rules(
+        &self,
+        station: &Station,
+        evaluate: impl Fn(&AlertRule, &mut AlertState) -> Vec<AlertTransition>,
+    ) -> Result<Vec<Alert>> {
+        let rec = self
+            .query
+            .evaluate_alert_rules(station.id, |rec| {
+                let (rule, mut state) = <(AlertRule, AlertState)>::try_from(rec)?;
+                let before = state;
+                let transitions = evaluate(&rule, &mut state);
+
+                Ok((state != before || !transitions.is_empty()).then_some((state, transitions)))
+            })
+            .await?;
+
+        rec.into_iter().map(Alert::try_from).collect()
+    }
+
+    pub async fn get_alerts(
+        &self,
+        station: Option<&str>,
+        rule_id: Option<i32>,
+        firing: Option<bool>,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Page<Alert>> {
+        let rec = self
+            .query
+            .get_alerts(station, rule_id, firing, cursor, limit + 1)
+            .await?;
+        let alerts = rec
+            .into_iter()
+            .map(Alert::try_from)
+            .collect::<Result<_>>()?;
+
+        Ok(Page::new(alerts, limit))
+    }
+
     pub async fn get_readings_between(
         &self,
         station: Station,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
-    ) -> Result<Vec<Reading>> {
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Page<Reading>> {
         let rec = self
             .query
-            .get_readings_between(station.id, start, end)
+            .get_readings_between(station.id, start, end, cursor, limit + 1)
             .await?;
 
-        Ok(rec)
+        Ok(Page::new(rec, limit))
+    }
+
+    pub async fn get_reading_buckets(
+        &self,
+        station: Station,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        bucket_seconds: i64,
+    ) -> Result<Vec<ReadingBucket>> {
+        self.stream_reading_buckets(station, start, end, bucket_seconds)
+            .try_collect()
+            .await
+    }
+
+    pub fn stream_readings_between(
+        &self,
+        station: Station,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> RowStream<Reading> {
+        self.query.stream_readings_between(station.id, start, end)
+    }
+
+    pub fn stream_reading_buckets(
+        &self,
+        station: Station,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        bucket_seconds: i64,
+    ) -> RowStream<ReadingBucket> {
+        self.query
+            .stream_reading_buckets(station.id, start, end, bucket_seconds)
+            .map_ok(|rec| ReadingBucket::from(&rec))
+            .boxed()
+    }
+
+    pub async fn get_series(
+        &self,
+        tokens: &[String],
+        metric: Metric,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        bucket: Bucket,
+    ) -> Result<ComparisonSeries> {
+        let rec = self
+            .query
+            .get_series(tokens, metric, start, end, bucket.seconds())
+            .await?;
+
+        Ok(ComparisonSeries::new(metric, bucket, rec))
+    }
+
+    /// Interpolate a metric over the box from the stations around it, using their latest
+    /// reading or, given a window, their mean over it
+    pub async fn get_heatmap(
+        &self,
+        bbox: BoundingBox,
+        metric: Metric,
+        window: Option<Window>,
+        (rows, cols): (usize, usize),
+        max_distance_km: Option<f64>,
+    ) -> Result<HeatmapGrid> {
+        // Stations just outside the box still shape the surface near its edges
+        let area = bbox.expand(HEATMAP_MARGIN);
+        let stations = match window {
+            Some(window) => {
+                let since = Utc::now() - Duration::seconds(window.seconds);
+                self.query
+                    .get_average_station_values(&area, metric, since)
+                    .await?
+            }
+            None => {
+                let since =
+                    Utc::now() - Duration::minutes(self.settings.windows.active_station_minutes);
+                self.query
+                    .get_latest_station_values(&area, metric, since)
+                    .await?
+            }
+        };
+
+        Ok(HeatmapGrid::new(
+            metric,
+            bbox,
+            rows,
+            cols,
+            &stations,
+            max_distance_km,
+        ))
+    }
+
+    /// Air quality indices of the station, see `Aqi::from_hourly`
+    pub async fn get_aqi(&self, station: &Station) -> Result<Aqi> {
+        let rec = self
+            .query
+            .get_hourly_averages(station.id, Utc::now(), AQI_HOURS)
+            .await?;
+
+        Ok(Aqi::from_hourly(&rec))
+    }
+
+    /// The air quality indices of each station, by station id
+    pub async fn get_aqi_of_stations(&self, stations: &[Station]) -> Result<HashMap<i32, Aqi>> {
+        let ids: Vec<i32> = stations.iter().map(|s| s.id).collect();
+        let rec = self
+            .query
+            .get_hourly_averages_of_stations(&ids, Utc::now(), AQI_HOURS)
+            .await?;
+
+        let mut hours: HashMap<i32, Vec<HourlyAverageRecord>> = HashMap::new();
+        for r in rec {
+            hours
+                .entry(r.station_id)
+                .or_default()
+                .push(HourlyAverageRecord {
+                    hour: r.hour,
+                    count: r.count,
+                    pm25: r.pm25,
+                    pm10: r.pm10,
+                });
+        }
+
+        Ok(ids
+            .into_iter()
+            .map(|id| {
+                let hours = hours.remove(&id).unwrap_or_default();
+                (id, Aqi::from_hourly(&hours))
+            })
+            .collect())
+    }
+
+    /// The latest reading of each station that has one, by station id
+    pub async fn get_latest_reading_of_stations(
+        &self,
+        stations: &[Station],
+    ) -> Result<HashMap<i32, Reading>> {
+        let ids: Vec<i32> = stations.iter().map(|s| s.id).collect();
+        let rec = self.query.get_latest_reading_of_stations(&ids).await?;
+
+        Ok(rec.into_iter().map(|r| (r.station_id, r)).collect())
     }
 
     pub async fn get_latest_reading(&self, station: Station) -> Result<Reading> {
@@ -114,36 +629,151 @@ impl DBRepository {
         Ok(rec)
     }
 
-    pub async fn get_latest_readings(&self, station: Station, count: i64) -> Result<Vec<Reading>> {
-        let rec = self.query.get_latest_readings(station.id, count).await?;
+    pub async fn get_latest_readings(
+        &self,
+        station: Station,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Page<Reading>> {
+        let rec = self
+            .query
+            .get_latest_readings(station.id, cursor, limit + 1)
+            .await?;
 
-        Ok(rec)
+        Ok(Page::new(rec, limit))
     }
 
-    pub async fn get_average_reading(&self, station: Station) -> Result<AverageReading> {
-        let rec = self.query.get_average_reading(station.id).await?;
+    pub async fn get_average_reading(
+        &self,
+        station: Station,
+        windows: &[Window],
+    ) -> Result<AverageReading> {
+        let rec = self.query.get_average_reading(station.id, windows).await?;
 
         Ok(rec)
     }
 
-    pub async fn get_past_hour_readings(&self, hours: impl Into<i64>) -> Result<Vec<Reading>> {
-        let rec = self.query.get_all_past_hour_readings(hours.into()).await?;
+    pub async fn get_past_hour_readings(
+        &self,
+        hours: impl Into<i64>,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Page<Reading>> {
+        let rec = self
+            .query
+            .get_all_past_hour_readings(hours.into(), cursor, limit + 1)
+            .await?;
 
-        Ok(rec)
+        Ok(Page::new(rec, limit))
+    }
+
+    pub fn stream_past_hour_readings(&self, hours: impl Into<i64>) -> RowStream<Reading> {
+        let since = Utc::now() - Duration::hours(hours.into());
+        self.query.stream_all_readings_since(since)
     }
 
-    pub async fn get_past_minutes_readings(&self, minutes: impl Into<i64>) -> Result<Vec<Reading>> {
+    pub async fn get_past_minutes_readings(
+        &self,
+        minutes: impl Into<i64>,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Page<Reading>> {
         let rec = self
             .query
-            .get_all_past_minutes_readings(minutes.into())
+            .get_all_past_minutes_readings(minutes.into(), cursor, limit + 1)
             .await?;
 
-        Ok(rec)
+        Ok(Page::new(rec, limit))
     }
 
-    pub async fn put_reading(&self, reading: &Reading) -> Result<i32> {
-        let rec = self.query.put_reading(reading).await?;
+    /// Store the reading together with the nonce of the payload that carried it
+    pub async fn put_reading(&self, reading: &Reading, nonce: &Nonce) -> Result<PutReadingRequest> {
+        self.query.put_reading(reading, nonce).await
+    }
 
-        Ok(rec.id)
+    /// Store all readings and the nonce of the payload that carried them and update the
+    /// station's `last_online` in one transaction. Returns the stored reading for each date
+    pub async fn put_readings(
+        &self,
+        station: &Station,
+        readings: &[Reading],
+        nonce: &Nonce,
+    ) -> Result<HashMap<DateTime<Utc>, PutReadingsRecord>> {
+        let rec = self.query.put_readings(station, readings, nonce).await?;
+
+        Ok(rec.into_iter().map(|r| (r.date, r)).collect())
+    }
+
+    /// Hand the events to the live subscribers of every instance, in one notification
+    /// statement. The feed is best effort, so a failed notification is only logged
+    pub async fn publish_events(&self, events: Vec<Event>) {
+        if !self.settings.events.postgres_notify {
+            self.publish_local_events(events);
+            return;
+        }
+
+        let payloads: serde_json::Result<Vec<String>> =
+            events.iter().map(serde_json::to_string).collect();
+        let sent = match payloads {
+            Ok(payloads) => self.query.notify(NOTIFY_CHANNEL, &payloads).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = sent {
+            error!("failed to publish {} events: {e}", events.len());
+        }
+    }
+
+    /// Hand the events to the live subscribers of this instance only
+    pub fn publish_local_events(&self, events: Vec<Event>) {
+        for event in events {
+            self.events.send(event);
+        }
+    }
+
+    pub fn subscribe_events(&self) -> Subscription {
+        self.events.subscribe()
+    }
+
+    pub fn subscribe_readings(&self, station_id: Option<i32>) -> ReadingSubscription {
+        self.events.subscribe_readings(station_id)
+    }
+
+    pub async fn get_readings_stored_after(
+        &self,
+        station_id: Option<i32>,
+        after: i32,
+        limit: i64,
+    ) -> Result<Vec<ReadingEvent>> {
+        let rec = self
+            .query
+            .get_readings_stored_after(station_id, after, limit)
+            .await?;
+
+        Ok(rec
+            .into_iter()
+            .map(|r| ReadingEvent {
+                station: r.token,
+                reading: Reading {
+                    id: r.id,
+                    station_id: r.station_id,
+                    location_id: r.location_id,
+                    date: r.date,
+                    temperature: r.temperature,
+                    humidity: r.humidity,
+                    pm10: r.pm10,
+                    pm25: r.pm25,
+                    co2: r.co2,
+                    voc: r.voc,
+                },
+            })
+            .collect())
+    }
+
+    pub async fn count_duplicate_readings(&self) -> Result<i64> {
+        self.query.count_duplicate_readings().await
+    }
+
+    pub async fn delete_duplicate_readings(&self) -> Result<u64> {
+        self.query.delete_duplicate_readings().await
     }
 }
@@ -0,0 +1,425 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
+
+use crate::{
+    error::{Error, Result},
+    models::{
+        alert::{AlertRule, AlertState, AlertTransition},
+        page::Cursor,
+    },
+    repository::query::Query,
+};
+
+pub struct AlertRuleRecord {
+    pub id: i32,
+    pub name: String,
+    pub metric: String,
+    pub comparison: String,
+    pub threshold: f32,
+    pub clear_threshold: f32,
+    pub duration_seconds: i32,
+    pub station: Option<String>,
+    pub group: Option<String>,
+    pub enabled: bool,
+}
+
+/// A rule that applies to a station, with where it stands at that station
+pub struct AlertRuleStateRecord {
+    pub id: i32,
+    pub name: String,
+    pub metric: String,
+    pub comparison: String,
+    pub threshold: f32,
+    pub clear_threshold: f32,
+    pub duration_seconds: i32,
+    pub breaching_since: Option<DateTime<Utc>>,
+    pub firing: bool,
+    pub last_date: Option<DateTime<Utc>>,
+}
+
+pub struct AlertRecord {
+    pub id: i32,
+    pub rule_id: i32,
+    pub rule: String,
+    pub station: String,
+    pub metric: String,
+    pub since: DateTime<Utc>,
+    pub fired_at: DateTime<Utc>,
+    pub value: f32,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolved_value: Option<f32>,
+}
+
+impl Query {
+    pub async fn get_alert_rules(&self) -> Result<Vec<AlertRuleRecord>> {
+        let rec = sqlx::query_as!(
+            AlertRuleRecord,
+            r#"
+        SELECT r.id, r.name, r.metric, r.comparison, r.threshold, r.clear_threshold, r.duration_seconds,
+            s.token AS "station?", g.name AS "group?", r.enabled
+        FROM alert_rules r
+        LEFT JOIN stations s ON s.id = r.station_id
+        LEFT JOIN station_groups g ON g.id = r.group_id
+        ORDER BY r.id
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    pub async fn get_alert_rule(&self, id: i32) -> Result<AlertRuleRecord> {
+        let rec = sqlx::query_as!(
+            AlertRuleRecord,
+            r#"
+        SELECT r.id, r.name, r.metric, r.comparison, r.threshold, r.clear_threshold, r.duration_seconds,
+            s.token AS "station?", g.name AS "group?", r.enabled
+        FROM alert_rules r
+        LEFT JOIN stations s ON s.id = r.station_id
+        LEFT JOIN station_groups g ON g.id = r.group_id
+        WHERE r.id = $1
+        "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        rec.ok_or_else(|| Error::NotFound(format!("alert rule {id} not found")))
+    }
+
+    /// The station and group of the rule are looked up by token and name, they must exist
+    pub async fn put_alert_rule(&self, rule: &AlertRule) -> Result<i32> {
+        let rec = sqlx::query!(
+            r#"
+        INSERT INTO alert_rules (name, metric, comparison, threshold, clear_threshold, duration_seconds, station_id, group_id, enabled)
+        VALUES (
+            $1, $2, $3, $4, $5, $6,
+            (SELECT id FROM stations WHERE token = $7),
+            (SELECT id FROM station_groups WHERE name = $8),
+            $9
+        )
+        RETURNING id
+        "#,
+            rule.name,
+            rule.metric.column(),
+            rule.comparison.as_str(),
+            rule.threshold,
+            rule.clear_threshold,
+            rule.duration_seconds,
+            rule.station,
+            rule.group,
+            rule.enabled
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(rec.id)
+    }
+
+    /// Replace the rule and start its evaluation over, in one transaction. Its firing
+    /// alerts are resolved as of `now` and returned
+    pub async fn update_alert_rule(
+        &self,
+        rule: &AlertRule,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<AlertRecord>> {
+        let mut tx = self.pool.begin().await?;
+
+        let updated = sqlx::query!(
+            r#"
+        UPDATE alert_rules SET
+            name = $2,
+            metric = $3,
+            comparison = $4,
+            threshold = $5,
+            clear_threshold = $6,
+            duration_seconds = $7,
+            station_id = (SELECT id FROM stations WHERE token = $8),
+            group_id = (SELECT id FROM station_groups WHERE name = $9),
+            enabled = $10
+        WHERE id = $1
+        "#,
+            rule.id,
+            rule.name,
+            rule.metric.column(),
+            rule.comparison.as_str(),
+            rule.threshold,
+            rule.clear_threshold,
+            rule.duration_seconds,
+            rule.station,
+            rule.group,
+            rule.enabled
+        )
+        .execute(&mut tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(Error::NotFound(format!("alert rule {} not found", rule.id)));
+        }
+
+        let resolved = sqlx::query_as!(
+            AlertRecord,
+            r#"
+        WITH resolved AS (
+            UPDATE alerts SET resolved_at = $2
+            WHERE rule_id = $1 AND resolved_at IS NULL
+            RETURNING *
+        )
+        SELECT a.id AS "id!", a.rule_id AS "rule_id!", r.name AS rule, s.token AS station, r.metric,
+            a.since AS "since!", a.fired_at AS "fired_at!", a.value AS "value!", a.resolved_at, a.resolved_value
+        FROM resolved a
+        JOIN alert_rules r ON r.id = a.rule_id
+        JOIN stations s ON s.id = a.station_id
+        "#,
+            rule.id,
+            now
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+        DELETE FROM alert_states
+        WHERE rule_id = $1
+        "#,
+            rule.id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(resolved)
+    }
+
+    /// Delete the rule together with its alerts, in one transaction
+    pub async fn delete_alert_rule(&self, id: i32) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+        DELETE FROM alerts
+        WHERE rule_id = $1
+        "#,
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+        DELETE FROM alert_states
+        WHERE rule_id = $1
+        "#,
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let deleted = sqlx::query!(
+            r#"
+        DELETE FROM alert_rules
+        WHERE id = $1
+        "#,
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(Error::NotFound(format!("alert rule {id} not found")));
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Evaluate the enabled rules that apply to the station, its own, those of its groups and
+    /// those of every station, in one transaction. The state of each rule at the station is
+    /// locked until the alerts that fired or resolved and the new states are stored, so
+    /// concurrent evaluations at the station take turns. `evaluate` returns None when the
+    /// state didn't change
+    pub async fn evaluate_alert_rules<F>(
+        &self,
+        station_id: i32,
+        mut evaluate: F,
+    ) -> Result<Vec<AlertRecord>>
+    where
+        F: FnMut(AlertRuleStateRecord) -> Result<Option<(AlertState, Vec<AlertTransition>)>>,
+    {
+        let mut tx = self.pool.begin().await?;
+
+        // Rules that were never evaluated at the station have no state to lock yet
+        sqlx::query!(
+            r#"
+        INSERT INTO alert_states (rule_id, station_id)
+        SELECT r.id, $1
+        FROM alert_rules r
+        WHERE r.enabled
+        AND (
+            (r.station_id IS NULL AND r.group_id IS NULL)
+            OR r.station_id = $1
+            OR r.group_id IN (SELECT group_id FROM station_group_members WHERE station_id = $1)
+        )
+        ON CONFLICT (rule_id, station_id) DO NOTHING
+        "#,
+            station_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let rules = sqlx::query_as!(
+            AlertRuleStateRecord,
+            r#"
+        SELECT r.id, r.name, r.metric, r.comparison, r.threshold, r.clear_threshold, r.duration_seconds,
+            st.breaching_since, st.firing, st.last_date
+        FROM alert_rules r
+        JOIN alert_states st ON st.rule_id = r.id AND st.station_id = $1
+        WHERE r.enabled
+        AND (
+            (r.station_id IS NULL AND r.group_id IS NULL)
+            OR r.station_id = $1
+            OR r.group_id IN (SELECT group_id FROM station_group_members WHERE station_id = $1)
+        )
+        ORDER BY r.id
+        FOR UPDATE OF st
+        "#,
+            station_id
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        let mut alerts = vec![];
+        for rule in rules {
+            let rule_id = rule.id;
+            if let Some((state, transitions)) = evaluate(rule)? {
+                let changed =
+                    put_alert_evaluation(&mut tx, rule_id, station_id, state, &transitions).await?;
+                alerts.extend(changed);
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(alerts)
+    }
+
+    /// Up to `limit` alerts before the cursor, the latest first, optionally of one station
+    /// or rule, or only the firing or resolved ones
+    pub async fn get_alerts(
+        &self,
+        station: Option<&str>,
+        rule_id: Option<i32>,
+        firing: Option<bool>,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Vec<AlertRecord>> {
+        let rec = sqlx::query_as!(
+            AlertRecord,
+            r#"
+        SELECT a.id, a.rule_id, r.name AS rule, s.token AS station, r.metric,
+            a.since, a.fired_at, a.value, a.resolved_at, a.resolved_value
+        FROM alerts a
+        JOIN alert_rules r ON r.id = a.rule_id
+        JOIN stations s ON s.id = a.station_id
+        WHERE ($1::text IS NULL OR s.token = $1)
+        AND ($2::int IS NULL OR a.rule_id = $2)
+        AND ($3::bool IS NULL OR (a.resolved_at IS NULL) = $3)
+        AND ($4::timestamptz IS NULL OR (a.fired_at, a.id) < ($4, $5::int))
+        ORDER BY a.fired_at DESC, a.id DESC
+        LIMIT $6
+        "#,
+            station,
+            rule_id,
+            firing,
+            cursor.map(|c| c.date),
+            cursor.map(|c| c.id),
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+}
+
+/// Store the alerts that fired or resolved while evaluating the rule at the station and
+/// where the rule stands now
+async fn put_alert_evaluation(
+    tx: &mut Transaction<'_, Postgres>,
+    rule_id: i32,
+    station_id: i32,
+    state: AlertState,
+    transitions: &[AlertTransition],
+) -> Result<Vec<AlertRecord>> {
+    let mut alerts = Vec::with_capacity(transitions.len());
+
+    for transition in transitions {
+        let changed = match *transition {
+            AlertTransition::Fire { since, date, value } => {
+                sqlx::query_as!(
+                    AlertRecord,
+                    r#"
+        WITH fired AS (
+            INSERT INTO alerts (rule_id, station_id, since, fired_at, value)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+        )
+        SELECT a.id AS "id!", a.rule_id AS "rule_id!", r.name AS rule, s.token AS station, r.metric,
+            a.since AS "since!", a.fired_at AS "fired_at!", a.value AS "value!", a.resolved_at, a.resolved_value
+        FROM fired a
+        JOIN alert_rules r ON r.id = a.rule_id
+        JOIN stations s ON s.id = a.station_id
+        "#,
+                    rule_id,
+                    station_id,
+                    since,
+                    date,
+                    value
+                )
+                .fetch_all(&mut *tx)
+                .await?
+            }
+            AlertTransition::Resolve { date, value } => {
+                sqlx::query_as!(
+                    AlertRecord,
+                    r#"
+        WITH resolved AS (
+            UPDATE alerts SET resolved_at = $3, resolved_value = $4
+            WHERE rule_id = $1 AND station_id = $2 AND resolved_at IS NULL
+            RETURNING *
+        )
+        SELECT a.id AS "id!", a.rule_id AS "rule_id!", r.name AS rule, s.token AS station, r.metric,
+            a.since AS "since!", a.fired_at AS "fired_at!", a.value AS "value!", a.resolved_at, a.resolved_value
+        FROM resolved a
+        JOIN alert_rules r ON r.id = a.rule_id
+        JOIN stations s ON s.id = a.station_id
+        "#,
+                    rule_id,
+                    station_id,
+                    date,
+                    value
+                )
+                .fetch_all(&mut *tx)
+                .await?
+            }
+        };
+        alerts.extend(changed);
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE alert_states SET breaching_since = $3, firing = $4, last_date = $5
+        WHERE rule_id = $1 AND station_id = $2
+        "#,
+        rule_id,
+        station_id,
+        state.breaching_since,
+        state.firing,
+        state.last_date
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(alerts)
+}
@@ -0,0 +1,88 @@
+use crate::{
+    error::{Error, Result},
+    repository::query::Query,
+};
+
+pub struct StationGroupRecord {
+    pub id: i32,
+    pub name: String,
+    pub stations: Vec<String>,
+}
+
+impl Query {
+    pub async fn get_group(&self, name: &str) -> Result<StationGroupRecord> {
+        let rec = sqlx::query_as!(
+            StationGroupRecord,
+            r#"
+        SELECT g.id, g.name, COALESCE(ARRAY_AGG(s.token ORDER BY s.token) FILTER (WHERE s.token IS NOT NULL), '{}') AS "stations!"
+        FROM station_groups g
+        LEFT JOIN station_group_members m ON m.group_id = g.id
+        LEFT JOIN stations s ON s.id = m.station_id
+        WHERE g.name = $1
+        GROUP BY g.id
+        "#,
+            name
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        rec.ok_or_else(|| Error::NotFound(format!("group {name} not found")))
+    }
+
+    /// Create the group or replace its members, in one transaction. Nothing is changed if
+    /// one of the stations doesn't exist
+    pub async fn put_group(&self, name: &str, tokens: &[String]) -> Result<i32> {
+        let mut tx = self.pool.begin().await?;
+
+        let group = sqlx::query!(
+            r#"
+        INSERT INTO station_groups (name)
+        VALUES ($1)
+        ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+        RETURNING id
+        "#,
+            name
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+        DELETE FROM station_group_members
+        WHERE group_id = $1
+        "#,
+            group.id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let members = sqlx::query!(
+            r#"
+        INSERT INTO station_group_members (group_id, station_id)
+        SELECT $1, id FROM stations
+        WHERE token = ANY($2)
+        RETURNING (SELECT token FROM stations WHERE id = station_id) AS "token!"
+        "#,
+            group.id,
+            tokens
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        let unknown: Vec<&str> = tokens
+            .iter()
+            .filter(|token| !members.iter().any(|m| &m.token == *token))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(Error::NotFound(format!(
+                "stations {} not found",
+                unknown.join(", ")
+            )));
+        }
+
+        tx.commit().await?;
+
+        Ok(group.id)
+    }
+}
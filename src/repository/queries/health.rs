@@ -0,0 +1,11 @@
+use crate::{error::Result, repository::query::Query};
+
+impl Query {
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query!("SELECT 1 AS ping")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
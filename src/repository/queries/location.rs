@@ -1,5 +1,8 @@
-use crate::{models::location::Location, repository::query::Query};
-use anyhow::Result;
+use crate::{
+    error::{Error, Result},
+    models::location::Location,
+    repository::query::Query,
+};
 
 pub struct PutLocationRecord {
     pub id: i32,
@@ -15,10 +18,10 @@ impl Query {
             "#,
             location_id
         )
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(rec)
+        rec.ok_or_else(|| Error::NotFound(format!("location {location_id} not found")))
     }
 
     pub async fn put_location(&self, location: &Location) -> Result<PutLocationRecord> {
@@ -0,0 +1,49 @@
+use crate::{error::Result, repository::query::Query};
+use sqlx::migrate::{AppliedMigration, Migrate, Migrator};
+
+/// The migrations in `migrations/`, embedded at compile time
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+pub struct AppliedMigrationsRecord {
+    /// Version of a migration that failed halfway, if any
+    pub dirty_version: Option<i64>,
+    pub migrations: Vec<AppliedMigration>,
+}
+
+impl Query {
+    /// Read sqlx' bookkeeping of the applied migrations, without creating its table
+    pub async fn get_applied_migrations(&self) -> Result<AppliedMigrationsRecord> {
+        let rec = sqlx::query!(
+            r#"
+        SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "exists!"
+        "#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        if !rec.exists {
+            return Ok(AppliedMigrationsRecord {
+                dirty_version: None,
+                migrations: vec![],
+            });
+        }
+
+        let mut conn = self.pool.acquire().await?;
+        let dirty_version = conn.dirty_version().await.map_err(sqlx::Error::from)?;
+        let migrations = conn
+            .list_applied_migrations()
+            .await
+            .map_err(sqlx::Error::from)?;
+
+        Ok(AppliedMigrationsRecord {
+            dirty_version,
+            migrations,
+        })
+    }
+
+    pub async fn run_migrations(&self) -> Result<()> {
+        MIGRATOR.run(&self.pool).await.map_err(sqlx::Error::from)?;
+
+        Ok(())
+    }
+}
@@ -1,3 +1,9 @@
 pub mod station;
 pub mod location;
-pub mod reading;
\ No newline at end of file
+pub mod health;
+pub mod migration;
+pub mod nonce;
+pub mod reading;
+pub mod group;
+pub mod alert;
+pub mod webhook;
\ No newline at end of file
@@ -0,0 +1,38 @@
+use crate::{
+    error::{Error, Result},
+    models::nonce::Nonce,
+};
+use sqlx::{Postgres, Transaction};
+
+/// Remember the nonce within the transaction that stores what it signed, forgetting the
+/// station's expired ones. Fails with a conflict if the station already used it
+pub(super) async fn put_nonce(tx: &mut Transaction<'_, Postgres>, nonce: &Nonce) -> Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM reading_nonces
+        WHERE station_id = $1
+        AND date < $2
+        "#,
+        nonce.station_id,
+        nonce.expire_before
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO reading_nonces (station_id, nonce)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        nonce.station_id,
+        nonce.value
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(Error::Conflict("nonce was already used".into()));
+    }
+
+    Ok(())
+}
@@ -1,20 +1,170 @@
 use crate::{
-    models::reading::{AverageReading, Reading},
-    repository::query::Query,
+    error::{Error, Result},
+    models::{
+        aggregate::Window,
+        geo::BoundingBox,
+        metric::Metric,
+        nonce::Nonce,
+        nowcast::{NowCast, NOWCAST_HOURS},
+        page::Cursor,
+        reading::{AverageReading, AverageReadingValues, Reading, WindowReadingValues},
+        regulatory::RegulatoryAverages,
+        station::Station,
+    },
+    repository::query::{row_channel, row_stream, Query, RowStream},
 };
-use anyhow::{anyhow, Result};
 use chrono::{DateTime, Duration, Utc};
+use futures_util::StreamExt;
+use std::collections::HashSet;
 
+use super::nonce::put_nonce;
+
+/// `inserted` is false when a reading with the same station and date was already stored
 pub struct PutReadingRequest {
     pub id: i32,
+    pub inserted: bool,
+}
+
+pub struct PutReadingsRecord {
+    pub id: i32,
+    pub date: DateTime<Utc>,
+    pub inserted: bool,
+}
+
+/// Average particulate matter within one hour, `hour` 0 being the last 60 minutes
+pub struct HourlyAverageRecord {
+    pub hour: i32,
+    pub count: i64,
+    pub pm25: f32,
+    pub pm10: f32,
+}
+
+/// Like `HourlyAverageRecord`, for one of several stations
+pub struct StationHourlyAverageRecord {
+    pub station_id: i32,
+    pub hour: i32,
+    pub count: i64,
+    pub pm25: f32,
+    pub pm10: f32,
+}
+
+/// A reading along with the token of its station
+pub struct StationReadingRecord {
+    pub token: String,
+    pub id: i32,
+    pub station_id: i32,
+    pub location_id: Option<i32>,
+    pub date: DateTime<Utc>,
+    pub temperature: f32,
+    pub humidity: f32,
+    pub pm10: f32,
+    pub pm25: f32,
+    pub co2: f32,
+    pub voc: f32,
+}
+
+/// The value of a metric at the location of a station
+pub struct StationValueRecord {
+    pub latitude: f32,
+    pub longitude: f32,
+    pub value: f32,
+}
+
+/// Mean of a metric for a station within the bucket starting at `date`
+pub struct SeriesPointRecord {
+    pub token: String,
+    pub date: DateTime<Utc>,
+    pub value: Option<f32>,
+}
+
+/// Means of the readings within the clock hour starting at `hour`
+pub struct ClockHourAverageRecord {
+    pub hour: DateTime<Utc>,
+    pub pm25: f32,
+    pub pm10: f32,
+    pub co2: f32,
+}
+
+/// Statistics of the readings within the `seconds` before now, every value is None when
+/// there are no readings
+pub struct WindowStatisticsRecord {
+    pub seconds: i64,
+    pub count: i64,
+    pub temperature_mean: Option<f32>,
+    pub temperature_min: Option<f32>,
+    pub temperature_max: Option<f32>,
+    pub temperature_median: Option<f32>,
+    pub temperature_p95: Option<f32>,
+    pub temperature_stddev: Option<f32>,
+    pub humidity_mean: Option<f32>,
+    pub humidity_min: Option<f32>,
+    pub humidity_max: Option<f32>,
+    pub humidity_median: Option<f32>,
+    pub humidity_p95: Option<f32>,
+    pub humidity_stddev: Option<f32>,
+    pub pm10_mean: Option<f32>,
+    pub pm10_min: Option<f32>,
+    pub pm10_max: Option<f32>,
+    pub pm10_median: Option<f32>,
+    pub pm10_p95: Option<f32>,
+    pub pm10_stddev: Option<f32>,
+    pub pm25_mean: Option<f32>,
+    pub pm25_min: Option<f32>,
+    pub pm25_max: Option<f32>,
+    pub pm25_median: Option<f32>,
+    pub pm25_p95: Option<f32>,
+    pub pm25_stddev: Option<f32>,
+    pub co2_mean: Option<f32>,
+    pub co2_min: Option<f32>,
+    pub co2_max: Option<f32>,
+    pub co2_median: Option<f32>,
+    pub co2_p95: Option<f32>,
+    pub co2_stddev: Option<f32>,
+    pub voc_mean: Option<f32>,
+    pub voc_min: Option<f32>,
+    pub voc_max: Option<f32>,
+    pub voc_median: Option<f32>,
+    pub voc_p95: Option<f32>,
+    pub voc_stddev: Option<f32>,
+}
+
+pub struct ReadingBucketRecord {
+    pub date: DateTime<Utc>,
+    pub temperature_count: i64,
+    pub temperature_avg: f32,
+    pub temperature_min: f32,
+    pub temperature_max: f32,
+    pub humidity_count: i64,
+    pub humidity_avg: f32,
+    pub humidity_min: f32,
+    pub humidity_max: f32,
+    pub pm10_count: i64,
+    pub pm10_avg: f32,
+    pub pm10_min: f32,
+    pub pm10_max: f32,
+    pub pm25_count: i64,
+    pub pm25_avg: f32,
+    pub pm25_min: f32,
+    pub pm25_max: f32,
+    pub co2_count: i64,
+    pub co2_avg: f32,
+    pub co2_min: f32,
+    pub co2_max: f32,
+    pub voc_count: i64,
+    pub voc_avg: f32,
+    pub voc_min: f32,
+    pub voc_max: f32,
 }
 
 impl Query {
+    /// Up to `limit` readings in `[start, end]` after the cursor, oldest first
     pub async fn get_readings_between(
         &self,
         station_id: i32,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
+        cursor: Option<Cursor>,
+        limit: i64,
     ) -> Result<Vec<Reading>> {
         let rec = sqlx::query_as!(
             Reading,
@@ -22,10 +172,273 @@ impl Query {
         SELECT * FROM readings
         WHERE station_id = $1
         AND date BETWEEN $2 AND $3
+        AND ($4::timestamptz IS NULL OR (date, id) > ($4, $5::int))
+        ORDER BY date, id
+        LIMIT $6
         "#,
             station_id,
             start,
-            end
+            end,
+            cursor.map(|c| c.date),
+            cursor.map(|c| c.id),
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    /// Like `get_readings_between`, oldest first, without loading every reading at once
+    pub fn stream_readings_between(
+        &self,
+        station_id: i32,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> RowStream<Reading> {
+        let pool = self.pool.clone();
+        let (tx, rx) = row_channel();
+
+        actix_web::rt::spawn(async move {
+            let mut rows = sqlx::query_as!(
+                Reading,
+                r#"
+            SELECT * FROM readings
+            WHERE station_id = $1
+            AND date BETWEEN $2 AND $3
+            ORDER BY date, id
+            "#,
+                station_id,
+                start,
+                end
+            )
+            .fetch(&pool);
+
+            while let Some(row) = rows.next().await {
+                // The receiver is gone when the client disconnected
+                if tx.send(row.map_err(Into::into)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        row_stream(rx)
+    }
+
+    /// Aggregate the readings in `[start, end)` into buckets of `bucket_seconds`, aligned to
+    /// the Unix epoch. Buckets without readings are left out
+    pub fn stream_reading_buckets(
+        &self,
+        station_id: i32,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        bucket_seconds: i64,
+    ) -> RowStream<ReadingBucketRecord> {
+        let pool = self.pool.clone();
+        let (tx, rx) = row_channel();
+
+        actix_web::rt::spawn(async move {
+            let mut rows = sqlx::query_as!(
+                ReadingBucketRecord,
+                r#"
+            SELECT
+                date_bin(make_interval(secs => $4), date, TIMESTAMPTZ '1970-01-01 00:00:00+00') AS "date!",
+                COUNT(temperature) AS "temperature_count!",
+                AVG(temperature)::real AS "temperature_avg!",
+                MIN(temperature) AS "temperature_min!",
+                MAX(temperature) AS "temperature_max!",
+                COUNT(humidity) AS "humidity_count!",
+                AVG(humidity)::real AS "humidity_avg!",
+                MIN(humidity) AS "humidity_min!",
+                MAX(humidity) AS "humidity_max!",
+                COUNT(pm10) AS "pm10_count!",
+                AVG(pm10)::real AS "pm10_avg!",
+                MIN(pm10) AS "pm10_min!",
+                MAX(pm10) AS "pm10_max!",
+                COUNT(pm25) AS "pm25_count!",
+                AVG(pm25)::real AS "pm25_avg!",
+                MIN(pm25) AS "pm25_min!",
+                MAX(pm25) AS "pm25_max!",
+                COUNT(co2) AS "co2_count!",
+                AVG(co2)::real AS "co2_avg!",
+                MIN(co2) AS "co2_min!",
+                MAX(co2) AS "co2_max!",
+                COUNT(voc) AS "voc_count!",
+                AVG(voc)::real AS "voc_avg!",
+                MIN(voc) AS "voc_min!",
+                MAX(voc) AS "voc_max!"
+            FROM readings
+            WHERE station_id = $1
+            AND date >= $2
+            AND date < $3
+            GROUP BY 1
+            ORDER BY 1
+            "#,
+                station_id,
+                start,
+                end,
+                bucket_seconds as f64
+            )
+            .fetch(&pool);
+
+            while let Some(row) = rows.next().await {
+                if tx.send(row.map_err(Into::into)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        row_stream(rx)
+    }
+
+    /// Aggregate one metric of every station in `tokens` into the same buckets of `[start, end)`,
+    /// ordered by station and date. Every station gets a row for every bucket, the value is
+    /// None if it has no readings in it
+    pub async fn get_series(
+        &self,
+        tokens: &[String],
+        metric: Metric,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        bucket_seconds: i64,
+    ) -> Result<Vec<SeriesPointRecord>> {
+        let rec = sqlx::query_as!(
+            SeriesPointRecord,
+            r#"
+        WITH selected AS (
+            SELECT id, token FROM stations
+            WHERE token = ANY($1)
+        ),
+        buckets AS (
+            SELECT date FROM generate_series(
+                date_bin(make_interval(secs => $5), $3, TIMESTAMPTZ '1970-01-01 00:00:00+00'),
+                $4,
+                make_interval(secs => $5)
+            ) AS date
+            WHERE date < $4
+        ),
+        aggregated AS (
+            SELECT
+                station_id,
+                date_bin(make_interval(secs => $5), date, TIMESTAMPTZ '1970-01-01 00:00:00+00') AS date,
+                AVG(CASE $2
+                    WHEN 'temperature' THEN temperature
+                    WHEN 'humidity' THEN humidity
+                    WHEN 'pm10' THEN pm10
+                    WHEN 'pm25' THEN pm25
+                    WHEN 'co2' THEN co2
+                    WHEN 'voc' THEN voc
+                END)::real AS value
+            FROM readings
+            WHERE station_id IN (SELECT id FROM selected)
+            AND date >= $3
+            AND date < $4
+            GROUP BY 1, 2
+        )
+        SELECT s.token AS "token!", b.date AS "date!", a.value
+        FROM selected s
+        CROSS JOIN buckets b
+        LEFT JOIN aggregated a ON a.station_id = s.id AND a.date = b.date
+        ORDER BY s.token, b.date
+        "#,
+            tokens,
+            metric.column(),
+            start,
+            end,
+            bucket_seconds as f64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    /// The latest value since `since` of every station located in the box
+    pub async fn get_latest_station_values(
+        &self,
+        bbox: &BoundingBox,
+        metric: Metric,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<StationValueRecord>> {
+        let rec = sqlx::query_as!(
+            StationValueRecord,
+            r#"
+        SELECT l.latitude, l.longitude, v.value AS "value!"
+        FROM stations s
+        JOIN locations l ON l.id = s.location_id
+        CROSS JOIN LATERAL (
+            SELECT CASE $5
+                WHEN 'temperature' THEN temperature
+                WHEN 'humidity' THEN humidity
+                WHEN 'pm10' THEN pm10
+                WHEN 'pm25' THEN pm25
+                WHEN 'co2' THEN co2
+                WHEN 'voc' THEN voc
+            END AS value
+            FROM readings
+            WHERE station_id = s.id
+            AND date >= $6
+            ORDER BY date DESC
+            LIMIT 1
+        ) v
+        WHERE l.latitude BETWEEN $1::float8 AND $2::float8
+        AND CASE
+            WHEN $3::float8 <= $4::float8 THEN l.longitude BETWEEN $3 AND $4
+            ELSE l.longitude >= $3 OR l.longitude <= $4
+        END
+        "#,
+            bbox.min_lat,
+            bbox.max_lat,
+            bbox.min_lon,
+            bbox.max_lon,
+            metric.column(),
+            since
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    /// The mean since `since` of every station located in the box that has readings
+    pub async fn get_average_station_values(
+        &self,
+        bbox: &BoundingBox,
+        metric: Metric,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<StationValueRecord>> {
+        let rec = sqlx::query_as!(
+            StationValueRecord,
+            r#"
+        SELECT l.latitude, l.longitude, v.value AS "value!"
+        FROM stations s
+        JOIN locations l ON l.id = s.location_id
+        CROSS JOIN LATERAL (
+            SELECT AVG(CASE $5
+                WHEN 'temperature' THEN temperature
+                WHEN 'humidity' THEN humidity
+                WHEN 'pm10' THEN pm10
+                WHEN 'pm25' THEN pm25
+                WHEN 'co2' THEN co2
+                WHEN 'voc' THEN voc
+            END)::real AS value
+            FROM readings
+            WHERE station_id = s.id
+            AND date >= $6
+        ) v
+        WHERE v.value IS NOT NULL
+        AND l.latitude BETWEEN $1::float8 AND $2::float8
+        AND CASE
+            WHEN $3::float8 <= $4::float8 THEN l.longitude BETWEEN $3 AND $4
+            ELSE l.longitude >= $3 OR l.longitude <= $4
+        END
+        "#,
+            bbox.min_lat,
+            bbox.max_lat,
+            bbox.min_lon,
+            bbox.max_lon,
+            metric.column(),
+            since
         )
         .fetch_all(&self.pool)
         .await?;
@@ -44,23 +457,92 @@ impl Query {
         "#,
             station_id
         )
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        rec.ok_or_else(|| Error::NotFound(format!("station {station_id} has no readings")))
+    }
+
+    /// The latest reading of each of the stations, stations without readings are left out
+    pub async fn get_latest_reading_of_stations(
+        &self,
+        station_ids: &[i32],
+    ) -> Result<Vec<Reading>> {
+        let rec = sqlx::query_as!(
+            Reading,
+            r#"
+        SELECT r.id AS "id!", r.station_id AS "station_id!", r.location_id, r.date AS "date!",
+            r.temperature AS "temperature!", r.humidity AS "humidity!", r.pm10 AS "pm10!",
+            r.pm25 AS "pm25!", r.co2 AS "co2!", r.voc AS "voc!"
+        FROM UNNEST($1::int[]) AS s(id)
+        CROSS JOIN LATERAL (
+            SELECT * FROM readings
+            WHERE station_id = s.id
+            ORDER BY date DESC
+            LIMIT 1
+        ) r
+        "#,
+            station_ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    /// The latest `limit` readings stored after the reading with id `after`, of the station
+    /// or of every station, in the order they were stored
+    pub async fn get_readings_stored_after(
+        &self,
+        station_id: Option<i32>,
+        after: i32,
+        limit: i64,
+    ) -> Result<Vec<StationReadingRecord>> {
+        let rec = sqlx::query_as!(
+            StationReadingRecord,
+            r#"
+        SELECT * FROM (
+            SELECT s.token, r.id, r.station_id, r.location_id, r.date,
+                r.temperature, r.humidity, r.pm10, r.pm25, r.co2, r.voc
+            FROM readings r
+            JOIN stations s ON s.id = r.station_id
+            WHERE r.id > $2
+            AND ($1::int IS NULL OR r.station_id = $1)
+            ORDER BY r.id DESC
+            LIMIT $3
+        ) r
+        ORDER BY id
+        "#,
+            station_id,
+            after,
+            limit
+        )
+        .fetch_all(&self.pool)
         .await?;
 
         Ok(rec)
     }
 
-    pub async fn get_latest_readings(&self, station_id: i32, count: i64) -> Result<Vec<Reading>> {
+    /// Up to `limit` readings before the cursor, newest first
+    pub async fn get_latest_readings(
+        &self,
+        station_id: i32,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Vec<Reading>> {
         let rec = sqlx::query_as!(
             Reading,
             r#"
         SELECT * FROM readings
         WHERE station_id = $1
-        ORDER BY date DESC
-        LIMIT $2
+        AND ($2::timestamptz IS NULL OR (date, id) < ($2, $3::int))
+        ORDER BY date DESC, id DESC
+        LIMIT $4
         "#,
             station_id,
-            count
+            cursor.map(|c| c.date),
+            cursor.map(|c| c.id),
+            limit
         )
         .fetch_all(&self.pool)
         .await?;
@@ -68,25 +550,148 @@ impl Query {
         Ok(rec)
     }
 
-    pub async fn get_average_reading(&self, station_id: i32) -> Result<AverageReading> {
-        let hour_readings = self.get_past_hour_readings(station_id, 1).await?;
-        let day_readings = self.get_past_hour_readings(station_id, 24).await?;
-        let rec = AverageReading::new(hour_readings, day_readings);
+    pub async fn get_average_reading(
+        &self,
+        station_id: i32,
+        windows: &[Window],
+    ) -> Result<AverageReading> {
+        let now = Utc::now();
+        let mut seconds = vec![Window::HOUR.seconds, Window::DAY.seconds];
+        seconds.extend(windows.iter().map(|w| w.seconds));
+
+        let rec = self
+            .get_window_statistics(station_id, now, &seconds)
+            .await?;
+        let values = |seconds: i64| {
+            rec.iter()
+                .find(|r| r.seconds == seconds)
+                .map(AverageReadingValues::from)
+                .unwrap_or_else(AverageReadingValues::empty)
+        };
+
+        let hour = values(Window::HOUR.seconds);
+        let day = values(Window::DAY.seconds);
+        let windows = windows
+            .iter()
+            .map(|&window| WindowReadingValues {
+                window,
+                values: values(window.seconds),
+            })
+            .collect();
+
+        let hourly = self
+            .get_hourly_averages(station_id, now, NOWCAST_HOURS)
+            .await?;
+        let clock_hours = self
+            .get_clock_hour_averages(
+                station_id,
+                RegulatoryAverages::start(now),
+                RegulatoryAverages::end(now),
+            )
+            .await?;
+
+        Ok(AverageReading::new(
+            hour,
+            day,
+            windows,
+            NowCast::new(&hourly),
+            RegulatoryAverages::new(&clock_hours, now),
+        ))
+    }
+
+    /// Compute the statistics of the readings within each window of `seconds` before `now`,
+    /// returning one row per distinct window
+    pub async fn get_window_statistics(
+        &self,
+        station_id: i32,
+        now: DateTime<Utc>,
+        seconds: &[i64],
+    ) -> Result<Vec<WindowStatisticsRecord>> {
+        let rec = sqlx::query_as!(
+            WindowStatisticsRecord,
+            r#"
+        SELECT
+            w.seconds AS "seconds!",
+            COUNT(r.id) AS "count!",
+            AVG(r.temperature)::real AS temperature_mean,
+            MIN(r.temperature) AS temperature_min,
+            MAX(r.temperature) AS temperature_max,
+            (PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY r.temperature))::real AS temperature_median,
+            (PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY r.temperature))::real AS temperature_p95,
+            STDDEV_POP(r.temperature)::real AS temperature_stddev,
+            AVG(r.humidity)::real AS humidity_mean,
+            MIN(r.humidity) AS humidity_min,
+            MAX(r.humidity) AS humidity_max,
+            (PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY r.humidity))::real AS humidity_median,
+            (PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY r.humidity))::real AS humidity_p95,
+            STDDEV_POP(r.humidity)::real AS humidity_stddev,
+            AVG(r.pm10)::real AS pm10_mean,
+            MIN(r.pm10) AS pm10_min,
+            MAX(r.pm10) AS pm10_max,
+            (PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY r.pm10))::real AS pm10_median,
+            (PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY r.pm10))::real AS pm10_p95,
+            STDDEV_POP(r.pm10)::real AS pm10_stddev,
+            AVG(r.pm25)::real AS pm25_mean,
+            MIN(r.pm25) AS pm25_min,
+            MAX(r.pm25) AS pm25_max,
+            (PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY r.pm25))::real AS pm25_median,
+            (PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY r.pm25))::real AS pm25_p95,
+            STDDEV_POP(r.pm25)::real AS pm25_stddev,
+            AVG(r.co2)::real AS co2_mean,
+            MIN(r.co2) AS co2_min,
+            MAX(r.co2) AS co2_max,
+            (PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY r.co2))::real AS co2_median,
+            (PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY r.co2))::real AS co2_p95,
+            STDDEV_POP(r.co2)::real AS co2_stddev,
+            AVG(r.voc)::real AS voc_mean,
+            MIN(r.voc) AS voc_min,
+            MAX(r.voc) AS voc_max,
+            (PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY r.voc))::real AS voc_median,
+            (PERCENTILE_CONT(0.95) WITHIN GROUP (ORDER BY r.voc))::real AS voc_p95,
+            STDDEV_POP(r.voc)::real AS voc_stddev
+        FROM (SELECT DISTINCT seconds FROM UNNEST($2::bigint[]) AS seconds) w
+        LEFT JOIN readings r
+            ON r.station_id = $1
+            AND r.date <= $3
+            AND r.date > $3 - make_interval(secs => w.seconds)
+        GROUP BY w.seconds
+        "#,
+            station_id,
+            seconds,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await?;
 
         Ok(rec)
     }
 
-    async fn get_past_hour_readings(&self, station_id: i32, hours: i64) -> Result<Vec<Reading>> {
-        let date = Utc::now() - Duration::hours(hours);
+    /// Average the readings in `[start, end)` per clock hour. Hours without readings are
+    /// left out
+    pub async fn get_clock_hour_averages(
+        &self,
+        station_id: i32,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<ClockHourAverageRecord>> {
         let rec = sqlx::query_as!(
-            Reading,
+            ClockHourAverageRecord,
             r#"
-        SELECT * FROM readings
+        SELECT
+            date_trunc('hour', date) AS "hour!",
+            AVG(pm25)::real AS "pm25!",
+            AVG(pm10)::real AS "pm10!",
+            AVG(co2)::real AS "co2!"
+        FROM readings
         WHERE station_id = $1
         AND date >= $2
+        AND date < $3
+        GROUP BY 1
+        ORDER BY 1
         "#,
             station_id,
-            date
+            start,
+            end
         )
         .fetch_all(&self.pool)
         .await?;
@@ -94,15 +699,95 @@ impl Query {
         Ok(rec)
     }
 
-    pub async fn get_all_past_hour_readings(&self, hours: i64) -> Result<Vec<Reading>> {
+    /// Average the readings of each of the `hours` hours before `now`. Hours without
+    /// readings are left out
+    pub async fn get_hourly_averages(
+        &self,
+        station_id: i32,
+        now: DateTime<Utc>,
+        hours: i64,
+    ) -> Result<Vec<HourlyAverageRecord>> {
+        let since = now - Duration::hours(hours);
+        let rec = sqlx::query_as!(
+            HourlyAverageRecord,
+            r#"
+        SELECT
+            FLOOR(EXTRACT(EPOCH FROM ($2 - date)) / 3600)::int AS "hour!",
+            COUNT(*) AS "count!",
+            AVG(pm25)::real AS "pm25!",
+            AVG(pm10)::real AS "pm10!"
+        FROM readings
+        WHERE station_id = $1
+        AND date <= $2
+        AND date > $3
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+            station_id,
+            now,
+            since
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    /// Like `get_hourly_averages`, for each of the stations at once
+    pub async fn get_hourly_averages_of_stations(
+        &self,
+        station_ids: &[i32],
+        now: DateTime<Utc>,
+        hours: i64,
+    ) -> Result<Vec<StationHourlyAverageRecord>> {
+        let since = now - Duration::hours(hours);
+        let rec = sqlx::query_as!(
+            StationHourlyAverageRecord,
+            r#"
+        SELECT
+            station_id,
+            FLOOR(EXTRACT(EPOCH FROM ($2 - date)) / 3600)::int AS "hour!",
+            COUNT(*) AS "count!",
+            AVG(pm25)::real AS "pm25!",
+            AVG(pm10)::real AS "pm10!"
+        FROM readings
+        WHERE station_id = ANY($1)
+        AND date <= $2
+        AND date > $3
+        GROUP BY 1, 2
+        ORDER BY 1, 2
+        "#,
+            station_ids,
+            now,
+            since
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    /// Up to `limit` readings of every station after the cursor, oldest first
+    pub async fn get_all_past_hour_readings(
+        &self,
+        hours: i64,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Vec<Reading>> {
         let date = Utc::now() - Duration::hours(hours);
         let rec = sqlx::query_as!(
             Reading,
             r#"
         SELECT * FROM readings
         WHERE date >= $1
+        AND ($2::timestamptz IS NULL OR (date, id) > ($2, $3::int))
+        ORDER BY date, id
+        LIMIT $4
         "#,
-            date
+            date,
+            cursor.map(|c| c.date),
+            cursor.map(|c| c.id),
+            limit
         )
         .fetch_all(&self.pool)
         .await?;
@@ -110,7 +795,41 @@ impl Query {
         Ok(rec)
     }
 
-    pub async fn get_all_past_minutes_readings(&self, minutes: i64) -> Result<Vec<Reading>> {
+    /// The readings of every station since `since`, oldest first, without loading them all
+    /// at once
+    pub fn stream_all_readings_since(&self, since: DateTime<Utc>) -> RowStream<Reading> {
+        let pool = self.pool.clone();
+        let (tx, rx) = row_channel();
+
+        actix_web::rt::spawn(async move {
+            let mut rows = sqlx::query_as!(
+                Reading,
+                r#"
+            SELECT * FROM readings
+            WHERE date >= $1
+            ORDER BY date, id
+            "#,
+                since
+            )
+            .fetch(&pool);
+
+            while let Some(row) = rows.next().await {
+                if tx.send(row.map_err(Into::into)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        row_stream(rx)
+    }
+
+    /// Up to `limit` latest readings of the stations after the cursor, oldest first
+    pub async fn get_all_past_minutes_readings(
+        &self,
+        minutes: i64,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Vec<Reading>> {
         let date = Utc::now() - Duration::minutes(minutes);
         let rec = sqlx::query_as!(
             Reading,
@@ -120,9 +839,15 @@ impl Query {
         AND (date, station_id) IN (
             SELECT MAX(date), station_id FROM readings
             GROUP BY station_id
-        )    
+        )
+        AND ($2::timestamptz IS NULL OR (date, id) > ($2, $3::int))
+        ORDER BY date, id
+        LIMIT $4
         "#,
-            date
+            date,
+            cursor.map(|c| c.date),
+            cursor.map(|c| c.id),
+            limit
         )
         .fetch_all(&self.pool)
         .await?;
@@ -130,13 +855,37 @@ impl Query {
         Ok(rec)
     }
 
-    pub async fn put_reading(&self, reading: &Reading) -> Result<PutReadingRequest> {
-        let rec = sqlx::query_as!(
+    /// Insert the reading and remember the nonce that signed it, in one transaction. A
+    /// reading that was already stored for the same date is left as is without looking at
+    /// the nonce, so a station re-sending a payload it got no answer to gets the original id
+    pub async fn put_reading(&self, reading: &Reading, nonce: &Nonce) -> Result<PutReadingRequest> {
+        let mut tx = self.pool.begin().await?;
+
+        let existing = sqlx::query_as!(
+            PutReadingRequest,
+            r#"
+        SELECT id, FALSE AS "inserted!" FROM readings
+        WHERE station_id = $1
+        AND date = $2
+        "#,
+            reading.station_id,
+            reading.date
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        if let Some(rec) = existing {
+            return Ok(rec);
+        }
+
+        put_nonce(&mut tx, nonce).await?;
+
+        let inserted = sqlx::query_as!(
             PutReadingRequest,
             r#"
         INSERT INTO readings (station_id, location_id, date, temperature, humidity, pm10, pm25, co2, voc)
         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-        RETURNING id    
+        ON CONFLICT (station_id, date) DO NOTHING
+        RETURNING id, TRUE AS "inserted!"
         "#,
             reading.station_id,
             reading.location_id,
@@ -147,8 +896,163 @@ impl Query {
             reading.pm25,
             reading.co2,
             reading.voc
-        ).fetch_one(&self.pool).await?;
+        ).fetch_optional(&mut tx).await?;
+
+        let rec = match inserted {
+            Some(rec) => rec,
+            None => {
+                sqlx::query_as!(
+                    PutReadingRequest,
+                    r#"
+        SELECT id, FALSE AS "inserted!" FROM readings
+        WHERE station_id = $1
+        AND date = $2
+        "#,
+                    reading.station_id,
+                    reading.date
+                )
+                .fetch_one(&mut tx)
+                .await?
+            }
+        };
+
+        tx.commit().await?;
+
+        Ok(rec)
+    }
+
+    /// Insert all readings for `station` with a single statement, remember the nonce that
+    /// signed them and mark the station as online, in one transaction. Readings that were
+    /// already stored keep their original row, so `readings` must not contain the same date
+    /// twice
+    pub async fn put_readings(
+        &self,
+        station: &Station,
+        readings: &[Reading],
+        nonce: &Nonce,
+    ) -> Result<Vec<PutReadingsRecord>> {
+        let dates: Vec<DateTime<Utc>> = readings.iter().map(|r| r.date).collect();
+        let temperatures: Vec<f32> = readings.iter().map(|r| r.temperature).collect();
+        let humidities: Vec<f32> = readings.iter().map(|r| r.humidity).collect();
+        let pm10s: Vec<f32> = readings.iter().map(|r| r.pm10).collect();
+        let pm25s: Vec<f32> = readings.iter().map(|r| r.pm25).collect();
+        let co2s: Vec<f32> = readings.iter().map(|r| r.co2).collect();
+        let vocs: Vec<f32> = readings.iter().map(|r| r.voc).collect();
+
+        let mut tx = self.pool.begin().await?;
+        put_nonce(&mut tx, nonce).await?;
+
+        let mut rec = sqlx::query_as!(
+            PutReadingsRecord,
+            r#"
+        INSERT INTO readings (station_id, location_id, date, temperature, humidity, pm10, pm25, co2, voc)
+        SELECT $1, $2, date, temperature, humidity, pm10, pm25, co2, voc
+        FROM UNNEST($3::timestamptz[], $4::real[], $5::real[], $6::real[], $7::real[], $8::real[], $9::real[])
+            AS t(date, temperature, humidity, pm10, pm25, co2, voc)
+        ON CONFLICT (station_id, date) DO NOTHING
+        RETURNING id, date, TRUE AS "inserted!"
+        "#,
+            station.id,
+            station.location_id,
+            &dates,
+            &temperatures,
+            &humidities,
+            &pm10s,
+            &pm25s,
+            &co2s,
+            &vocs
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        // Look up the rows of the readings that were stored already, by an earlier upload
+        let inserted: HashSet<DateTime<Utc>> = rec.iter().map(|r| r.date).collect();
+        let existing: Vec<DateTime<Utc>> = dates
+            .into_iter()
+            .filter(|date| !inserted.contains(date))
+            .collect();
+        if !existing.is_empty() {
+            let stored = sqlx::query_as!(
+                PutReadingsRecord,
+                r#"
+        SELECT id, date, FALSE AS "inserted!" FROM readings
+        WHERE station_id = $1
+        AND date = ANY($2)
+        "#,
+                station.id,
+                &existing
+            )
+            .fetch_all(&mut tx)
+            .await?;
+            rec.extend(stored);
+        }
+
+        sqlx::query!(
+            r#"
+        UPDATE stations
+        SET last_online = $1
+        WHERE id = $2
+        "#,
+            station.last_online,
+            station.id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
 
         Ok(rec)
     }
+
+    /// Send each payload to every session listening on the channel, in one statement
+    pub async fn notify(&self, channel: &str, payloads: &[String]) -> Result<()> {
+        sqlx::query!(
+            r#"
+        SELECT 1 AS sent
+        FROM UNNEST($2::text[]) AS p(payload), pg_notify($1, p.payload)
+        "#,
+            channel,
+            payloads
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Count the readings that share their station and date with an older reading
+    pub async fn count_duplicate_readings(&self) -> Result<i64> {
+        let rec = sqlx::query!(
+            r#"
+        SELECT COUNT(*) AS "count!" FROM readings a
+        WHERE EXISTS (
+            SELECT 1 FROM readings b
+            WHERE b.station_id = a.station_id
+            AND b.date = a.date
+            AND b.id < a.id
+        )
+        "#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(rec.count)
+    }
+
+    /// Delete every reading that shares its station and date with an older reading
+    pub async fn delete_duplicate_readings(&self) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+        DELETE FROM readings a
+        USING readings b
+        WHERE b.station_id = a.station_id
+        AND b.date = a.date
+        AND b.id < a.id
+        "#
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
 }
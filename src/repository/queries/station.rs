@@ -1,5 +1,11 @@
-use crate::{models::station::Station, repository::query::Query};
-use anyhow::Result;
+use crate::{
+    error::{Error, Result},
+    models::{
+        geo::{BoundingBox, EARTH_RADIUS_KM},
+        station::Station,
+    },
+    repository::query::Query,
+};
 use chrono::{DateTime, Utc};
 
 pub struct StationRecord {
@@ -10,12 +16,49 @@ pub struct StationRecord {
     pub sw_version: i32,
     pub location_id: Option<i32>,
     pub last_online: DateTime<Utc>,
+    pub secret_hash: Option<String>,
 }
 
 pub struct PutStationRecord {
     pub id: i32,
 }
 
+pub struct StationDistanceRecord {
+    pub id: i32,
+    pub uid: String,
+    pub token: String,
+    pub hw_version: i32,
+    pub sw_version: i32,
+    pub location_id: Option<i32>,
+    pub last_online: DateTime<Utc>,
+    pub secret_hash: Option<String>,
+    pub distance_km: f64,
+}
+
+impl StationDistanceRecord {
+    pub fn split(self) -> (StationRecord, f64) {
+        let station = StationRecord {
+            id: self.id,
+            uid: self.uid,
+            token: self.token,
+            hw_version: self.hw_version,
+            sw_version: self.sw_version,
+            location_id: self.location_id,
+            last_online: self.last_online,
+            secret_hash: self.secret_hash,
+        };
+
+        (station, self.distance_km)
+    }
+}
+
+/// An active station with the values of its latest reading, if it has one
+pub struct ActiveStationReadingRecord {
+    pub token: String,
+    pub pm25: Option<f32>,
+    pub co2: Option<f32>,
+}
+
 impl Query {
     pub async fn get_station(&self, token: String) -> Result<StationRecord> {
         let rec = sqlx::query_as!(
@@ -26,19 +69,114 @@ impl Query {
         "#,
             token
         )
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        rec.ok_or_else(|| Error::NotFound(format!("station {token} not found")))
+    }
+
+    /// Stations online since `since` ordered by id, after the station with id `after` and
+    /// at most `limit` of them. All of them without a limit
+    pub async fn get_active_stations(
+        &self,
+        since: DateTime<Utc>,
+        after: Option<i32>,
+        limit: Option<i64>,
+    ) -> Result<Vec<StationRecord>> {
+        let rec = sqlx::query_as!(
+            StationRecord,
+            r#"
+        SELECT * FROM stations
+        WHERE last_online >= $1
+        AND ($2::int IS NULL OR id > $2)
+        ORDER BY id
+        LIMIT $3
+        "#,
+            since,
+            after,
+            limit
+        )
+        .fetch_all(&self.pool)
         .await?;
 
         Ok(rec)
     }
 
-    pub async fn get_active_stations(&self) -> Result<Vec<StationRecord>> {
+    pub async fn get_stations_last_online_between(
+        &self,
+        after: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Vec<StationRecord>> {
         let rec = sqlx::query_as!(
             StationRecord,
             r#"
         SELECT * FROM stations
-        WHERE last_online >= NOW() - INTERVAL '1 HOUR'
-        "#
+        WHERE last_online > $1
+        AND last_online <= $2
+        "#,
+            after,
+            until
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    pub async fn get_active_station_readings(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<ActiveStationReadingRecord>> {
+        let rec = sqlx::query_as!(
+            ActiveStationReadingRecord,
+            r#"
+        SELECT s.token, r.pm25 AS "pm25?", r.co2 AS "co2?"
+        FROM stations s
+        LEFT JOIN LATERAL (
+            SELECT pm25, co2 FROM readings
+            WHERE station_id = s.id
+            ORDER BY date DESC
+            LIMIT 1
+        ) r ON TRUE
+        WHERE s.last_online >= $1
+        "#,
+            since
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    /// Stations with a location inside the box, which may cross the antimeridian
+    /// Stations located within the box ordered by id, after the station with id `after` and
+    /// at most `limit` of them
+    pub async fn get_stations_in_bbox(
+        &self,
+        bbox: &BoundingBox,
+        after: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<StationRecord>> {
+        let rec = sqlx::query_as!(
+            StationRecord,
+            r#"
+        SELECT s.* FROM stations s
+        JOIN locations l ON l.id = s.location_id
+        WHERE l.latitude BETWEEN $1::float8 AND $2::float8
+        AND CASE
+            WHEN $3::float8 <= $4::float8 THEN l.longitude BETWEEN $3 AND $4
+            ELSE l.longitude >= $3 OR l.longitude <= $4
+        END
+        AND ($5::int IS NULL OR s.id > $5)
+        ORDER BY s.id
+        LIMIT $6
+        "#,
+            bbox.min_lat,
+            bbox.max_lat,
+            bbox.min_lon,
+            bbox.max_lon,
+            after,
+            limit
         )
         .fetch_all(&self.pool)
         .await?;
@@ -46,19 +184,63 @@ impl Query {
         Ok(rec)
     }
 
-    pub async fn put_station(&self, station: Station) -> Result<PutStationRecord> {
+    /// Stations with a location, ordered by their haversine distance to the coordinate.
+    /// Only the stations within `radius_km` are returned, at most `limit` of them
+    pub async fn get_stations_near(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_km: f64,
+        limit: i64,
+    ) -> Result<Vec<StationDistanceRecord>> {
+        let rec = sqlx::query_as!(
+            StationDistanceRecord,
+            r#"
+        SELECT id, uid, token, hw_version, sw_version, location_id, last_online, secret_hash,
+            distance_km AS "distance_km!"
+        FROM (
+            SELECT s.*, $3::float8 * 2 * ASIN(LEAST(1, SQRT(
+                POWER(SIN(RADIANS(l.latitude - $1::float8) / 2), 2)
+                + COS(RADIANS($1::float8)) * COS(RADIANS(l.latitude))
+                * POWER(SIN(RADIANS(l.longitude - $2::float8) / 2), 2)
+            ))) AS distance_km
+            FROM stations s
+            JOIN locations l ON l.id = s.location_id
+        ) d
+        WHERE distance_km <= $4::float8
+        ORDER BY distance_km, id
+        LIMIT $5
+        "#,
+            lat,
+            lon,
+            EARTH_RADIUS_KM,
+            radius_km,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    pub async fn put_station(
+        &self,
+        station: Station,
+        secret_hash: String,
+    ) -> Result<PutStationRecord> {
         let rec = sqlx::query_as!(
             PutStationRecord,
             r#"
-        INSERT INTO stations (uid, token, hw_version, sw_version, location_id)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO stations (uid, token, hw_version, sw_version, location_id, secret_hash)
+        VALUES ($1, $2, $3, $4, $5, $6)
         RETURNING id
         "#,
             station.uid,
             station.token,
             station.hw_version,
             station.sw_version,
-            station.location_id
+            station.location_id,
+            secret_hash
         )
         .fetch_one(&self.pool)
         .await?;
@@ -66,6 +248,39 @@ impl Query {
         Ok(rec)
     }
 
+    pub async fn update_station_secret(&self, token: &str, secret_hash: &str) -> Result<()> {
+        let updated = sqlx::query!(
+            r#"
+        UPDATE stations
+        SET secret_hash = $1
+        WHERE token = $2
+        "#,
+            secret_hash,
+            token
+        )
+        .execute(&self.pool)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(Error::NotFound(format!("station {token} not found")));
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_station_tokens_without_secret(&self) -> Result<Vec<String>> {
+        let rec = sqlx::query!(
+            r#"
+        SELECT token FROM stations
+        WHERE secret_hash IS NULL
+        ORDER BY token
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec.into_iter().map(|r| r.token).collect())
+    }
+
     pub async fn update_station(&self, station: &Station) -> Result<()> {
         sqlx::query!(
             r#"
@@ -0,0 +1,296 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+    error::{Error, Result},
+    models::page::Cursor,
+    repository::query::Query,
+};
+
+pub struct WebhookRecord {
+    pub id: i32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub station: Option<String>,
+    pub enabled: bool,
+    pub created: DateTime<Utc>,
+}
+
+pub struct WebhookDeliveryRecord {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt: DateTime<Utc>,
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    pub created: DateTime<Utc>,
+    pub payload: String,
+}
+
+pub struct DueDeliveryRecord {
+    pub id: i32,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+impl Query {
+    pub async fn get_webhooks(&self) -> Result<Vec<WebhookRecord>> {
+        let rec = sqlx::query_as!(
+            WebhookRecord,
+            r#"
+        SELECT w.id, w.url, w.events, s.token AS "station?", w.enabled, w.created
+        FROM webhooks w
+        LEFT JOIN stations s ON s.id = w.station_id
+        ORDER BY w.id
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    pub async fn get_webhook(&self, id: i32) -> Result<WebhookRecord> {
+        let rec = sqlx::query_as!(
+            WebhookRecord,
+            r#"
+        SELECT w.id, w.url, w.events, s.token AS "station?", w.enabled, w.created
+        FROM webhooks w
+        LEFT JOIN stations s ON s.id = w.station_id
+        WHERE w.id = $1
+        "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        rec.ok_or_else(|| Error::NotFound(format!("webhook {id} not found")))
+    }
+
+    /// The station is looked up by token, it must exist
+    pub async fn put_webhook(
+        &self,
+        url: &str,
+        events: &[String],
+        station: Option<&str>,
+        secret: &str,
+        enabled: bool,
+    ) -> Result<i32> {
+        let rec = sqlx::query!(
+            r#"
+        INSERT INTO webhooks (url, events, station_id, secret, enabled)
+        VALUES ($1, $2, (SELECT id FROM stations WHERE token = $3), $4, $5)
+        RETURNING id
+        "#,
+            url,
+            events,
+            station,
+            secret,
+            enabled
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(rec.id)
+    }
+
+    /// Everything but the secret is replaced
+    pub async fn update_webhook(
+        &self,
+        id: i32,
+        url: &str,
+        events: &[String],
+        station: Option<&str>,
+        enabled: bool,
+    ) -> Result<()> {
+        let updated = sqlx::query!(
+            r#"
+        UPDATE webhooks SET
+            url = $2,
+            events = $3,
+            station_id = (SELECT id FROM stations WHERE token = $4),
+            enabled = $5
+        WHERE id = $1
+        "#,
+            id,
+            url,
+            events,
+            station,
+            enabled
+        )
+        .execute(&self.pool)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(Error::NotFound(format!("webhook {id} not found")));
+        }
+
+        Ok(())
+    }
+
+    /// Delete the webhook together with its deliveries, in one transaction
+    pub async fn delete_webhook(&self, id: i32) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+        DELETE FROM webhook_deliveries
+        WHERE webhook_id = $1
+        "#,
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let deleted = sqlx::query!(
+            r#"
+        DELETE FROM webhooks
+        WHERE id = $1
+        "#,
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(Error::NotFound(format!("webhook {id} not found")));
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Up to `limit` deliveries of the webhook before the cursor, the latest first,
+    /// optionally only those with `status`
+    pub async fn get_webhook_deliveries(
+        &self,
+        webhook_id: i32,
+        status: Option<&str>,
+        cursor: Option<Cursor>,
+        limit: i64,
+    ) -> Result<Vec<WebhookDeliveryRecord>> {
+        let rec = sqlx::query_as!(
+            WebhookDeliveryRecord,
+            r#"
+        SELECT id, webhook_id, event, status, attempts, next_attempt, last_attempt, response_status, error, created, payload
+        FROM webhook_deliveries
+        WHERE webhook_id = $1
+        AND ($2::text IS NULL OR status = $2)
+        AND ($3::timestamptz IS NULL OR (created, id) < ($3, $4::int))
+        ORDER BY created DESC, id DESC
+        LIMIT $5
+        "#,
+            webhook_id,
+            status,
+            cursor.map(|c| c.date),
+            cursor.map(|c| c.id),
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    /// Queue a delivery of each event to every enabled webhook that subscribed to it and to
+    /// its station, in one statement. The events are given as parallel arrays, those already
+    /// queued under their dedupe key are skipped. Returns the number of queued deliveries
+    pub async fn put_webhook_deliveries(
+        &self,
+        events: &[String],
+        dedupe_keys: &[String],
+        payloads: &[String],
+        stations: &[String],
+    ) -> Result<u64> {
+        let queued = sqlx::query!(
+            r#"
+        INSERT INTO webhook_deliveries (webhook_id, event, dedupe_key, payload)
+        SELECT w.id, e.event, e.dedupe_key, e.payload
+        FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[]) AS e(event, dedupe_key, payload, station)
+        JOIN webhooks w ON w.enabled AND e.event = ANY(w.events)
+        WHERE w.station_id IS NULL
+        OR w.station_id = (SELECT id FROM stations WHERE token = e.station)
+        ON CONFLICT (webhook_id, dedupe_key) DO NOTHING
+        "#,
+            events,
+            dedupe_keys,
+            payloads,
+            stations
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(queued.rows_affected())
+    }
+
+    /// Claim up to `limit` pending deliveries of enabled webhooks that are due, the oldest
+    /// first. The other instances skip them until `lease_until`
+    pub async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Vec<DueDeliveryRecord>> {
+        let rec = sqlx::query_as!(
+            DueDeliveryRecord,
+            r#"
+        UPDATE webhook_deliveries d SET next_attempt = $2
+        FROM webhooks w
+        WHERE w.id = d.webhook_id
+        AND d.id IN (
+            SELECT id FROM webhook_deliveries
+            WHERE status = 'pending'
+            AND next_attempt <= NOW()
+            AND webhook_id IN (SELECT id FROM webhooks WHERE enabled)
+            ORDER BY next_attempt
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING d.id, d.event, d.payload, d.attempts, w.url, w.secret
+        "#,
+            limit,
+            lease_until
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rec)
+    }
+
+    /// Record the outcome of an attempt to send the delivery
+    pub async fn update_webhook_delivery(
+        &self,
+        id: i32,
+        status: &str,
+        attempts: i32,
+        next_attempt: DateTime<Utc>,
+        response_status: Option<i32>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+        UPDATE webhook_deliveries SET
+            status = $2,
+            attempts = $3,
+            next_attempt = $4,
+            last_attempt = NOW(),
+            response_status = $5,
+            error = $6
+        WHERE id = $1
+        "#,
+            id,
+            status,
+            attempts,
+            next_attempt,
+            response_status,
+            error
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
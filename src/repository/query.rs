@@ -1,4 +1,15 @@
+use futures_util::stream::{self, BoxStream, StreamExt};
 use sqlx::{Pool, Postgres};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::error::Result;
+
+/// Rows a streamed query may run ahead of the consumer
+const STREAM_BUFFER: usize = 256;
+
+/// Rows of a query that runs on a task of its own, the query stops once the stream is
+/// dropped
+pub type RowStream<T> = BoxStream<'static, Result<T>>;
 
 pub struct Query {
     pub pool: Pool<Postgres>,
@@ -9,3 +20,16 @@ impl Query {
         Query { pool }
     }
 }
+
+/// A bounded channel for the rows of a streamed query, the receiving half is turned into
+/// a stream with [`row_stream`]
+pub fn row_channel<T>() -> (Sender<Result<T>>, Receiver<Result<T>>) {
+    mpsc::channel(STREAM_BUFFER)
+}
+
+pub fn row_stream<T: Send + 'static>(rows: Receiver<Result<T>>) -> RowStream<T> {
+    stream::unfold(rows, |mut rows| async move {
+        rows.recv().await.map(|row| (row, rows))
+    })
+    .boxed()
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use actix_web::web::Data;
+
+use crate::{
+    api::alert::{AlertRuleRequest, AlertsRequest},
+    error::{Error, Result},
+    events::Event,
+    models::{
+        alert::{Alert, AlertRule},
+        page::{page_params, Page},
+        reading::Reading,
+        station::Station,
+    },
+    repository::db::DBRepository,
+    services::event_service::EventService,
+};
+
+pub struct AlertService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> AlertService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        AlertService { db }
+    }
+
+    pub async fn get_rules(&self) -> Result<Vec<AlertRule>> {
+        self.db.get_alert_rules().await
+    }
+
+    pub async fn get_rule(&self, id: i32) -> Result<AlertRule> {
+        self.db.get_alert_rule(id).await
+    }
+
+    pub async fn put_rule(&self, request: AlertRuleRequest) -> Result<AlertRule> {
+        let rule = self.validate_rule(0, request).await?;
+
+        self.db.put_alert_rule(&rule).await
+    }
+
+    /// Replace the rule. Its firing alerts are resolved and its evaluation starts over, so
+    /// they fire again once the new rule is breached
+    pub async fn update_rule(&self, id: i32, request: AlertRuleRequest) -> Result<AlertRule> {
+        let rule = self.validate_rule(id, request).await?;
+        let resolved = self.db.update_alert_rule(&rule).await?;
+        self.publish(resolved).await;
+
+        self.db.get_alert_rule(id).await
+    }
+
+    /// Delete the rule and its alerts, disable it instead to keep them
+    pub async fn delete_rule(&self, id: i32) -> Result<()> {
+        self.db.delete_alert_rule(id).await
+    }
+
+    /// A page of the alerts, the latest first. Page sizes above the configured maximum are
+    /// reduced to it
+    pub async fn get_alerts(&self, request: AlertsRequest) -> Result<Page<Alert>> {
+        let (cursor, limit) = page_params(
+            request.cursor.as_deref(),
+            request.limit,
+            &self.db.settings().pagination,
+        )?;
+        if let Some(token) = &request.station {
+            self.db.get_station(token.clone(), false).await?;
+        }
+
+        self.db
+            .get_alerts(
+                request.station.as_deref(),
+                request.rule,
+                request.firing,
+                cursor,
+                limit,
+            )
+            .await
+    }
+
+    /// Evaluate the rules that apply to the station against its new readings, which must
+    /// be sorted by date. Returns the alerts that fired or resolved
+    pub async fn evaluate(&self, station: &Station, readings: &[Reading]) -> Result<Vec<Alert>> {
+        self.db
+            .evaluate_alert_rules(station, |rule, state| {
+                readings
+                    .iter()
+                    .filter_map(|reading| {
+                        rule.evaluate(state, reading.date, reading.value(rule.metric))
+                    })
+                    .collect()
+            })
+            .await
+    }
+
+    /// Hand the alerts that fired or resolved to the live subscribers and the webhooks
+    pub async fn publish(&self, alerts: Vec<Alert>) {
+        let events = alerts
+            .into_iter()
+            .map(|alert| Event::Alert(Arc::new(alert)))
+            .collect();
+        EventService::new(self.db).publish(events).await;
+    }
+
+    async fn validate_rule(&self, id: i32, request: AlertRuleRequest) -> Result<AlertRule> {
+        if request.name.trim().is_empty() {
+            return Err(Error::Validation("rule name must not be empty".into()));
+        }
+        let clear_threshold = request.clear_threshold.unwrap_or(request.threshold);
+        if !request.threshold.is_finite() || !clear_threshold.is_finite() {
+            return Err(Error::Validation(
+                "thresholds must be finite numbers".into(),
+            ));
+        }
+        if request
+            .comparison
+            .exceeds(clear_threshold, request.threshold)
+        {
+            return Err(Error::Validation(format!(
+                "clear_threshold must not be {} threshold",
+                request.comparison.as_str()
+            )));
+        }
+        if request.duration_seconds < 0 {
+            return Err(Error::Validation(
+                "duration_seconds must not be negative".into(),
+            ));
+        }
+
+        match (&request.station, &request.group) {
+            (Some(_), Some(_)) => {
+                return Err(Error::Validation(
+                    "a rule is limited to a station or to a group, not both".into(),
+                ))
+            }
+            (Some(token), None) => {
+                self.db.get_station(token.clone(), false).await?;
+            }
+            (None, Some(name)) => {
+                self.db.get_group(name).await?;
+            }
+            (None, None) => {}
+        }
+
+        Ok(AlertRule {
+            id,
+            name: request.name,
+            metric: request.metric,
+            comparison: request.comparison,
+            threshold: request.threshold,
+            clear_threshold,
+            duration_seconds: request.duration_seconds,
+            station: request.station,
+            group: request.group,
+            enabled: request.enabled.unwrap_or(true),
+        })
+    }
+}
@@ -0,0 +1,324 @@
+use actix_web::web::Data;
+use chrono::{Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    api::auth::SignedPayload,
+    error::{Error, Result},
+    models::{
+        nonce::Nonce,
+        station::{Station, StationCredentials},
+    },
+    repository::db::DBRepository,
+};
+
+pub struct AuthService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> AuthService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        AuthService { db }
+    }
+
+    /// Resolve the station identified by `token`, provided `secret` matches the one
+    /// it was issued at registration
+    pub async fn authenticate(&self, token: String, secret: &str) -> Result<Station> {
+        let (station, secret_hash) = match self.db.get_station_credentials(token).await {
+            Err(Error::NotFound(_)) => return Err(invalid_credentials()),
+            result => result?,
+        };
+        let secret_hash = secret_hash.ok_or_else(invalid_credentials)?;
+
+        if constant_time_eq(hash_secret(secret).as_bytes(), secret_hash.as_bytes()) {
+            Ok(station)
+        } else {
+            Err(invalid_credentials())
+        }
+    }
+
+    /// Check the bearer token of a request to an admin endpoint against `auth.admin_token`
+    pub fn authorize_admin(&self, token: &str) -> Result<()> {
+        let admin_token = self
+            .db
+            .settings()
+            .auth
+            .admin_token
+            .as_deref()
+            .ok_or_else(|| Error::Unauthorized("admin endpoints are disabled".into()))?;
+
+        if constant_time_eq(token.as_bytes(), admin_token.as_bytes()) {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized("invalid admin token".into()))
+        }
+    }
+
+    /// Generate the credentials of a station, along with the hash of its secret that is
+    /// stored in its place
+    pub fn issue_credentials(&self) -> (StationCredentials, String) {
+        let secret = generate_secret();
+        let secret_hash = hash_secret(&secret);
+        let credentials = StationCredentials {
+            signing_key: self.signing_key(&secret_hash),
+            secret,
+        };
+
+        (credentials, secret_hash)
+    }
+
+    /// Resolve the station identified by `token`, provided `payload` is fresh and was
+    /// signed with the station's key. The nonce of the payload is returned rather than
+    /// stored, it has to be stored along with what the payload carried.
+    ///
+    /// The signing key is derived from the hash of the station secret with the server's
+    /// `auth.signing_secret`, which isn't stored in the database. It's handed to the
+    /// station with its secret, see `issue_credentials`.
+    pub async fn verify_signature(
+        &self,
+        token: String,
+        payload: &SignedPayload,
+    ) -> Result<(Station, Nonce)> {
+        let (station, secret_hash) = match self.db.get_station_credentials(token).await {
+            Err(Error::NotFound(_)) => return Err(invalid_signature()),
+            result => result?,
+        };
+        let secret_hash = secret_hash.ok_or_else(invalid_signature)?;
+
+        let signing_key = self.signing_key(&secret_hash);
+        let mut mac = Hmac::<Sha256>::new_from_slice(signing_key.as_bytes())
+            .map_err(|_| invalid_signature())?;
+        mac.update(payload.timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(payload.nonce.as_bytes());
+        mac.update(b".");
+        mac.update(&payload.body);
+
+        let signature = hex::decode(&payload.signature).map_err(|_| invalid_signature())?;
+        mac.verify_slice(&signature)
+            .map_err(|_| invalid_signature())?;
+
+        let now = Utc::now();
+        let max_skew = Duration::seconds(self.db.settings().windows.max_clock_skew_seconds);
+        let signed_at = Utc
+            .timestamp_millis_opt(payload.timestamp)
+            .single()
+            .ok_or_else(invalid_signature)?;
+        if signed_at < now - max_skew || signed_at > now + max_skew {
+            return Err(Error::Unauthorized(
+                "signature timestamp outside the allowed clock skew".into(),
+            ));
+        }
+
+        // A nonce has to be remembered for as long as its timestamp could still be accepted
+        let nonce = Nonce {
+            station_id: station.id,
+            value: payload.nonce.clone(),
+            expire_before: now - max_skew * 2,
+        };
+
+        Ok((station, nonce))
+    }
+
+    /// Hex encoded HMAC-SHA256 of the secret hash, keyed with `auth.signing_secret`
+    fn signing_key(&self, secret_hash: &str) -> String {
+        let signing_secret = self
+            .db
+            .settings()
+            .auth
+            .signing_secret
+            .as_deref()
+            .unwrap_or_default();
+        let mut mac = Hmac::<Sha256>::new_from_slice(signing_secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(secret_hash.as_bytes());
+
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+/// Generate a new random station secret, hex encoded
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    hex::encode(bytes)
+}
+
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn invalid_credentials() -> Error {
+    Error::Unauthorized("invalid station credentials".into())
+}
+
+fn invalid_signature() -> Error {
+    Error::Unauthorized("invalid signature".into())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{reading_body, settings, sign, sign_at, TestDb};
+
+    const SKEW_SECONDS: i64 = 300;
+
+    fn assert_unauthorized(result: Result<(Station, Nonce)>, message: &str) {
+        match result {
+            Err(Error::Unauthorized(m)) => assert_eq!(m, message),
+            Err(e) => panic!("expected unauthorized, got {e}"),
+            Ok(_) => panic!("expected unauthorized, the signature was accepted"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_fresh_signature_resolves_the_station() {
+        let db = TestDb::new().await;
+        let (station, credentials) = db.station("st-1").await;
+        let payload = sign(&credentials.signing_key, "{}");
+
+        let (verified, nonce) = AuthService::new(&db)
+            .verify_signature("st-1".into(), &payload)
+            .await
+            .unwrap();
+        assert_eq!(verified.id, station.id);
+        assert_eq!(nonce.station_id, station.id);
+        assert_eq!(nonce.value, payload.nonce);
+
+        // Nonces are kept for as long as a payload carrying them could still be accepted
+        let window = Utc::now() - Duration::seconds(2 * SKEW_SECONDS) - nonce.expire_before;
+        assert!(window >= Duration::zero() && window < Duration::seconds(5));
+    }
+
+    #[actix_web::test]
+    async fn tampered_payloads_are_refused() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let (_, other) = db.station("st-2").await;
+        let service = AuthService::new(&db);
+        let body = reading_body("st-1", Utc::now(), 5.0);
+        let signed = sign(&credentials.signing_key, &body);
+
+        let mut payload = sign(&credentials.signing_key, &body);
+        payload.body = reading_body("st-1", Utc::now(), 500.0).into();
+        assert_unauthorized(
+            service.verify_signature("st-1".into(), &payload).await,
+            "invalid signature",
+        );
+
+        let mut payload = sign(&credentials.signing_key, &body);
+        payload.nonce = "someothernonce".into();
+        assert_unauthorized(
+            service.verify_signature("st-1".into(), &payload).await,
+            "invalid signature",
+        );
+
+        let mut payload = sign(&credentials.signing_key, &body);
+        payload.timestamp += 1;
+        assert_unauthorized(
+            service.verify_signature("st-1".into(), &payload).await,
+            "invalid signature",
+        );
+
+        let mut payload = sign(&credentials.signing_key, &body);
+        payload.signature = "not hex".into();
+        assert_unauthorized(
+            service.verify_signature("st-1".into(), &payload).await,
+            "invalid signature",
+        );
+
+        // Signed by another station, or sent on behalf of one
+        let payload = sign(&other.signing_key, &body);
+        assert_unauthorized(
+            service.verify_signature("st-1".into(), &payload).await,
+            "invalid signature",
+        );
+        assert_unauthorized(
+            service.verify_signature("st-2".into(), &signed).await,
+            "invalid signature",
+        );
+        assert_unauthorized(
+            service.verify_signature("unknown".into(), &signed).await,
+            "invalid signature",
+        );
+
+        assert!(service
+            .verify_signature("st-1".into(), &signed)
+            .await
+            .is_ok());
+    }
+
+    #[actix_web::test]
+    async fn signatures_outside_the_clock_skew_are_refused() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let service = AuthService::new(&db);
+        let at = |offset: i64| {
+            let timestamp = (Utc::now() + Duration::seconds(offset)).timestamp_millis();
+            sign_at(&credentials.signing_key, "{}", timestamp, "0123456789")
+        };
+
+        for offset in [-SKEW_SECONDS - 5, SKEW_SECONDS + 5, -86_400, 86_400] {
+            assert_unauthorized(
+                service.verify_signature("st-1".into(), &at(offset)).await,
+                "signature timestamp outside the allowed clock skew",
+            );
+        }
+        for offset in [-SKEW_SECONDS + 5, 0, SKEW_SECONDS - 5] {
+            assert!(service
+                .verify_signature("st-1".into(), &at(offset))
+                .await
+                .is_ok());
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_clock_skew_is_configurable() {
+        let mut settings = settings();
+        settings.windows.max_clock_skew_seconds = 10;
+        let db = TestDb::with_settings(settings).await;
+        let (_, credentials) = db.station("st-1").await;
+        let timestamp = (Utc::now() - Duration::seconds(30)).timestamp_millis();
+        let payload = sign_at(&credentials.signing_key, "{}", timestamp, "0123456789");
+
+        assert_unauthorized(
+            AuthService::new(&db)
+                .verify_signature("st-1".into(), &payload)
+                .await,
+            "signature timestamp outside the allowed clock skew",
+        );
+    }
+
+    #[actix_web::test]
+    async fn signing_keys_depend_on_the_signing_secret() {
+        let db = TestDb::new().await;
+        let (_, credentials) = db.station("st-1").await;
+        let payload = sign(&credentials.signing_key, "{}");
+
+        let mut settings = settings();
+        settings.auth.signing_secret = Some("f".repeat(32));
+        let other = db.with_other_settings(settings);
+
+        assert_unauthorized(
+            AuthService::new(&other)
+                .verify_signature("st-1".into(), &payload)
+                .await,
+            "invalid signature",
+        );
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_slices() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"x"));
+    }
+}
@@ -0,0 +1,47 @@
+use actix_web::web::Data;
+use log::{debug, error};
+
+use crate::{
+    events::Event, repository::db::DBRepository, services::webhook_service::WebhookService,
+};
+
+pub struct EventService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> EventService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        EventService { db }
+    }
+
+    /// Queue the events for the webhooks and hand them to the live subscribers of every
+    /// instance
+    pub async fn publish(&self, events: Vec<Event>) {
+        if events.is_empty() {
+            return;
+        }
+
+        self.queue_webhook_deliveries(&events).await;
+        self.db.publish_events(events).await;
+    }
+
+    /// Like `publish`, but only for the live subscribers of this instance, for events every
+    /// instance detects on its own. The webhooks still get them once
+    pub async fn publish_local(&self, events: Vec<Event>) {
+        if events.is_empty() {
+            return;
+        }
+
+        self.queue_webhook_deliveries(&events).await;
+        self.db.publish_local_events(events);
+    }
+
+    /// A failure is only logged, the events are stored already
+    async fn queue_webhook_deliveries(&self, events: &[Event]) {
+        match WebhookService::new(self.db).queue(events).await {
+            Ok(0) => {}
+            Ok(count) => debug!("queued {count} webhook deliveries"),
+            Err(e) => error!("failed to queue webhook deliveries: {e}"),
+        }
+    }
+}
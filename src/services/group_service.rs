@@ -0,0 +1,33 @@
+use actix_web::web::Data;
+
+use crate::{
+    error::{Error, Result},
+    models::group::StationGroup,
+    repository::db::DBRepository,
+};
+
+pub struct GroupService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> GroupService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        GroupService { db }
+    }
+
+    pub async fn get_group(&self, name: String) -> Result<StationGroup> {
+        self.db.get_group(&name).await
+    }
+
+    /// Create the group, or replace its members if it already exists
+    pub async fn put_group(&self, name: String, mut tokens: Vec<String>) -> Result<StationGroup> {
+        if name.trim().is_empty() {
+            return Err(Error::Validation("group name must not be empty".into()));
+        }
+
+        tokens.sort();
+        tokens.dedup();
+
+        self.db.put_group(&name, &tokens).await
+    }
+}
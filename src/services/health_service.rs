@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use actix_web::{rt::time::timeout, web::Data};
+
+use crate::{
+    models::health::{Readiness, Version},
+    repository::db::DBRepository,
+    services::schema_service::SchemaService,
+};
+
+/// How long the database gets to answer a readiness check
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct HealthService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> HealthService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        HealthService { db }
+    }
+
+    /// Ready when the database is reachable and the schema is up to date
+    pub async fn get_readiness(&self) -> Readiness {
+        let database = matches!(timeout(READINESS_TIMEOUT, self.db.ping()).await, Ok(Ok(())));
+        let migrations = database
+            && matches!(
+                timeout(READINESS_TIMEOUT, SchemaService::new(self.db).get_status()).await,
+                Ok(Ok(status)) if status.is_up_to_date()
+            );
+
+        Readiness {
+            ready: database && migrations,
+            database,
+            migrations,
+        }
+    }
+
+    /// The schema version is left out if the database can't be reached
+    pub async fn get_version(&self) -> Version {
+        let schema_version = timeout(READINESS_TIMEOUT, SchemaService::new(self.db).get_status())
+            .await
+            .ok()
+            .and_then(|status| status.ok())
+            .and_then(|status| status.schema_version);
+
+        Version {
+            version: env!("CARGO_PKG_VERSION").into(),
+            git_hash: env!("AUSPEX_GIT_HASH").into(),
+            schema_version,
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use actix_web::web::Data;
+
+use crate::{
+    api::heatmap::HeatmapRequest,
+    error::{Error, Result},
+    models::{geo::BoundingBox, geojson::FeatureCollection, heatmap::HeatmapGrid},
+    repository::db::DBRepository,
+    services::reading_service::MAX_WINDOW,
+};
+
+/// Rows and columns of a heatmap when none are given
+pub const DEFAULT_GRID_SIZE: usize = 50;
+
+/// Maximum number of rows and of columns of a heatmap
+pub const MAX_GRID_SIZE: usize = 200;
+
+/// Number of contour levels when none are given
+pub const DEFAULT_LEVELS: usize = 10;
+
+/// Maximum number of contour levels
+pub const MAX_LEVELS: usize = 50;
+
+pub struct HeatmapService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> HeatmapService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        HeatmapService { db }
+    }
+
+    pub async fn get_heatmap(&self, request: &HeatmapRequest) -> Result<HeatmapGrid> {
+        let bbox = BoundingBox {
+            min_lat: request.min_lat,
+            min_lon: request.min_lon,
+            max_lat: request.max_lat,
+            max_lon: request.max_lon,
+        };
+        bbox.validate()?;
+        if bbox.min_lat == bbox.max_lat || bbox.lon_span() == 0.0 {
+            return Err(Error::Validation("bounding box must not be empty".into()));
+        }
+
+        let rows = request.rows.unwrap_or(DEFAULT_GRID_SIZE);
+        let cols = request.cols.unwrap_or(DEFAULT_GRID_SIZE);
+        for (name, size) in [("rows", rows), ("cols", cols)] {
+            if !(2..=MAX_GRID_SIZE).contains(&size) {
+                return Err(Error::Validation(format!(
+                    "{name} must be between 2 and {MAX_GRID_SIZE}, got {size}"
+                )));
+            }
+        }
+
+        if let Some(max) = request.max_distance_km {
+            if max.is_nan() || max <= 0.0 {
+                return Err(Error::Validation("max_distance_km must be positive".into()));
+            }
+        }
+        if let Some(window) = request.window {
+            if window.seconds > MAX_WINDOW.seconds {
+                return Err(Error::Validation(format!(
+                    "window must not be longer than {MAX_WINDOW}"
+                )));
+            }
+        }
+
+        self.db
+            .get_heatmap(
+                bbox,
+                request.metric,
+                request.window,
+                (rows, cols),
+                request.max_distance_km,
+            )
+            .await
+    }
+
+    /// Contour lines of the heatmap at the given levels, or at evenly spaced levels
+    /// between its lowest and highest value
+    pub async fn get_contours(
+        &self,
+        request: &HeatmapRequest,
+        levels: Option<Vec<f32>>,
+    ) -> Result<FeatureCollection> {
+        if let Some(levels) = &levels {
+            if levels.is_empty() || levels.len() > MAX_LEVELS {
+                return Err(Error::Validation(format!(
+                    "between 1 and {MAX_LEVELS} levels are required"
+                )));
+            }
+            if levels.iter().any(|level| !level.is_finite()) {
+                return Err(Error::Validation("levels must be finite numbers".into()));
+            }
+        }
+
+        let grid = self.get_heatmap(request).await?;
+        let levels = levels.unwrap_or_else(|| grid.default_levels(DEFAULT_LEVELS));
+
+        Ok(grid.contours(&levels))
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use actix_web::web::Data;
+
+use crate::{
+    api::live::{ClientMessage, LiveReading, ServerMessage, Topics},
+    error::{Error, Result},
+    events::Event,
+    models::metric::Metric,
+    repository::db::DBRepository,
+};
+
+pub struct LiveService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+/// What a live client subscribed to, nothing is delivered while it is empty
+#[derive(Default)]
+pub struct LiveSubscription {
+    stations: BTreeSet<String>,
+    /// Member tokens of each group, as they were when subscribing
+    groups: BTreeMap<String, Vec<String>>,
+    metrics: HashSet<Metric>,
+}
+
+impl<'a> LiveService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        LiveService { db }
+    }
+
+    /// Apply a message of the client to its subscription and tell what the subscription
+    /// became, or why the message was refused
+    pub async fn handle(&self, subscription: &mut LiveSubscription, text: &str) -> ServerMessage {
+        let changed = match serde_json::from_str(text) {
+            Ok(ClientMessage::Subscribe(topics)) => self.subscribe(subscription, topics).await,
+            Ok(ClientMessage::Unsubscribe(topics)) => {
+                subscription.remove(&topics);
+                Ok(())
+            }
+            Err(e) => Err(Error::from(e)),
+        };
+
+        match changed {
+            Ok(()) => ServerMessage::Subscribed(subscription.topics()),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Add the topics, leaving the subscription as it was when a station or group is
+    /// unknown
+    async fn subscribe(&self, subscription: &mut LiveSubscription, topics: Topics) -> Result<()> {
+        for token in &topics.stations {
+            self.db.get_station(token.clone(), false).await?;
+        }
+
+        let mut groups = Vec::with_capacity(topics.groups.len());
+        for name in &topics.groups {
+            groups.push(self.db.get_group(name).await?);
+        }
+
+        subscription.stations.extend(topics.stations);
+        subscription
+            .groups
+            .extend(groups.into_iter().map(|group| (group.name, group.stations)));
+        subscription.metrics.extend(topics.metrics);
+
+        Ok(())
+    }
+}
+
+impl LiveSubscription {
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty() && self.groups.is_empty() && self.metrics.is_empty()
+    }
+
+    fn remove(&mut self, topics: &Topics) {
+        for token in &topics.stations {
+            self.stations.remove(token);
+        }
+        for name in &topics.groups {
+            self.groups.remove(name);
+        }
+        for metric in &topics.metrics {
+            self.metrics.remove(metric);
+        }
+    }
+
+    pub fn topics(&self) -> Topics {
+        Topics {
+            stations: self.stations.iter().cloned().collect(),
+            groups: self.groups.keys().cloned().collect(),
+            metrics: Metric::ALL
+                .into_iter()
+                .filter(|metric| self.metrics.contains(metric))
+                .collect(),
+        }
+    }
+
+    fn includes_station(&self, token: &str) -> bool {
+        if self.stations.is_empty() && self.groups.is_empty() {
+            return true;
+        }
+
+        self.stations.contains(token)
+            || self
+                .groups
+                .values()
+                .any(|members| members.iter().any(|member| member == token))
+    }
+
+    /// The message telling the client about the event, None when it is not subscribed to it
+    pub fn message(&self, event: &Event) -> Option<ServerMessage> {
+        if self.is_empty() || !self.includes_station(event.station()) {
+            return None;
+        }
+
+        let message = match event {
+            Event::Reading(event) => {
+                let values = Metric::ALL
+                    .into_iter()
+                    .filter(|metric| self.metrics.is_empty() || self.metrics.contains(metric))
+                    .map(|metric| (metric.column(), event.reading.value(metric)))
+                    .collect();
+
+                ServerMessage::Reading(LiveReading {
+                    station: event.station.clone(),
+                    id: event.reading.id,
+                    date: event.reading.date,
+                    values,
+                })
+            }
+            Event::StationStatus(event) => ServerMessage::StationStatus(event.clone()),
+            Event::Alert(alert) => {
+                if !self.metrics.is_empty() && !self.metrics.contains(&alert.metric) {
+                    return None;
+                }
+                ServerMessage::Alert(alert.clone())
+            }
+        };
+
+        Some(message)
+    }
+}
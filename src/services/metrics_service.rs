@@ -0,0 +1,24 @@
+use actix_web::web::Data;
+
+use crate::{error::Result, metrics, repository::db::DBRepository};
+
+pub struct MetricsService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> MetricsService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        MetricsService { db }
+    }
+
+    /// Refresh the pool and fleet gauges and render all metrics
+    pub async fn gather(&self) -> Result<String> {
+        let (size, idle, max) = self.db.pool_usage();
+        metrics::set_pool_usage(size, idle, max);
+
+        let stations = self.db.get_active_station_readings().await?;
+        metrics::set_fleet(&stations);
+
+        Ok(metrics::encode())
+    }
+}
@@ -1,2 +1,13 @@
+pub mod auth_service;
+pub mod health_service;
+pub mod schema_service;
 pub mod station_service;
-pub mod reading_service;
\ No newline at end of file
+pub mod reading_service;
+pub mod metrics_service;
+pub mod group_service;
+pub mod heatmap_service;
+pub mod live_service;
+pub mod presence_service;
+pub mod alert_service;
+pub mod webhook_service;
+pub mod event_service;
\ No newline at end of file
@@ -0,0 +1,63 @@
+use std::{sync::Arc, time::Duration as StdDuration};
+
+use actix_web::{rt::time::interval, web::Data};
+use chrono::{DateTime, Duration, Utc};
+use log::error;
+
+use crate::{
+    error::Result,
+    events::{Event, StationStatusEvent},
+    repository::db::DBRepository,
+    services::event_service::EventService,
+};
+
+pub struct PresenceService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> PresenceService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        PresenceService { db }
+    }
+
+    /// Announce the stations that became inactive, checking every
+    /// `events.status_check_seconds`. Runs until the process ends. Every instance runs its
+    /// own check, so the announcements stay local
+    pub async fn watch(&self) {
+        let period = StdDuration::from_secs(self.db.settings().events.status_check_seconds);
+        let mut ticks = interval(period);
+        let mut checked = Utc::now();
+
+        loop {
+            ticks.tick().await;
+            let now = Utc::now();
+            match self.announce_offline(checked, now).await {
+                Ok(()) => checked = now,
+                Err(e) => error!("failed to check for offline stations: {e}"),
+            }
+        }
+    }
+
+    /// Announce the stations whose activity window ended in `(after, until]`
+    async fn announce_offline(&self, after: DateTime<Utc>, until: DateTime<Utc>) -> Result<()> {
+        let window = Duration::minutes(self.db.settings().windows.active_station_minutes);
+        let stations = self
+            .db
+            .get_stations_last_online_between(after - window, until - window)
+            .await?;
+
+        let events = stations
+            .into_iter()
+            .map(|station| {
+                Event::StationStatus(Arc::new(StationStatusEvent {
+                    station: station.token,
+                    online: false,
+                    last_online: station.last_online,
+                }))
+            })
+            .collect();
+        EventService::new(self.db).publish_local(events).await;
+
+        Ok(())
+    }
+}
@@ -1,11 +1,57 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
 use actix_web::web::Data;
-use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use log::error;
 
 use crate::{
-    api::reading::AddReadingRequest,
-    models::reading::{AverageReading, Reading},
-    repository::db::DBRepository,
+    api::{
+        auth::SignedPayload,
+        reading::{AddReadingBatchItem, AddReadingRequest, BatchReadingResult, PageRequest},
+    },
+    error::{Error, Result},
+    events::{Event, ReadingEvent, ReadingSubscription, StationStatusEvent},
+    metrics,
+    models::{
+        aggregate::{Bucket, ComparisonSeries, ReadingBucket, Window},
+        alert::Alert,
+        aqi::Aqi,
+        metric::Metric,
+        page::{page_params, Cursor, Page},
+        reading::{AverageReading, Reading},
+        station::Station,
+    },
+    repository::{db::DBRepository, query::RowStream},
+    services::{
+        alert_service::AlertService, auth_service::AuthService, event_service::EventService,
+    },
+};
+
+/// Maximum number of readings stored from a single batch, the rest is rejected
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+/// Maximum number of buckets a single aggregation may span
+pub const MAX_BUCKETS: i64 = 10_000;
+
+/// Maximum number of buckets a single export may span, exports are streamed so this
+/// allows a year of one minute buckets
+pub const MAX_EXPORT_BUCKETS: i64 = 366 * 24 * 60 + 1;
+
+/// Maximum number of stations in a single comparison
+pub const MAX_COMPARED_STATIONS: usize = 50;
+
+/// Maximum number of extra windows in a single average request
+pub const MAX_WINDOWS: usize = 10;
+
+/// Maximum number of missed readings sent to a stream that resumes, older ones are lost
+pub const MAX_REPLAYED_READINGS: i64 = 1000;
+
+/// Longest window an average may be computed over
+pub const MAX_WINDOW: Window = Window {
+    seconds: 366 * 24 * 60 * 60,
 };
 
 pub struct ReadingService<'a> {
@@ -17,14 +63,120 @@ impl<'a> ReadingService<'a> {
         ReadingService { db }
     }
 
+    /// A page of the readings in `[start, end]`, oldest first
     pub async fn get_readings_between(
         &self,
         token: String,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
-    ) -> Result<Vec<Reading>> {
+        page: &PageRequest,
+    ) -> Result<Page<Reading>> {
+        if start > end {
+            return Err(Error::Validation("start must not be after end".into()));
+        }
+        let (cursor, limit) = self.page_params(page)?;
+
+        let station = self.db.get_station(token, false).await?;
+        self.db
+            .get_readings_between(station, start, end, cursor, limit)
+            .await
+    }
+
+    /// Like `get_readings_between` but unpaged and streamed, for exports
+    pub async fn stream_readings_between(
+        &self,
+        token: String,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<RowStream<Reading>> {
+        if start > end {
+            return Err(Error::Validation("start must not be after end".into()));
+        }
+
+        let station = self.db.get_station(token, false).await?;
+        Ok(self.db.stream_readings_between(station, start, end))
+    }
+
+    /// Like `get_reading_buckets` but streamed, for exports
+    pub async fn stream_reading_buckets(
+        &self,
+        token: String,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        bucket: Bucket,
+    ) -> Result<RowStream<ReadingBucket>> {
+        validate_buckets(start, end, bucket, MAX_EXPORT_BUCKETS)?;
+
+        let station = self.db.get_station(token, false).await?;
+        Ok(self
+            .db
+            .stream_reading_buckets(station, start, end, bucket.seconds()))
+    }
+
+    /// Aggregate the readings in `[start, end)` into buckets of the given width
+    pub async fn get_reading_buckets(
+        &self,
+        token: String,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        bucket: Bucket,
+    ) -> Result<Vec<ReadingBucket>> {
+        validate_buckets(start, end, bucket, MAX_BUCKETS)?;
+
         let station = self.db.get_station(token, false).await?;
-        self.db.get_readings_between(station, start, end).await
+        self.db
+            .get_reading_buckets(station, start, end, bucket.seconds())
+            .await
+    }
+
+    /// Aggregate one metric of several stations into aligned buckets of `[start, end)`. The
+    /// stations are the given tokens together with the members of `group`
+    pub async fn compare_readings(
+        &self,
+        mut tokens: Vec<String>,
+        group: Option<String>,
+        metric: Metric,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        bucket: Bucket,
+    ) -> Result<ComparisonSeries> {
+        validate_buckets(start, end, bucket, MAX_BUCKETS)?;
+
+        if let Some(group) = group {
+            tokens.extend(self.db.get_group(&group).await?.stations);
+        }
+        tokens.sort();
+        tokens.dedup();
+
+        if tokens.is_empty() {
+            return Err(Error::Validation(
+                "no stations to compare, pass stations or a group with members".into(),
+            ));
+        }
+        if tokens.len() > MAX_COMPARED_STATIONS {
+            return Err(Error::Validation(format!(
+                "at most {MAX_COMPARED_STATIONS} stations can be compared at once"
+            )));
+        }
+
+        let series = self
+            .db
+            .get_series(&tokens, metric, start, end, bucket)
+            .await?;
+
+        let unknown: Vec<&str> = tokens
+            .iter()
+            .filter(|token| !series.series.contains_key(*token))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(Error::NotFound(format!(
+                "stations {} not found",
+                unknown.join(", ")
+            )));
+        }
+
+        Ok(series)
     }
 
     pub async fn get_latest_reading(&self, token: String) -> Result<Reading> {
@@ -32,37 +184,318 @@ impl<'a> ReadingService<'a> {
         self.db.get_latest_reading(station).await
     }
 
-    pub async fn get_latest_readings(&self, token: String, count: i64) -> Result<Vec<Reading>> {
+    /// The latest reading of each of the stations that has one, by station id
+    pub async fn get_latest_reading_of_stations(
+        &self,
+        stations: &[Station],
+    ) -> Result<HashMap<i32, Reading>> {
+        self.db.get_latest_reading_of_stations(stations).await
+    }
+
+    /// A page of the readings of the station, newest first
+    pub async fn get_latest_readings(
+        &self,
+        token: String,
+        page: &PageRequest,
+    ) -> Result<Page<Reading>> {
+        let (cursor, limit) = self.page_params(page)?;
+
+        let station = self.db.get_station(token, false).await?;
+        self.db.get_latest_readings(station, cursor, limit).await
+    }
+
+    /// Statistics of the last hour and day, and of every extra window
+    pub async fn get_average_reading(
+        &self,
+        token: String,
+        windows: &[Window],
+    ) -> Result<AverageReading> {
+        if windows.len() > MAX_WINDOWS {
+            return Err(Error::Validation(format!(
+                "at most {MAX_WINDOWS} windows are allowed"
+            )));
+        }
+        if let Some(window) = windows.iter().find(|w| w.seconds > MAX_WINDOW.seconds) {
+            return Err(Error::Validation(format!(
+                "window {window} is longer than {MAX_WINDOW}"
+            )));
+        }
+
+        let station = self.db.get_station(token, false).await?;
+        self.db.get_average_reading(station, windows).await
+    }
+
+    /// Air quality indices of the station, computed from its hourly and daily averages
+    pub async fn get_aqi(&self, token: String) -> Result<Aqi> {
         let station = self.db.get_station(token, false).await?;
-        self.db.get_latest_readings(station, count).await
+        self.db.get_aqi(&station).await
+    }
+
+    /// The air quality indices of each of the stations, by station id
+    pub async fn get_aqi_of_stations(&self, stations: &[Station]) -> Result<HashMap<i32, Aqi>> {
+        self.db.get_aqi_of_stations(stations).await
     }
 
-    pub async fn get_average_reading(&self, token: String) -> Result<AverageReading> {
+    /// The latest reading of the station along with its air quality indices
+    pub async fn get_latest_reading_aqi(&self, token: String) -> Result<(Reading, Aqi)> {
         let station = self.db.get_station(token, false).await?;
-        self.db.get_average_reading(station).await
+        let aqi = self.db.get_aqi(&station).await?;
+        let reading = self.db.get_latest_reading(station).await?;
+
+        Ok((reading, aqi))
     }
 
-    pub async fn get_past_hour_readings(&self) -> Result<Vec<Reading>> {
-        self.db.get_past_hour_readings(1).await
+    /// A page of the readings of every station within the configured number of hours,
+    /// oldest first
+    pub async fn get_past_hour_readings(&self, page: &PageRequest) -> Result<Page<Reading>> {
+        let (cursor, limit) = self.page_params(page)?;
+        let hours = self.db.settings().windows.past_hours;
+        self.db.get_past_hour_readings(hours, cursor, limit).await
     }
 
-    /// Get every reading from the past 5 minutes, across all stations
-    pub async fn get_past_minute_readings(&self) -> Result<Vec<Reading>> {
-        self.db.get_past_minutes_readings(5).await
+    /// Like `get_past_hour_readings` but unpaged and streamed
+    pub fn stream_past_hour_readings(&self) -> RowStream<Reading> {
+        let hours = self.db.settings().windows.past_hours;
+        self.db.stream_past_hour_readings(hours)
     }
 
-    pub async fn put_reading(&self, request: AddReadingRequest) -> Result<i32> {
-        let mut station = self
-            .db
-            .get_station(request.station_token.clone(), false)
+    /// Get the latest reading of every station that sent one within the configured
+    /// number of minutes
+    pub async fn get_past_minute_readings(&self, page: &PageRequest) -> Result<Page<Reading>> {
+        let (cursor, limit) = self.page_params(page)?;
+        let minutes = self.db.settings().windows.past_minutes;
+        self.db
+            .get_past_minutes_readings(minutes, cursor, limit)
+            .await
+    }
+
+    /// The cursor and page size of a listing
+    fn page_params(&self, page: &PageRequest) -> Result<(Option<Cursor>, i64)> {
+        page_params(
+            page.cursor.as_deref(),
+            page.limit,
+            &self.db.settings().pagination,
+        )
+    }
+
+    /// Store a reading signed by the station identified by `token`, rejecting it if the
+    /// signature doesn't verify or the nonce was used for another reading. A reading that
+    /// was stored already for the same date keeps its original id
+    pub async fn put_reading(&self, token: String, payload: SignedPayload) -> Result<i32> {
+        let (mut station, nonce) = AuthService::new(self.db)
+            .verify_signature(token, &payload)
             .await?;
-        station.last_online = Utc::now();
-        self.db.update_station(&station).await?;
+        let request: AddReadingRequest = payload.json()?;
+        if request.station_token != station.token {
+            return Err(Error::Validation(
+                "station_token doesn't match the station of the request".into(),
+            ));
+        }
 
         let mut reading = Reading::from(request);
         reading.station_id = station.id;
         reading.location_id = station.location_id;
+        reading.validate()?;
+        let now = Utc::now();
+        let max_skew = Duration::seconds(self.db.settings().windows.max_clock_skew_seconds);
+        reading.validate_date(now, max_skew)?;
+
+        let rec = self.db.put_reading(&reading, &nonce).await?;
+
+        let previous = station.last_online;
+        station.last_online = now;
+        self.db.update_station(&station).await?;
+
+        let mut events: Vec<Event> = self.online_event(&station, previous).into_iter().collect();
+        if rec.inserted {
+            metrics::record_readings(&station.token, 1);
+            reading.id = rec.id;
+            let alerts = self
+                .evaluate_alerts(&station, std::slice::from_ref(&reading))
+                .await;
+            events.push(reading_event(&station, reading));
+            events.extend(
+                alerts
+                    .into_iter()
+                    .map(|alert| Event::Alert(Arc::new(alert))),
+            );
+        }
+        EventService::new(self.db).publish(events).await;
+
+        Ok(rec.id)
+    }
+
+    /// The readings stored from now on, of the station identified by `token` or, without
+    /// one, of every station. A stream that resumes after the reading with id `last_id`
+    /// first gets the readings it missed, some of which may come again live
+    pub async fn subscribe(
+        &self,
+        token: Option<String>,
+        last_id: Option<i32>,
+    ) -> Result<(Vec<ReadingEvent>, ReadingSubscription)> {
+        let station_id = match token {
+            Some(token) => Some(self.db.get_station(token, false).await?.id),
+            None => None,
+        };
+
+        // Subscribe before looking up the missed readings, so none are stored in between
+        let subscription = self.db.subscribe_readings(station_id);
+        let missed = match last_id {
+            Some(id) => {
+                self.db
+                    .get_readings_stored_after(station_id, id, MAX_REPLAYED_READINGS)
+                    .await?
+            }
+            None => vec![],
+        };
 
-        self.db.put_reading(&reading).await
+        Ok((missed, subscription))
     }
+
+    /// Evaluate the alert rules against the new readings. The readings are stored already,
+    /// so a failed evaluation is only logged
+    async fn evaluate_alerts(&self, station: &Station, readings: &[Reading]) -> Vec<Alert> {
+        AlertService::new(self.db)
+            .evaluate(station, readings)
+            .await
+            .unwrap_or_else(|e| {
+                error!(
+                    "failed to evaluate the alert rules of station {}: {e}",
+                    station.token
+                );
+                vec![]
+            })
+    }
+
+    /// The announcement that the station came online, when it was inactive before `previous`
+    fn online_event(&self, station: &Station, previous: DateTime<Utc>) -> Option<Event> {
+        let window = Duration::minutes(self.db.settings().windows.active_station_minutes);
+        if previous >= station.last_online - window {
+            return None;
+        }
+
+        let event = StationStatusEvent {
+            station: station.token.clone(),
+            online: true,
+            last_online: station.last_online,
+        };
+        Some(Event::StationStatus(Arc::new(event)))
+    }
+
+    /// Store a signed batch of readings buffered by the station identified by `token`.
+    /// Every item is validated on its own, the valid ones are stored in a single transaction.
+    /// Readings that were already stored for the same date keep their original id
+    pub async fn put_readings(
+        &self,
+        token: String,
+        payload: SignedPayload,
+    ) -> Result<Vec<BatchReadingResult>> {
+        let (mut station, nonce) = AuthService::new(self.db)
+            .verify_signature(token, &payload)
+            .await?;
+        let items: Vec<AddReadingBatchItem> = payload.json()?;
+
+        let now = Utc::now();
+        let max_skew = Duration::seconds(self.db.settings().windows.max_clock_skew_seconds);
+        let mut results = Vec::with_capacity(items.len());
+        let mut dates = Vec::with_capacity(items.len());
+        let mut readings = vec![];
+        let mut seen = HashSet::new();
+
+        for (index, item) in items.into_iter().enumerate() {
+            let mut reading = Reading::from(item);
+            reading.station_id = station.id;
+            reading.location_id = station.location_id;
+
+            let error = if index >= MAX_BATCH_SIZE {
+                Some("batch size limit exceeded".into())
+            } else if let Err(e) = reading.validate() {
+                Some(e.to_string())
+            } else if let Err(e) = reading.validate_date(now, max_skew) {
+                Some(e.to_string())
+            } else {
+                None
+            };
+
+            dates.push(reading.date);
+            if error.is_none() && seen.insert(reading.date) {
+                readings.push(reading);
+            }
+            results.push(BatchReadingResult {
+                index,
+                id: None,
+                error,
+            });
+        }
+
+        let previous = station.last_online;
+        station.last_online = now;
+        let stored = self.db.put_readings(&station, &readings, &nonce).await?;
+        let inserted = stored.values().filter(|rec| rec.inserted).count();
+        metrics::record_readings(&station.token, inserted);
+
+        for (result, date) in results.iter_mut().zip(dates) {
+            if result.error.is_none() {
+                result.id = stored.get(&date).map(|rec| rec.id);
+            }
+        }
+
+        readings.sort_by_key(|reading| reading.date);
+        let inserted: Vec<Reading> = readings
+            .into_iter()
+            .filter_map(|mut reading| match stored.get(&reading.date) {
+                Some(rec) if rec.inserted => {
+                    reading.id = rec.id;
+                    Some(reading)
+                }
+                _ => None,
+            })
+            .collect();
+
+        let alerts = self.evaluate_alerts(&station, &inserted).await;
+        let events = self
+            .online_event(&station, previous)
+            .into_iter()
+            .chain(
+                inserted
+                    .into_iter()
+                    .map(|reading| reading_event(&station, reading)),
+            )
+            .chain(
+                alerts
+                    .into_iter()
+                    .map(|alert| Event::Alert(Arc::new(alert))),
+            )
+            .collect();
+        EventService::new(self.db).publish(events).await;
+
+        Ok(results)
+    }
+}
+
+fn reading_event(station: &Station, reading: Reading) -> Event {
+    Event::Reading(Arc::new(ReadingEvent {
+        station: station.token.clone(),
+        reading,
+    }))
+}
+
+fn validate_buckets(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    bucket: Bucket,
+    max_buckets: i64,
+) -> Result<()> {
+    if start >= end {
+        return Err(Error::Validation("start must be before end".into()));
+    }
+
+    let buckets = (end - start).num_seconds() / bucket.seconds() + 1;
+    if buckets > max_buckets {
+        return Err(Error::Validation(format!(
+            "the range spans {buckets} buckets, at most {max_buckets} are allowed, use a wider bucket"
+        )));
+    }
+
+    Ok(())
 }
@@ -0,0 +1,204 @@
+use actix_web::web::Data;
+use log::{info, warn};
+use sqlx::migrate::MigrateError;
+
+use crate::{
+    error::{Error, Result},
+    models::schema::SchemaStatus,
+    repository::{
+        db::DBRepository,
+        queries::migration::{AppliedMigrationsRecord, MIGRATOR},
+    },
+};
+
+pub struct SchemaService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> SchemaService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        SchemaService { db }
+    }
+
+    /// Compare the migrations applied to the database with the ones embedded in this build
+    pub async fn get_status(&self) -> Result<SchemaStatus> {
+        let applied = self.db.get_applied_migrations().await?;
+
+        Ok(schema_status(&applied))
+    }
+
+    /// Make sure the schema is in a state the server can run against: nothing half applied,
+    /// nothing unknown and no applied migration that was changed afterwards. Pending
+    /// migrations are applied if `run_migrations` is set, and only warned about otherwise
+    pub async fn prepare(&self, run_migrations: bool) -> Result<SchemaStatus> {
+        let applied = self.db.get_applied_migrations().await?;
+        let status = schema_status(&applied);
+
+        if let Some(version) = status.dirty_version {
+            return Err(migrate_error(MigrateError::Dirty(version)));
+        }
+        if let Some(version) = status.unknown_migrations.first() {
+            return Err(migrate_error(MigrateError::VersionMissing(*version)));
+        }
+        for migration in up_migrations() {
+            let changed = applied
+                .migrations
+                .iter()
+                .any(|m| m.version == migration.version && m.checksum != migration.checksum);
+            if changed {
+                return Err(migrate_error(MigrateError::VersionMismatch(
+                    migration.version,
+                )));
+            }
+        }
+
+        if status.pending_migrations.is_empty() {
+            return Ok(status);
+        }
+
+        if !run_migrations {
+            warn!(
+                "{} pending migrations, enable database.run_migrations or apply them manually",
+                status.pending_migrations.len()
+            );
+            return Ok(status);
+        }
+
+        info!("applying {} migrations", status.pending_migrations.len());
+        self.db.run_migrations().await?;
+
+        self.get_status().await
+    }
+}
+
+fn schema_status(applied: &AppliedMigrationsRecord) -> SchemaStatus {
+    let applied_versions: Vec<i64> = applied.migrations.iter().map(|m| m.version).collect();
+    let known_versions: Vec<i64> = up_migrations().map(|m| m.version).collect();
+
+    SchemaStatus {
+        schema_version: applied_versions.iter().copied().max(),
+        latest_version: known_versions.iter().copied().max(),
+        pending_migrations: known_versions
+            .iter()
+            .copied()
+            .filter(|v| !applied_versions.contains(v))
+            .collect(),
+        unknown_migrations: applied_versions
+            .iter()
+            .copied()
+            .filter(|v| !known_versions.contains(v))
+            .collect(),
+        dirty_version: applied.dirty_version,
+    }
+}
+
+fn up_migrations() -> impl Iterator<Item = &'static sqlx::migrate::Migration> {
+    MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+}
+
+fn migrate_error(e: MigrateError) -> Error {
+    Error::Storage(sqlx::Error::from(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{settings, TestDb};
+
+    fn latest_version() -> i64 {
+        up_migrations().map(|m| m.version).max().unwrap()
+    }
+
+    async fn execute(db: &TestDb, statement: &str) {
+        sqlx::query(statement).execute(&db.pool).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn pending_migrations_are_only_applied_when_enabled() {
+        let db = TestDb::unmigrated(settings()).await;
+        let service = SchemaService::new(&db);
+
+        let status = service.get_status().await.unwrap();
+        assert_eq!(status.schema_version, None);
+        assert_eq!(status.latest_version, Some(latest_version()));
+        assert_eq!(status.pending_migrations.len(), up_migrations().count());
+        assert!(!status.is_up_to_date());
+
+        let status = service.prepare(false).await.unwrap();
+        assert_eq!(status.pending_migrations.len(), up_migrations().count());
+
+        let status = service.prepare(true).await.unwrap();
+        assert!(status.is_up_to_date());
+        assert_eq!(status.schema_version, Some(latest_version()));
+
+        // Nothing left to do the next time
+        let status = service.prepare(true).await.unwrap();
+        assert!(status.is_up_to_date());
+    }
+
+    #[actix_web::test]
+    async fn dirty_migrations_are_refused() {
+        let db = TestDb::new().await;
+        let service = SchemaService::new(&db);
+        execute(
+            &db,
+            &format!(
+                "UPDATE _sqlx_migrations SET success = FALSE WHERE version = {}",
+                latest_version()
+            ),
+        )
+        .await;
+
+        let status = service.get_status().await.unwrap();
+        assert_eq!(status.dirty_version, Some(latest_version()));
+        assert!(!status.is_up_to_date());
+        assert!(matches!(
+            service.prepare(true).await,
+            Err(Error::Storage(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn unknown_migrations_are_refused() {
+        let db = TestDb::new().await;
+        let service = SchemaService::new(&db);
+        execute(
+            &db,
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (99991231000000, 'from a newer build', TRUE, '\\x00', 0)",
+        )
+        .await;
+
+        let status = service.get_status().await.unwrap();
+        assert_eq!(status.unknown_migrations, vec![99991231000000]);
+        assert_eq!(status.schema_version, Some(99991231000000));
+        assert!(!status.is_up_to_date());
+        assert!(matches!(
+            service.prepare(true).await,
+            Err(Error::Storage(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn changed_migrations_are_refused() {
+        let db = TestDb::new().await;
+        let service = SchemaService::new(&db);
+        execute(
+            &db,
+            &format!(
+                "UPDATE _sqlx_migrations SET checksum = '\\x00' WHERE version = {}",
+                latest_version()
+            ),
+        )
+        .await;
+
+        // The status only compares versions
+        assert!(service.get_status().await.unwrap().is_up_to_date());
+        assert!(matches!(
+            service.prepare(true).await,
+            Err(Error::Storage(_))
+        ));
+    }
+}
@@ -1,10 +1,28 @@
 use crate::{
-    api::station::{AddLocationRequest, UpdateStationRequest},
-    models::{location::Location, station::Station},
+    api::{
+        reading::PageRequest,
+        station::{AddLocationRequest, UpdateStationRequest},
+    },
+    error::{Error, Result},
+    models::{
+        geo::{validate_coordinate, BoundingBox, EARTH_RADIUS_KM},
+        location::Location,
+        page::{page_params, Cursor, Page},
+        station::{Station, StationCredentials},
+    },
     repository::db::DBRepository,
+    services::auth_service::AuthService,
 };
 use actix_web::web::Data;
-use anyhow::Result;
+
+/// Maximum number of stations returned by a nearest stations query
+pub const MAX_NEAREST_STATIONS: i64 = 100;
+
+/// Maximum number of stations returned by a radius query, the nearest ones are kept
+pub const MAX_RADIUS_STATIONS: i64 = 1000;
+
+/// Half the circumference of the earth, no two points are further apart
+const MAX_DISTANCE_KM: f64 = std::f64::consts::PI * EARTH_RADIUS_KM;
 
 pub struct StationService<'a> {
     db: &'a Data<DBRepository>,
@@ -19,26 +37,116 @@ impl<'a> StationService<'a> {
         self.db.get_station(token, true).await
     }
 
+    /// Every active station, for the exports
     pub async fn get_active_stations(&self) -> Result<Vec<Station>> {
         self.db.get_active_stations().await
     }
 
-    pub async fn put_station(&self, station: Station) -> Result<i32> {
-        self.db.put_station(station).await
+    pub async fn get_active_stations_page(&self, page: &PageRequest) -> Result<Page<Station>> {
+        let (cursor, limit) = self.page_params(page)?;
+
+        self.db.get_active_stations_page(cursor, limit).await
+    }
+
+    pub async fn get_stations_in_bbox(
+        &self,
+        bbox: BoundingBox,
+        page: &PageRequest,
+    ) -> Result<Page<Station>> {
+        bbox.validate()?;
+        let (cursor, limit) = self.page_params(page)?;
+
+        self.db.get_stations_in_bbox(&bbox, cursor, limit).await
+    }
+
+    fn page_params(&self, page: &PageRequest) -> Result<(Option<Cursor>, i64)> {
+        page_params(
+            page.cursor.as_deref(),
+            page.limit,
+            &self.db.settings().pagination,
+        )
+    }
+
+    /// Stations within `radius_km` of the coordinate, nearest first and at most
+    /// `MAX_RADIUS_STATIONS` of them
+    pub async fn get_stations_within(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_km: f64,
+    ) -> Result<Vec<(Station, f64)>> {
+        validate_coordinate(lat, lon)?;
+        if radius_km.is_nan() || radius_km <= 0.0 {
+            return Err(Error::Validation("radius_km must be positive".into()));
+        }
+
+        self.db
+            .get_stations_near(
+                lat,
+                lon,
+                radius_km.min(MAX_DISTANCE_KM),
+                MAX_RADIUS_STATIONS,
+            )
+            .await
+    }
+
+    /// The `count` stations nearest to the coordinate
+    pub async fn get_nearest_stations(
+        &self,
+        lat: f64,
+        lon: f64,
+        count: i64,
+    ) -> Result<Vec<(Station, f64)>> {
+        validate_coordinate(lat, lon)?;
+        if !(1..=MAX_NEAREST_STATIONS).contains(&count) {
+            return Err(Error::Validation(format!(
+                "count must be between 1 and {MAX_NEAREST_STATIONS}"
+            )));
+        }
+
+        self.db
+            .get_stations_near(lat, lon, MAX_DISTANCE_KM, count)
+            .await
+    }
+
+    /// Register a new station, returning its id and the credentials it has to authenticate
+    /// with. Only a hash of the secret is stored, so they can't be recovered afterwards
+    pub async fn put_station(&self, station: Station) -> Result<(i32, StationCredentials)> {
+        let (credentials, secret_hash) = AuthService::new(self.db).issue_credentials();
+        let id = self.db.put_station(station, secret_hash).await?;
+
+        Ok((id, credentials))
+    }
+
+    /// Issue new credentials to the station, the previous ones stop working. They can't be
+    /// recovered afterwards
+    pub async fn rotate_secret(&self, token: &str) -> Result<StationCredentials> {
+        let (credentials, secret_hash) = AuthService::new(self.db).issue_credentials();
+        self.db.update_station_secret(token, &secret_hash).await?;
+
+        Ok(credentials)
+    }
+
+    pub async fn get_stations_without_secret(&self) -> Result<Vec<String>> {
+        self.db.get_station_tokens_without_secret().await
     }
 
     pub async fn update_station(
         &self,
-        token: String,
+        mut station: Station,
         request: UpdateStationRequest,
     ) -> Result<()> {
-        let mut station = self.db.get_station(token, false).await?;
         station.apply_update(request);
 
         self.db.update_station(&station).await
     }
 
-    pub async fn update_location(&self, request: AddLocationRequest) -> Result<()> {
+    pub async fn update_location(
+        &self,
+        station: Station,
+        mut request: AddLocationRequest,
+    ) -> Result<()> {
+        request.station_token = station.token;
         let location = Location::from(request);
         let location_id = self.db.put_location(&location).await?;
 
@@ -0,0 +1,529 @@
+use std::{error::Error as StdError, net::SocketAddr, time::Duration as StdDuration};
+
+use actix_tls::connect::{Connector as TcpConnector, Resolve, Resolver};
+use actix_web::{
+    http::{header::CONTENT_TYPE, Uri},
+    rt::time::interval,
+    web::Data,
+};
+use awc::{Client, Connector};
+use chrono::{Duration, Utc};
+use futures_util::future::{self, LocalBoxFuture};
+use hmac::{Hmac, Mac};
+use log::{error, warn};
+use sha2::Sha256;
+use tokio::net::lookup_host;
+
+use crate::{
+    api::webhook::{DeliveriesRequest, WebhookRequest},
+    error::{Error, Result},
+    events::Event,
+    models::{
+        page::{page_params, Page},
+        webhook::{
+            is_public_address, DeliveryStatus, DueDelivery, Webhook, WebhookDelivery,
+            WebhookPayload,
+        },
+    },
+    repository::db::DBRepository,
+    services::auth_service::generate_secret,
+};
+
+/// Retries are never put off for longer than this
+const MAX_BACKOFF_SECONDS: i64 = 6 * 60 * 60;
+
+pub struct WebhookService<'a> {
+    db: &'a Data<DBRepository>,
+}
+
+impl<'a> WebhookService<'a> {
+    pub fn new(db: &'a Data<DBRepository>) -> Self {
+        WebhookService { db }
+    }
+
+    pub async fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        self.db.get_webhooks().await
+    }
+
+    pub async fn get_webhook(&self, id: i32) -> Result<Webhook> {
+        self.db.get_webhook(id).await
+    }
+
+    /// Create the webhook with a new signing secret, which is returned along with it
+    pub async fn put_webhook(&self, request: WebhookRequest) -> Result<(Webhook, String)> {
+        self.validate(&request).await?;
+        let secret = generate_secret();
+        let webhook = self
+            .db
+            .put_webhook(
+                &request.url,
+                &request.events,
+                request.station.as_deref(),
+                &secret,
+                request.enabled.unwrap_or(true),
+            )
+            .await?;
+
+        Ok((webhook, secret))
+    }
+
+    /// Replace everything but the secret. Deliveries that are already queued are still
+    /// sent to the new URL
+    pub async fn update_webhook(&self, id: i32, request: WebhookRequest) -> Result<Webhook> {
+        self.validate(&request).await?;
+
+        self.db
+            .update_webhook(
+                id,
+                &request.url,
+                &request.events,
+                request.station.as_deref(),
+                request.enabled.unwrap_or(true),
+            )
+            .await
+    }
+
+    pub async fn delete_webhook(&self, id: i32) -> Result<()> {
+        self.db.delete_webhook(id).await
+    }
+
+    /// A page of the deliveries, the latest first. Page sizes above the configured maximum
+    /// are reduced to it
+    pub async fn get_deliveries(
+        &self,
+        id: i32,
+        request: DeliveriesRequest,
+    ) -> Result<Page<WebhookDelivery>> {
+        let (cursor, limit) = page_params(
+            request.cursor.as_deref(),
+            request.limit,
+            &self.db.settings().pagination,
+        )?;
+        self.db.get_webhook(id).await?;
+
+        self.db
+            .get_webhook_deliveries(id, request.status, cursor, limit)
+            .await
+    }
+
+    /// Queue the events for the webhooks that subscribed to them, all in one statement.
+    /// Returns the number of queued deliveries
+    pub async fn queue(&self, events: &[Event]) -> Result<u64> {
+        let queued = events
+            .iter()
+            .map(|event| WebhookPayload::new(event).queued(event.station()))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.db.put_webhook_deliveries(&queued).await
+    }
+
+    /// Send the deliveries that are due, checking every `webhooks.poll_seconds`. Runs until
+    /// the process ends. Every instance sends its own share of the queue
+    pub async fn deliver(&self) {
+        let settings = &self.db.settings().webhooks;
+        let client = self.client();
+        let mut ticks = interval(StdDuration::from_secs(settings.poll_seconds));
+
+        loop {
+            ticks.tick().await;
+            if let Err(e) = self.deliver_due(&client).await {
+                error!("failed to send webhook deliveries: {e}");
+            }
+        }
+    }
+
+    /// The client deliveries are sent with, it connects to the hosts `check_host` allows only
+    fn client(&self) -> Client {
+        let settings = &self.db.settings().webhooks;
+        let resolver = Resolver::custom(CheckedResolver {
+            allowed_hosts: settings.allowed_hosts.clone(),
+        });
+        let connector = Connector::new().connector(TcpConnector::new(resolver).service());
+
+        Client::builder()
+            .connector(connector)
+            .timeout(StdDuration::from_secs(settings.timeout_seconds))
+            .disable_redirects()
+            .finish()
+    }
+
+    /// Send batches of due deliveries until none are left
+    async fn deliver_due(&self, client: &Client) -> Result<()> {
+        let settings = &self.db.settings().webhooks;
+        // Long enough for a whole batch to be sent before another instance may claim it
+        let lease = Duration::seconds((settings.timeout_seconds + settings.poll_seconds) as i64);
+
+        loop {
+            let deliveries = self
+                .db
+                .claim_webhook_deliveries(settings.batch_size, Utc::now() + lease)
+                .await?;
+            let claimed = deliveries.len();
+
+            let sent = deliveries
+                .into_iter()
+                .map(|delivery| self.send(client, delivery));
+            future::try_join_all(sent).await?;
+
+            if (claimed as i64) < settings.batch_size {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Make one attempt to send the delivery and record how it went. Failed deliveries are
+    /// retried with exponential backoff until `webhooks.max_attempts` is reached
+    async fn send(&self, client: &Client, mut delivery: DueDelivery) -> Result<()> {
+        let timestamp = Utc::now().timestamp_millis();
+        let signature = sign(&delivery.secret, timestamp, &delivery.payload);
+
+        // The client only connects to the addresses a host name resolves to once they are
+        // checked, see `CheckedResolver`. Addresses aren't resolved, they're checked here
+        let (response_status, error) = match self.check_address_host(&delivery.url) {
+            Ok(()) => {
+                let response = client
+                    .post(&delivery.url)
+                    .insert_header((CONTENT_TYPE, "application/json"))
+                    .insert_header(("X-Auspex-Event", delivery.event.as_str()))
+                    .insert_header(("X-Auspex-Delivery", delivery.id.to_string()))
+                    .insert_header(("X-Auspex-Timestamp", timestamp.to_string()))
+                    .insert_header(("X-Auspex-Signature", signature))
+                    .send_body(delivery.payload.clone())
+                    .await;
+                match response {
+                    Ok(res) if res.status().is_success() => (Some(res.status().as_u16()), None),
+                    Ok(res) => (
+                        Some(res.status().as_u16()),
+                        Some(format!("unexpected response status {}", res.status())),
+                    ),
+                    Err(e) => (None, Some(e.to_string())),
+                }
+            }
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        delivery.attempts += 1;
+        let settings = &self.db.settings().webhooks;
+        let now = Utc::now();
+        let (status, next_attempt) = match &error {
+            None => (DeliveryStatus::Delivered, now),
+            Some(_) if delivery.attempts >= settings.max_attempts => {
+                warn!(
+                    "giving up webhook delivery {} after {} attempts",
+                    delivery.id, delivery.attempts
+                );
+                (DeliveryStatus::Failed, now)
+            }
+            Some(_) => {
+                let backoff = backoff_seconds(settings.backoff_seconds, delivery.attempts);
+                (DeliveryStatus::Pending, now + Duration::seconds(backoff))
+            }
+        };
+
+        self.db
+            .update_webhook_delivery(
+                &delivery,
+                status,
+                next_attempt,
+                response_status.map(i32::from),
+                error.as_deref(),
+            )
+            .await
+    }
+
+    async fn validate(&self, request: &WebhookRequest) -> Result<()> {
+        let uri: Uri = request
+            .url
+            .parse()
+            .map_err(|_| Error::Validation(format!("invalid url {:?}", request.url)))?;
+        if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.host().is_none() {
+            return Err(Error::Validation(
+                "url must be an absolute http or https URL".into(),
+            ));
+        }
+        if request.events.is_empty() {
+            return Err(Error::Validation("events must not be empty".into()));
+        }
+        if let Some(token) = &request.station {
+            self.db.get_station(token.clone(), false).await?;
+        }
+
+        self.check_host(&request.url).await
+    }
+
+    /// Refuse URLs whose host resolves to a loopback, private or link-local address, unless
+    /// it is one of `webhooks.allowed_hosts`, so webhooks can't reach into the network of
+    /// the server
+    async fn check_host(&self, url: &str) -> Result<()> {
+        let uri = parse_url(url)?;
+        let host = uri.host().unwrap_or_default();
+        let allowed = &self.db.settings().webhooks.allowed_hosts;
+        if is_allowed(host, allowed) {
+            return Ok(());
+        }
+
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("https") {
+                443
+            } else {
+                80
+            });
+        resolve_public(host, port).await?;
+
+        Ok(())
+    }
+
+    /// Like `check_host` for URLs with an address as host, the client connects to those
+    /// without asking its resolver. Other hosts pass
+    fn check_address_host(&self, url: &str) -> Result<()> {
+        let uri = parse_url(url)?;
+        let host = uri.host().unwrap_or_default();
+        let ip = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+            Ok(ip) => ip,
+            Err(_) => return Ok(()),
+        };
+
+        let allowed = &self.db.settings().webhooks.allowed_hosts;
+        if is_allowed(host, allowed) || is_public_address(ip) {
+            Ok(())
+        } else {
+            Err(internal_host(host))
+        }
+    }
+}
+
+/// Resolves the hosts of webhook URLs for the client, refusing the ones `check_host`
+/// refuses. The client connects to the very addresses that were checked, so a host can't
+/// pass the check and then resolve to an internal address
+struct CheckedResolver {
+    allowed_hosts: Vec<String>,
+}
+
+impl Resolve for CheckedResolver {
+    fn lookup<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> LocalBoxFuture<'a, std::result::Result<Vec<SocketAddr>, Box<dyn StdError>>> {
+        Box::pin(async move {
+            if is_allowed(host, &self.allowed_hosts) {
+                return Ok(resolve(host, port).await?);
+            }
+
+            Ok(resolve_public(host, port).await?)
+        })
+    }
+}
+
+fn parse_url(url: &str) -> Result<Uri> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|_| Error::Validation(format!("invalid url {url:?}")))?;
+    if uri.host().is_none() {
+        return Err(Error::Validation(format!("url {url:?} has no host")));
+    }
+
+    Ok(uri)
+}
+
+fn is_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    allowed_hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+}
+
+async fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let ip_host = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses = lookup_host((ip_host, port))
+        .await
+        .map_err(|e| Error::Validation(format!("failed to resolve {host}: {e}")))?;
+
+    Ok(addresses.collect())
+}
+
+/// The addresses of `host`, provided they are all public
+async fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let addresses = resolve(host, port).await?;
+    if addresses.is_empty() || addresses.iter().any(|a| !is_public_address(a.ip())) {
+        return Err(internal_host(host));
+    }
+
+    Ok(addresses)
+}
+
+fn internal_host(host: &str) -> Error {
+    Error::Validation(format!(
+        "{host} resolves to a loopback, private or link-local address, add it to \
+         webhooks.allowed_hosts to allow it"
+    ))
+}
+
+/// Hex encoded HMAC-SHA256 of `{timestamp}.{body}` with the webhook secret as key, sent as
+/// `X-Auspex-Signature` along with `X-Auspex-Timestamp` (milliseconds since epoch)
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Wait before the next attempt, `base` after the first failed attempt and doubled after
+/// every further one
+fn backoff_seconds(base: i64, attempts: i32) -> i64 {
+    let doublings = (attempts - 1).clamp(0, 20) as u32;
+
+    base.saturating_mul(1 << doublings).min(MAX_BACKOFF_SECONDS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use super::*;
+    use crate::{
+        events::StationStatusEvent,
+        models::webhook::WebhookEvent,
+        testing::{settings, TestDb},
+    };
+
+    /// A webhook receiver on a loopback port, counting the requests it gets
+    fn receiver() -> (u16, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counted = hits.clone();
+        let server = HttpServer::new(move || {
+            let counted = counted.clone();
+            App::new().route(
+                "/hook",
+                web::post().to(move || {
+                    counted.fetch_add(1, Ordering::SeqCst);
+                    async { HttpResponse::Ok().finish() }
+                }),
+            )
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let port = server.addrs()[0].port();
+        actix_web::rt::spawn(server.run());
+
+        (port, hits)
+    }
+
+    fn status_event(station: &str) -> Event {
+        Event::StationStatus(Arc::new(StationStatusEvent {
+            station: station.into(),
+            online: true,
+            last_online: Utc::now(),
+        }))
+    }
+
+    async fn deliveries(db: &Data<DBRepository>, id: i32) -> Vec<WebhookDelivery> {
+        let request = DeliveriesRequest {
+            status: None,
+            cursor: None,
+            limit: None,
+        };
+
+        WebhookService::new(db)
+            .get_deliveries(id, request)
+            .await
+            .unwrap()
+            .items
+    }
+
+    #[actix_web::test]
+    async fn the_resolver_refuses_internal_addresses_unless_allowed() {
+        let refusing = CheckedResolver {
+            allowed_hosts: vec![],
+        };
+        assert!(refusing.lookup("localhost", 80).await.is_err());
+
+        let allowing = CheckedResolver {
+            allowed_hosts: vec!["LOCALHOST".into()],
+        };
+        let addresses = allowing.lookup("localhost", 8080).await.unwrap();
+        assert!(!addresses.is_empty());
+        assert!(addresses
+            .iter()
+            .all(|a| a.ip().is_loopback() && a.port() == 8080));
+    }
+
+    #[actix_web::test]
+    async fn address_hosts_are_checked_without_the_resolver() {
+        let mut settings = settings();
+        settings.webhooks.allowed_hosts = vec!["10.0.0.5".into()];
+        let db = TestDb::with_settings(settings).await;
+        let service = WebhookService::new(&db);
+
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "https://192.168.1.1:8443/",
+            "http://[::1]:8080/hook",
+        ] {
+            assert!(service.check_address_host(url).is_err(), "{url}");
+        }
+        for url in [
+            "https://93.184.216.34/hook",
+            "http://10.0.0.5/hook",
+            "https://hooks.example.com/hook",
+        ] {
+            assert!(service.check_address_host(url).is_ok(), "{url}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn deliveries_only_connect_to_allowed_addresses() {
+        let (port, hits) = receiver();
+        let mut allowing = settings();
+        allowing.webhooks.allowed_hosts = vec!["localhost".into()];
+        let db = TestDb::with_settings(allowing).await;
+        db.station("st-1").await;
+
+        let service = WebhookService::new(&db);
+        let request = WebhookRequest {
+            url: format!("http://localhost:{port}/hook"),
+            events: vec![WebhookEvent::StationOnline],
+            station: None,
+            enabled: None,
+        };
+        let (webhook, _) = service.put_webhook(request).await.unwrap();
+        assert_eq!(service.queue(&[status_event("st-1")]).await.unwrap(), 1);
+
+        // As if the host resolved to a public address when the webhook was created, and to
+        // the loopback address by the time the delivery is sent
+        let refusing = db.with_other_settings(settings());
+        let sender = WebhookService::new(&refusing);
+        let client = sender.client();
+        let url = format!("http://localhost:{port}/hook");
+        assert!(client.post(&url).send().await.is_err());
+        sender.deliver_due(&client).await.unwrap();
+
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+        let delivery = &deliveries(&db, webhook.id).await[0];
+        assert_eq!(delivery.status, DeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 1);
+        let error = delivery.error.as_deref().unwrap();
+        assert!(error.contains("loopback"), "{error}");
+
+        // Once it is allowed, the retry goes through
+        sqlx::query("UPDATE webhook_deliveries SET next_attempt = NOW()")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        service.deliver_due(&service.client()).await.unwrap();
+
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        let delivery = &deliveries(&db, webhook.id).await[0];
+        assert_eq!(delivery.status, DeliveryStatus::Delivered);
+        assert_eq!(delivery.response_status, Some(200));
+    }
+}
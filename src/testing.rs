@@ -0,0 +1,202 @@
+//! Helpers of the tests that need a database. Every test gets a database of its own,
+//! created next to the one in `DATABASE_URL` with the migrations applied, and dropped
+//! again when the test ends
+
+use std::{ops::Deref, str::FromStr};
+
+use actix_web::{test::TestRequest, web::Data};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    ConnectOptions, Connection, Executor, PgConnection, Pool, Postgres,
+};
+use uuid::Uuid;
+
+use crate::{
+    api::auth::SignedPayload,
+    config::{Config, Settings},
+    events::EventBus,
+    models::station::{Station, StationCredentials},
+    repository::{db::DBRepository, queries::migration::MIGRATOR},
+    services::station_service::StationService,
+};
+
+pub const SIGNING_SECRET: &str = "0123456789abcdef0123456789abcdef";
+pub const ADMIN_TOKEN: &str = "adminadminadminadminadminadminadmin";
+
+/// The defaults, with the secrets the server refuses to start without
+pub fn settings() -> Settings {
+    let mut settings = Settings::default();
+    settings.auth.signing_secret = Some(SIGNING_SECRET.into());
+    settings.auth.admin_token = Some(ADMIN_TOKEN.into());
+    settings
+}
+
+pub struct TestDb {
+    db: Data<DBRepository>,
+    /// For what the repository doesn't offer, like moving stored dates back in time
+    pub pool: Pool<Postgres>,
+    name: String,
+    url: String,
+}
+
+impl TestDb {
+    pub async fn new() -> Self {
+        Self::with_settings(settings()).await
+    }
+
+    pub async fn with_settings(settings: Settings) -> Self {
+        let db = Self::unmigrated(settings).await;
+        MIGRATOR
+            .run(&db.pool)
+            .await
+            .expect("failed to migrate the test database");
+
+        db
+    }
+
+    /// A database without any migrations applied
+    pub async fn unmigrated(mut settings: Settings) -> Self {
+        let url = dotenvy::var("DATABASE_URL").expect("the tests need DATABASE_URL");
+        let name = format!("auspex_test_{}", Uuid::new_v4().simple());
+
+        let mut conn = PgConnection::connect(&url)
+            .await
+            .expect("failed to connect to DATABASE_URL");
+        conn.execute(format!(r#"CREATE DATABASE "{name}""#).as_str())
+            .await
+            .expect("failed to create the test database");
+        conn.close().await.ok();
+
+        let options = PgConnectOptions::from_str(&url)
+            .unwrap()
+            .database(&name)
+            .disable_statement_logging()
+            .clone();
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(options)
+            .await
+            .expect("failed to connect to the test database");
+
+        settings.database.url = Some(url.clone());
+        let events = EventBus::new(settings.events.buffer);
+        let config = Config {
+            pool: pool.clone(),
+            settings,
+            events,
+        };
+
+        TestDb {
+            db: Data::new(DBRepository::new(config)),
+            pool,
+            name,
+            url,
+        }
+    }
+
+    /// The repository, as handed to the handlers with `App::app_data`
+    pub fn data(&self) -> Data<DBRepository> {
+        self.db.clone()
+    }
+
+    /// The same database behind a server with other settings
+    pub fn with_other_settings(&self, settings: Settings) -> Data<DBRepository> {
+        let events = EventBus::new(settings.events.buffer);
+        let config = Config {
+            pool: self.pool.clone(),
+            settings,
+            events,
+        };
+
+        Data::new(DBRepository::new(config))
+    }
+
+    /// Register a station with `token`, returning it with its credentials
+    pub async fn station(&self, token: &str) -> (Station, StationCredentials) {
+        let service = StationService::new(&self.db);
+        let (id, credentials) = service
+            .put_station(Station::new(token, 1, 1))
+            .await
+            .expect("failed to register the station");
+        let station = self.db.get_station(token.into(), false).await.unwrap();
+        assert_eq!(station.id, id);
+
+        (station, credentials)
+    }
+}
+
+impl Deref for TestDb {
+    type Target = Data<DBRepository>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.db
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let url = self.url.clone();
+        let statement = format!(r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE)"#, self.name);
+
+        // Drop can't await, and the runtime of the test may be shutting down already
+        std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                if let Ok(mut conn) = PgConnection::connect(&url).await {
+                    conn.execute(statement.as_str()).await.ok();
+                    conn.close().await.ok();
+                }
+            })
+        })
+        .join()
+        .ok();
+    }
+}
+
+/// Body of a single signed reading of the station at `date`, with `pm25` and otherwise
+/// ordinary values
+pub fn reading_body(token: &str, date: DateTime<Utc>, pm25: f32) -> String {
+    json!({
+        "station_token": token,
+        "date": date.timestamp_millis(),
+        "temperature": 21.5,
+        "humidity": 40.0,
+        "pm10": 12.0,
+        "pm25": pm25,
+        "co2": 450.0,
+        "voc": 0.2,
+    })
+    .to_string()
+}
+
+/// Sign `body` with `signing_key` the way a station does, at `timestamp`
+pub fn sign_at(signing_key: &str, body: &str, timestamp: i64, nonce: &str) -> SignedPayload {
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_key.as_bytes()).unwrap();
+    mac.update(format!("{timestamp}.{nonce}.{body}").as_bytes());
+
+    SignedPayload {
+        timestamp,
+        nonce: nonce.into(),
+        signature: hex::encode(mac.finalize().into_bytes()),
+        body: body.to_owned().into(),
+    }
+}
+
+/// Sign `body` with `signing_key` now, with a fresh nonce
+pub fn sign(signing_key: &str, body: &str) -> SignedPayload {
+    let nonce = Uuid::new_v4().simple().to_string();
+
+    sign_at(signing_key, body, Utc::now().timestamp_millis(), &nonce)
+}
+
+/// A request carrying `payload` and its signature headers
+pub fn signed_request(request: TestRequest, payload: &SignedPayload) -> TestRequest {
+    request
+        .insert_header(("X-Auspex-Timestamp", payload.timestamp.to_string()))
+        .insert_header(("X-Auspex-Nonce", payload.nonce.clone()))
+        .insert_header(("X-Auspex-Signature", payload.signature.clone()))
+        .set_payload(payload.body.clone())
+}